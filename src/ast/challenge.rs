@@ -1,4 +1,4 @@
-use crate::token::{Token, TokenType};
+use crate::token::{Span, Token, TokenType};
 
 use super::*;
 
@@ -29,7 +29,7 @@ impl ReversePolishNotation {
     pub fn output(&mut self) -> String {
         // pop the rest of our stack
         while let Some(s) = self.stack.pop() {
-            self.output.push(s.lexeme);
+            self.output.push(s.lexeme.to_string());
         }
         let out = self.output.join(" ");
         self.output.clear();
@@ -46,7 +46,7 @@ impl ReversePolishNotation {
                     break;
                 } else {
                     // add to output
-                    self.output.push(c.lexeme);
+                    self.output.push(c.lexeme.to_string());
                 }
             }
         } else {
@@ -55,7 +55,8 @@ impl ReversePolishNotation {
                 // only right paren has the right to remove left paren
                 if char.token_type != TokenType::LeftParen && prio <= Self::prio(char) {
                     // pop the top of the stack into the output
-                    self.output.push(self.stack.pop().unwrap().lexeme);
+                    self.output
+                        .push(self.stack.pop().unwrap().lexeme.to_string());
                 } else {
                     // otherwise don't modify the current stack
                     break;
@@ -89,16 +90,16 @@ impl InspectorMut for ReversePolishNotation {
     fn visit_grouping(&mut self, expr: &Expr) {
         self.push_operator(&Token::new(
             TokenType::LeftParen,
-            "(".to_string(),
+            "(".into(),
             Literal::Nil,
-            1,
+            Span::default(),
         ));
         expr.accept_mut(self);
         self.push_operator(&Token::new(
             TokenType::RightParen,
-            ")".to_string(),
+            ")".into(),
             Literal::Nil,
-            1,
+            Span::default(),
         ));
     }
 
@@ -111,7 +112,7 @@ impl InspectorMut for ReversePolishNotation {
 impl Expr {
     pub fn accept_mut(&self, visitor: &mut challenge::ReversePolishNotation) {
         match self {
-            Expr::Literal(e) => visitor.visit_literal(e),
+            Expr::Literal(e, _) => visitor.visit_literal(e),
             Expr::Grouping { expression } => visitor.visit_grouping(expression),
             Expr::Binary {
                 left,
@@ -145,30 +146,30 @@ fn rpn() {
 
     let binary_expression = Expr::Binary {
         left: Box::new(Expr::Binary {
-            left: Box::new(Expr::Literal(Literal::Number(1.0))),
+            left: Box::new(Expr::Literal(Literal::Number(1.0), Span::default())),
             operator: Token {
                 token_type: TokenType::Plus,
-                lexeme: "+".to_string(),
+                lexeme: "+".into(),
                 literal: Literal::Nil,
-                line: 1,
+                span: Span::default(),
             },
-            right: Box::new(Expr::Literal(Literal::Number(2.0))),
+            right: Box::new(Expr::Literal(Literal::Number(2.0), Span::default())),
         }),
         operator: Token {
             token_type: TokenType::Star,
-            lexeme: "*".to_string(),
+            lexeme: "*".into(),
             literal: Literal::Nil,
-            line: 1,
+            span: Span::default(),
         },
         right: Box::new(Expr::Binary {
-            left: Box::new(Expr::Literal(Literal::Number(4.0))),
+            left: Box::new(Expr::Literal(Literal::Number(4.0), Span::default())),
             operator: Token {
                 token_type: TokenType::Minus,
-                lexeme: "-".to_string(),
+                lexeme: "-".into(),
                 literal: Literal::Nil,
-                line: 1,
+                span: Span::default(),
             },
-            right: Box::new(Expr::Literal(Literal::Number(3.0))),
+            right: Box::new(Expr::Literal(Literal::Number(3.0), Span::default())),
         }),
     };
 
@@ -178,30 +179,30 @@ fn rpn() {
 
     // let grouping = Expr::Binary {
     //     left: Box::new(Expr::Binary {
-    //         left: Box::new(Expr::Literal(Literal::Number(1.0))),
+    //         left: Box::new(Expr::Literal(Literal::Number(1.0), Span::default())),
     //         operator: Token {
     //             token_type: TokenType::Plus,
-    //             lexeme: "+".to_string(),
+    //             lexeme: "+".into(),
     //             literal: Literal::Nil,
     //             line: 1,
     //         },
-    //         right: Box::new(Expr::Literal(Literal::Number(2.0))),
+    //         right: Box::new(Expr::Literal(Literal::Number(2.0), Span::default())),
     //     }),
     //     operator: Token {
     //         token_type: TokenType::Star,
-    //         lexeme: "*".to_string(),
+    //         lexeme: "*".into(),
     //         literal: Literal::Nil,
     //         line: 1,
     //     },
     //     right: Box::new(Expr::Binary {
-    //         left: Box::new(Expr::Literal(Literal::Number(4.0))),
+    //         left: Box::new(Expr::Literal(Literal::Number(4.0), Span::default())),
     //         operator: Token {
     //             token_type: TokenType::Minus,
-    //             lexeme: "-".to_string(),
+    //             lexeme: "-".into(),
     //             literal: Literal::Nil,
     //             line: 1,
     //         },
-    //         right: Box::new(Expr::Literal(Literal::Number(3.0))),
+    //         right: Box::new(Expr::Literal(Literal::Number(3.0), Span::default())),
     //     }),
     // };
 
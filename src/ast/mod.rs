@@ -1,4 +1,6 @@
-use crate::token::Literal;
+use std::{cell::Cell, rc::Rc};
+
+use crate::token::{Literal, Span};
 mod challenge;
 pub mod printer;
 
@@ -15,14 +17,17 @@ use crate::token::Token;
 /// Anything that evaluates to a value
 pub enum Expr {
     // e.g. expression operator expression
-    Literal(Literal),
+    // The span is where the literal is written, or where whatever desugared into it is
+    Literal(Literal, Span),
     // e.g. "(" expression ")"
     Grouping {
         expression: Box<Expr>,
     },
+    // `depth` is filled in by the resolver, see [Expr::Variable]
     Assign {
         name: Token,
         value: Box<Expr>,
+        depth: Cell<Option<usize>>,
     },
     Logical {
         left: Box<Expr>,
@@ -41,13 +46,148 @@ pub enum Expr {
         right: Box<Expr>,
     },
     // E.g. [IDENTIFIER] accesses a variable
+    // `depth` is how many environments up the variable lives, it's `None` for globals (or before the resolver runs)
     Variable {
         name: Token,
+        depth: Cell<Option<usize>>,
+    },
+    // e.g. callee "(" arguments? ")"
+    // `paren` is the closing parenthesis, kept around so errors know where the call was
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+    },
+    // e.g. condition "?" then_branch ":" else_branch
+    Ternary {
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    // e.g. "++" IDENTIFIER or IDENTIFIER "--", `prefix` decides whether the old or the new value is returned
+    Update {
+        name: Token,
+        operator: Token,
+        prefix: bool,
+        depth: Cell<Option<usize>>,
+    },
+    // e.g. object "." IDENTIFIER
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+    // e.g. object "." IDENTIFIER "=" value
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    // e.g. "[" ( expression ( "," expression )* )? "]"
+    // `bracket` is the opening bracket
+    List {
+        bracket: Token,
+        elements: Vec<Expr>,
+    },
+    // e.g. "{" ( expression ":" expression ( "," expression ":" expression )* )? "}"
+    // `brace` is the opening brace
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    },
+    // e.g. object "[" index "]", where object is a list or a map
+    // `bracket` is the closing bracket, kept around for the line number like [Expr::Call]'s `paren`
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    // e.g. object "[" index "]" "=" value
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    // `this` inside of a method, resolved just like a variable
+    This {
+        keyword: Token,
+        depth: Cell<Option<usize>>,
     },
     // null
     Null,
 }
 
+impl Expr {
+    /// Creates an unresolved [Expr::Variable]
+    pub fn variable(name: Token) -> Self {
+        Expr::Variable {
+            name,
+            depth: Cell::new(None),
+        }
+    }
+
+    /// The source this expression covers, from its first token to its last one that's kept around.
+    /// Only [Expr::Null] doesn't have one, since it's not written anywhere
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Expr::Literal(_, span) => Some(*span),
+            Expr::Null => None,
+            Expr::Grouping { expression } => expression.span(),
+            Expr::Assign { name, value, .. } => Span::join(Some(name.span), value.span()),
+            Expr::Variable { name, .. } => Some(name.span),
+            Expr::Update {
+                name,
+                operator,
+                prefix,
+                ..
+            } => Some(match prefix {
+                true => operator.span.to(name.span),
+                false => name.span.to(operator.span),
+            }),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            }
+            | Expr::Binary {
+                left,
+                operator,
+                right,
+            } => Span::join(Span::join(left.span(), Some(operator.span)), right.span()),
+            Expr::Unary { operator, right } => Span::join(Some(operator.span), right.span()),
+            Expr::Call { callee, paren, .. } => Span::join(callee.span(), Some(paren.span)),
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => Span::join(
+                Span::join(condition.span(), then_branch.span()),
+                else_branch.span(),
+            ),
+            Expr::Get { object, name } => Span::join(object.span(), Some(name.span)),
+            Expr::Set { object, value, .. } | Expr::SetIndex { object, value, .. } => {
+                Span::join(object.span(), value.span())
+            }
+            Expr::This { keyword, .. } => Some(keyword.span),
+            Expr::List { bracket, elements } => {
+                Span::join(Some(bracket.span), elements.last().and_then(Expr::span))
+            }
+            Expr::Map { brace, entries } => Span::join(
+                Some(brace.span),
+                entries.last().and_then(|(_, value)| value.span()),
+            ),
+            Expr::Index {
+                object, bracket, ..
+            } => Span::join(object.span(), Some(bracket.span)),
+        }
+    }
+
+    /// The line the expression starts on
+    pub fn line(&self) -> Option<usize> {
+        self.span().map(|span| span.line)
+    }
+}
+
 #[derive(Debug)]
 /// A statement can be an expression, `print` followed by something, `var` followed by something, a `{}`, an `if {} else {}`, and more
 pub enum Stmt {
@@ -66,8 +206,96 @@ pub enum Stmt {
         else_branch: Option<Box<Stmt>>,
     },
     // Separate class for expressions and statements makes declaring this very nice (but I would argue the same for if condition)
-    While { 
+    // `increment` is only there for desugared `for` loops, it runs after the body, even if the body `continue`d.
+    // `keyword` is the `while` or the `for`, which is all that tells the two apart once a `for` is desugared
+    While {
+        keyword: Token,
         condition: Expr,
-        body: Box<Stmt>
+        body: Box<Stmt>,
+        increment: Option<Expr>,
+    },
+    // `break;` and `continue;`, the parser makes sure they're always inside of a loop
+    Break {
+        keyword: Token,
+    },
+    Continue {
+        keyword: Token,
+    },
+    // `func name(params) { body }`
+    Function(Rc<FunctionDecl>),
+    // `class Name { method() {..} }`
+    Class {
+        name: Token,
+        methods: Vec<Rc<FunctionDecl>>,
+    },
+    // `return value;`, where value is [Expr::Null] if there's nothing after the `return`
+    Return {
+        keyword: Token,
+        value: Expr,
+    },
+    // `import "path.lox" as name;`, where `name` defaults to the file's name without the extension
+    // `path` is the string token, relative to the file doing the importing
+    Import {
+        keyword: Token,
+        path: Token,
+        name: Token,
+    },
+    // `throw value;`, any value can be thrown
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    // `try { body } catch (name) { handler } finally { cleanup }`, at least one of `catch` and `finally` is always there
+    Try {
+        keyword: Token,
+        body: Vec<Stmt>,
+        catch: Option<(Token, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    },
+}
+
+impl Stmt {
+    /// Where the statement starts, and as far as its tokens can tell, where it ends
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Stmt::Expr(e) | Stmt::Print(e) => e.span(),
+            Stmt::Var { name, initializer } => Span::join(Some(name.span), initializer.span()),
+            Stmt::Class { name, .. } => Some(name.span),
+            Stmt::Block { statements } => Span::join(
+                statements.first().and_then(Stmt::span),
+                statements.last().and_then(Stmt::span),
+            ),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => Span::join(
+                Span::join(condition.span(), then_branch.span()),
+                else_branch.as_ref().and_then(|e| e.span()),
+            ),
+            Stmt::While { keyword, body, .. } => Span::join(Some(keyword.span), body.span()),
+            Stmt::Function(declaration) => Some(declaration.name.span),
+            Stmt::Return { keyword, value } | Stmt::Throw { keyword, value } => {
+                Span::join(Some(keyword.span), value.span())
+            }
+            Stmt::Import { keyword, name, .. } => Some(keyword.span.to(name.span)),
+            Stmt::Break { keyword } | Stmt::Continue { keyword } | Stmt::Try { keyword, .. } => {
+                Some(keyword.span)
+            }
+        }
     }
+
+    /// The line the statement starts on
+    pub fn line(&self) -> Option<usize> {
+        self.span().map(|span| span.line)
+    }
+}
+
+#[derive(Debug)]
+/// The declaration of a function. It lives behind an `Rc` because every function value created
+/// at runtime points back at the same declaration instead of cloning its body
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
 }
@@ -1,75 +1,641 @@
-use crate::token::{Literal, Token};
+//! Turns syntax trees back into source, the way `nenia fmt` lays it out: four spaces per block, a space around every
+//! binary operator, and never more than one blank line in a row.
+//! Comments aren't part of the tree, so [format] puts them back in between whatever they were found in between
 
-use super::Expr;
+use std::collections::VecDeque;
 
-impl Expr {
-    pub fn accept_str(&self, visitor: &Visitor) -> String {
-        match self {
-            Expr::Literal(e) => visitor.visit_literal(e),
-            Expr::Grouping { expression } => visitor.visit_grouping(expression),
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => visitor.visit_binary(left, operator, right),
-            Expr::Unary { operator, right } => visitor.visit_unary(operator, right),
-            _ => {
-                panic!()
+use crate::{
+    scanner::Scanner,
+    token::{Literal, Span, Token, TokenType},
+    Error,
+};
+
+use super::{Expr, FunctionDecl, Stmt};
+
+/// Formats `src`, comments and all. `file` is only there for error messages, if `src` doesn't parse
+pub fn format(src: &str, file: &str) -> Result<String, Error> {
+    let mut scanner = Scanner::with_file(src.to_string(), file);
+    let scanned = scanner.scan_tokens();
+    let comments = std::mem::take(&mut scanner.comments);
+    let braces = scanner
+        .tokens
+        .iter()
+        .filter(|t| matches!(t.token_type, TokenType::LeftBrace | TokenType::RightBrace))
+        .map(|t| t.span.start)
+        .collect();
+    let statements = crate::syntax(scanned, scanner.tokens)?;
+
+    let mut printer = Printer::new(src, comments.into(), braces);
+    printer.statements(&statements);
+    printer.comments_before(usize::MAX);
+    Ok(printer.out)
+}
+
+/// Prints statements that don't come from any source, so there's no comments to keep
+pub fn print(statements: &[Stmt]) -> String {
+    let mut printer = Printer::new("", VecDeque::new(), VecDeque::new());
+    printer.statements(statements);
+    printer.out
+}
+
+/// Prints a single expression, e.g. `-123 * (45.67)`
+pub fn print_expr(expr: &Expr) -> String {
+    let mut printer = Printer::new("", VecDeque::new(), VecDeque::new());
+    printer.expr(expr);
+    printer.out
+}
+
+struct Printer<'a> {
+    out: String,
+    /// How many blocks deep the line being printed is
+    indent: usize,
+    /// The source being formatted, empty if there isn't any.
+    /// Comments and blank lines are copied from it, and it's how sugar is told apart from what it desugared into
+    src: &'a str,
+    /// Comments that haven't been printed yet, in order
+    comments: VecDeque<Span>,
+    /// Where each `{` and `}` that hasn't been printed yet is in the source.
+    /// Braces get printed in the same order they're written in, so the first one is always the one being printed
+    braces: VecDeque<usize>,
+    /// Where in the source whatever was printed last ends
+    last: Option<usize>,
+    /// Where in `out` the last line ending in a `//` comment ends, nothing can be joined onto that line
+    line_comment: Option<usize>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(src: &'a str, comments: VecDeque<Span>, braces: VecDeque<usize>) -> Self {
+        Self {
+            out: String::new(),
+            indent: 0,
+            src,
+            comments,
+            braces,
+            last: None,
+            line_comment: None,
+        }
+    }
+
+    /// Whether `span` is where `text` is written, as opposed to a token the parser made up while desugaring.
+    /// Without any source there's no telling, so everything is printed as is
+    fn written(&self, span: Span, text: &str) -> bool {
+        self.src.is_empty() || self.src.get(span.start..span.end) == Some(text)
+    }
+
+    fn moved_past(&mut self, end: usize) {
+        self.last = Some(self.last.map_or(end, |last| last.max(end)));
+    }
+
+    /// Prints every comment that starts before `position`. One that shares its line with code stays at the end of
+    /// that line, any other gets a line of its own
+    fn comments_before(&mut self, position: usize) {
+        while self.comments.front().is_some_and(|c| c.start < position) {
+            let comment = self.comments.pop_front().unwrap();
+            let text = &self.src[comment.start..comment.end];
+            let trailing = !self.src[..comment.start]
+                .rsplit('\n')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .is_empty();
+            if trailing && self.out.ends_with('\n') && self.line_comment != Some(self.out.len()) {
+                self.out.pop();
+                self.out.push(' ');
+            } else {
+                self.blank_line(comment.start);
+                self.indent();
             }
+            self.out.push_str(text.trim_end());
+            self.out.push('\n');
+            if text.starts_with("//") {
+                self.line_comment = Some(self.out.len());
+            }
+            self.moved_past(comment.end);
         }
     }
-}
 
-pub trait Inspector<T> {
-    fn visit_binary(&self, left: &Expr, operator: &Token, right: &Expr) -> T;
-    fn visit_unary(&self, operator: &Token, right: &Expr) -> T;
-    fn visit_grouping(&self, expr: &Expr) -> T;
-    fn visit_literal(&self, expr: &Literal) -> T;
-}
+    /// Keeps one blank line before whatever starts at `start`, if there was at least one in the source.
+    /// Never right at the start of the file or of a block though
+    fn blank_line(&mut self, start: usize) {
+        if self.out.is_empty() || self.out.ends_with("{\n") {
+            return;
+        }
+        let between = self.last.and_then(|last| self.src.get(last..start));
+        if between.is_some_and(|between| between.matches('\n').count() >= 2) {
+            self.out.push('\n');
+        }
+    }
 
-pub struct Visitor;
+    fn indent(&mut self) {
+        self.out.push_str(&"    ".repeat(self.indent));
+    }
 
-impl Visitor {
-    pub fn new() -> Self {
-        Visitor {}
+    /// Starts the line of something that starts at `start` in the source, after the comments that come before it
+    fn begin_line(&mut self, start: Option<usize>) {
+        if let Some(start) = start {
+            self.comments_before(start);
+            self.blank_line(start);
+        }
+        self.indent();
     }
-    pub fn print(&self, expr: &Expr) -> String {
-        expr.accept_str(self)
+
+    fn end_line(&mut self, end: Option<usize>) {
+        self.out.push('\n');
+        if let Some(end) = end {
+            self.moved_past(end);
+        }
     }
-}
 
-impl Default for Visitor {
-    fn default() -> Self {
-        Self::new()
+    fn open_brace(&mut self) {
+        self.braces.pop_front();
+        self.out.push('{');
     }
-}
 
-impl Inspector<String> for Visitor {
-    fn visit_binary(&self, left: &Expr, operator: &Token, right: &Expr) -> String {
-        format!(
-            "({} {} {})",
-            operator,
-            left.accept_str(self),
-            right.accept_str(self)
-        )
+    fn close_brace(&mut self) {
+        if let Some(brace) = self.braces.pop_front() {
+            self.moved_past(brace + 1);
+        }
+        self.out.push('}');
     }
-    fn visit_unary(&self, operator: &Token, right: &Expr) -> String {
-        format!("({} {})", operator, right.accept_str(self))
+
+    /// `{`, every statement on a line of its own, then `}`. Comments before the `}` are still inside the block
+    fn block(&mut self, statements: &[Stmt]) {
+        self.open_brace();
+        let close = self.braces.front().copied().unwrap_or(usize::MAX);
+        let commented = self.comments.front().is_some_and(|c| c.start < close);
+        if statements.is_empty() && !commented {
+            self.close_brace();
+            return;
+        }
+        self.out.push('\n');
+        self.indent += 1;
+        self.statements(statements);
+        self.comments_before(close);
+        self.indent -= 1;
+        self.indent();
+        self.close_brace();
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            let start = self.start(statement);
+            self.begin_line(start);
+            self.stmt(statement);
+            self.end_line(statement.span().map(|span| span.end));
+        }
+    }
+
+    /// Where a statement starts in the source, or close enough that no comment sits in between.
+    /// A block starts at its `{`, which is the next brace to be printed
+    fn start(&self, statement: &Stmt) -> Option<usize> {
+        match statement {
+            Stmt::Block { statements } => match self.for_loop(statements) {
+                Some((_, keyword)) => Some(keyword.span.start),
+                None => self.braces.front().copied(),
+            },
+            Stmt::While { keyword, .. } => Some(keyword.span.start),
+            statement => statement.span().map(|span| span.start),
+        }
     }
-    fn visit_grouping(&self, expr: &Expr) -> String {
-        format!("(group {})", expr.accept_str(self))
+
+    /// A block that's really a `for` loop with an initializer, i.e. the initializer comes after the `for` keyword
+    fn for_loop<'s>(&self, statements: &'s [Stmt]) -> Option<(&'s Stmt, &'s Token)> {
+        match statements {
+            [initializer, Stmt::While { keyword, .. }]
+                if keyword.token_type == TokenType::For
+                    && self.start(initializer) > Some(keyword.span.start) =>
+            {
+                Some((initializer, keyword))
+            }
+            _ => None,
+        }
     }
-    fn visit_literal(&self, expr: &Literal) -> String {
-        format!("{}", expr)
+
+    /// Whatever comes after `if (..)`, `else` or `while (..)`, on the same line
+    fn body(&mut self, body: &Stmt) {
+        self.out.push(' ');
+        self.stmt(body);
+    }
+
+    /// Whether `statement` prints as a block, i.e. ends in a `}` an `else` can go right after
+    fn is_block(&self, statement: &Stmt) -> bool {
+        match statement {
+            Stmt::Block { statements } => self.for_loop(statements).is_none(),
+            _ => false,
+        }
+    }
+
+    fn stmt(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expr(expr) => {
+                self.expr(expr);
+                self.out.push(';');
+            }
+            Stmt::Print(expr) => {
+                self.out.push_str("print ");
+                self.expr(expr);
+                self.out.push(';');
+            }
+            Stmt::Var { name, initializer } => {
+                self.out.push_str("var ");
+                self.out.push_str(&name.lexeme.as_str());
+                if *initializer != Expr::Null {
+                    self.out.push_str(" = ");
+                    self.expr(initializer);
+                }
+                self.out.push(';');
+            }
+            Stmt::Block { statements } => match (self.for_loop(statements), &statements[..]) {
+                (
+                    Some((initializer, _)),
+                    [_, Stmt::While {
+                        condition,
+                        body,
+                        increment,
+                        ..
+                    }],
+                ) => self.for_statement(Some(initializer), condition, increment.as_ref(), body),
+                _ => self.block(statements),
+            },
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.out.push_str("if (");
+                self.expr(condition);
+                self.out.push(')');
+                self.body(then_branch);
+                if let Some(else_branch) = else_branch {
+                    match self.is_block(then_branch) {
+                        true => self.out.push(' '),
+                        false => {
+                            self.out.push('\n');
+                            self.indent();
+                        }
+                    }
+                    self.out.push_str("else");
+                    self.body(else_branch);
+                }
+            }
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+                increment,
+            } => match keyword.token_type {
+                TokenType::For => self.for_statement(None, condition, increment.as_ref(), body),
+                _ => {
+                    self.out.push_str("while (");
+                    self.expr(condition);
+                    self.out.push(')');
+                    self.body(body);
+                }
+            },
+            Stmt::Break { .. } => self.out.push_str("break;"),
+            Stmt::Continue { .. } => self.out.push_str("continue;"),
+            Stmt::Function(declaration) => {
+                self.out.push_str("func ");
+                self.function(declaration);
+            }
+            Stmt::Class { name, methods } => {
+                self.out.push_str("class ");
+                self.out.push_str(&name.lexeme.as_str());
+                self.out.push(' ');
+                self.open_brace();
+                let close = self.braces.front().copied().unwrap_or(usize::MAX);
+                self.out.push('\n');
+                self.indent += 1;
+                for method in methods {
+                    self.begin_line(Some(method.name.span.start));
+                    self.function(method);
+                    self.end_line(None);
+                }
+                self.comments_before(close);
+                self.indent -= 1;
+                self.indent();
+                self.close_brace();
+            }
+            Stmt::Return { value, .. } => {
+                self.out.push_str("return");
+                if *value != Expr::Null {
+                    self.out.push(' ');
+                    self.expr(value);
+                }
+                self.out.push(';');
+            }
+            Stmt::Import { path, name, .. } => {
+                self.out.push_str("import ");
+                self.literal(&path.literal);
+                // Without an `as`, the name is made up from the path and points at it
+                if name.span != path.span {
+                    self.out.push_str(" as ");
+                    self.out.push_str(&name.lexeme.as_str());
+                }
+                self.out.push(';');
+            }
+            Stmt::Throw { value, .. } => {
+                self.out.push_str("throw ");
+                self.expr(value);
+                self.out.push(';');
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.out.push_str("try ");
+                self.block(body);
+                if let Some((name, handler)) = catch {
+                    self.out.push_str(" catch (");
+                    self.out.push_str(&name.lexeme.as_str());
+                    self.out.push_str(") ");
+                    self.block(handler);
+                }
+                if let Some(cleanup) = finally {
+                    self.out.push_str(" finally ");
+                    self.block(cleanup);
+                }
+            }
+        }
+    }
+
+    /// `for (initializer; condition; increment) body`, undoing what the parser desugared it into
+    fn for_statement(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: &Expr,
+        increment: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        self.out.push_str("for (");
+        match initializer {
+            Some(initializer) => self.stmt(initializer),
+            None => self.out.push(';'),
+        }
+        // A missing condition is a `true` that isn't written anywhere
+        let omitted = matches!(condition, Expr::Literal(Literal::Boolean(true), span) if !self.written(*span, "true"));
+        if !omitted {
+            self.out.push(' ');
+            self.expr(condition);
+        }
+        self.out.push(';');
+        if let Some(increment) = increment {
+            self.out.push(' ');
+            self.expr(increment);
+        }
+        self.out.push(')');
+        self.body(body);
+    }
+
+    /// `name(params) { body }`, what's left of a function after the `func`
+    fn function(&mut self, declaration: &FunctionDecl) {
+        self.out.push_str(&declaration.name.lexeme.as_str());
+        self.out.push('(');
+        for (i, param) in declaration.params.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.out.push_str(&param.lexeme.as_str());
+        }
+        self.out.push_str(") ");
+        self.block(&declaration.body);
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(literal, _) => self.literal(literal),
+            Expr::Grouping { expression } => {
+                self.out.push('(');
+                self.expr(expression);
+                self.out.push(')');
+            }
+            Expr::Assign { name, value, .. } => {
+                self.out.push_str(&name.lexeme.as_str());
+                match value.as_ref() {
+                    // `a += b`, which became `a = a + b` with the `+` pointing at the `+=`
+                    Expr::Binary {
+                        left,
+                        operator,
+                        right,
+                    } if matches!(left.as_ref(), Expr::Variable { name: variable, .. } if variable.span == name.span)
+                        && !self.written(operator.span, &operator.lexeme.as_str()) =>
+                    {
+                        self.out.push(' ');
+                        self.out.push_str(&operator.lexeme.as_str());
+                        self.out.push_str("= ");
+                        self.expr(right);
+                    }
+                    value => {
+                        self.out.push_str(" = ");
+                        self.expr(value);
+                    }
+                }
+            }
+            Expr::Binary { operator, .. }
+                if operator.token_type == TokenType::Plus && !self.written(operator.span, "+") =>
+            {
+                self.interpolation(expr)
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            }
+            | Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expr(left);
+                self.out.push(' ');
+                self.out.push_str(&operator.lexeme.as_str());
+                self.out.push(' ');
+                self.expr(right);
+            }
+            Expr::Unary { operator, right } => {
+                self.out.push_str(&operator.lexeme.as_str());
+                let operand = self.out.len();
+                self.expr(right);
+                // `- -a` would scan as `--a` without the space
+                if operator.token_type == TokenType::Minus && self.out[operand..].starts_with('-') {
+                    self.out.insert(operand, ' ');
+                }
+            }
+            Expr::Variable { name, .. } => self.out.push_str(&name.lexeme.as_str()),
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expr(callee);
+                self.out.push('(');
+                self.exprs(arguments);
+                self.out.push(')');
+            }
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.out.push_str(" ? ");
+                self.expr(then_branch);
+                self.out.push_str(" : ");
+                self.expr(else_branch);
+            }
+            Expr::Update {
+                name,
+                operator,
+                prefix,
+                ..
+            } => match prefix {
+                true => {
+                    self.out.push_str(&operator.lexeme.as_str());
+                    self.out.push_str(&name.lexeme.as_str());
+                }
+                false => {
+                    self.out.push_str(&name.lexeme.as_str());
+                    self.out.push_str(&operator.lexeme.as_str());
+                }
+            },
+            Expr::Get { object, name } => {
+                self.expr(object);
+                self.out.push('.');
+                self.out.push_str(&name.lexeme.as_str());
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expr(object);
+                self.out.push('.');
+                self.out.push_str(&name.lexeme.as_str());
+                self.out.push_str(" = ");
+                self.expr(value);
+            }
+            Expr::List { elements, .. } => {
+                self.out.push('[');
+                self.exprs(elements);
+                self.out.push(']');
+            }
+            Expr::Map { entries, .. } => {
+                self.open_brace();
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expr(key);
+                    self.out.push_str(": ");
+                    self.expr(value);
+                }
+                self.close_brace();
+            }
+            Expr::Index { object, index, .. } => {
+                self.expr(object);
+                self.out.push('[');
+                self.expr(index);
+                self.out.push(']');
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expr(object);
+                self.out.push('[');
+                self.expr(index);
+                self.out.push_str("] = ");
+                self.expr(value);
+            }
+            Expr::This { .. } => self.out.push_str("this"),
+            Expr::Null => {}
+        }
+    }
+
+    /// `"a ${b} c"`, which the parser turned into `"a " + (b) + " c"` with made up `+`s
+    fn interpolation(&mut self, mut expr: &Expr) {
+        let mut parts = Vec::new();
+        while let Expr::Binary {
+            left,
+            operator,
+            right,
+        } = expr
+        {
+            if operator.token_type != TokenType::Plus || self.written(operator.span, "+") {
+                break;
+            }
+            parts.push(right.as_ref());
+            expr = left;
+        }
+        parts.push(expr);
+
+        self.out.push('"');
+        for part in parts.into_iter().rev() {
+            match part {
+                Expr::Literal(Literal::String(s), _) => self.escaped(&s.as_str()),
+                Expr::Grouping { expression } => {
+                    self.out.push_str("${");
+                    self.expr(expression);
+                    self.out.push('}');
+                }
+                part => {
+                    self.out.push_str("${");
+                    self.expr(part);
+                    self.out.push('}');
+                }
+            }
+        }
+        self.out.push('"');
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        match literal {
+            Literal::String(s) => {
+                self.out.push('"');
+                self.escaped(&s.as_str());
+                self.out.push('"');
+            }
+            Literal::Nil => self.out.push_str("nil"),
+            literal => self.out.push_str(&literal.to_string()),
+        }
+    }
+
+    /// The inside of a string literal that scans back into `s`
+    fn escaped(&mut self, s: &str) {
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\t' => self.out.push_str("\\t"),
+                '$' if chars.peek() == Some(&'{') => self.out.push_str("\\$"),
+                c if c.is_control() => self.out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                c => self.out.push(c),
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn tree() {
-        use super::*;
         use crate::token::Literal;
+        use crate::token::Span;
         use crate::token::Token;
         use crate::token::TokenType;
         // create a new tree
@@ -77,24 +643,104 @@ mod test {
             left: Box::new(Expr::Unary {
                 operator: Token {
                     token_type: TokenType::Minus,
-                    lexeme: "-".to_string(),
+                    lexeme: "-".into(),
                     literal: Literal::Nil,
-                    line: 1,
+                    span: Span::default(),
                 },
-                right: Box::new(Expr::Literal(Literal::Number(123.0))),
+                right: Box::new(Expr::Literal(Literal::Number(123.0), Span::default())),
             }),
             operator: Token {
                 token_type: TokenType::Star,
-                lexeme: "*".to_string(),
+                lexeme: "*".into(),
                 literal: Literal::Nil,
-                line: 1,
+                span: Span::default(),
             },
             right: Box::new(Expr::Grouping {
-                expression: Box::new(Expr::Literal(Literal::Number(45.67))),
+                expression: Box::new(Expr::Literal(Literal::Number(45.67), Span::default())),
             }),
         };
-        let str = Visitor::new().print(&binary_expression);
+        let str = print_expr(&binary_expression);
+
+        assert_eq!(str, "-123 * (45.67)");
+    }
 
-        assert_eq!(str, "(* (- 123) (group 45.67))");
+    /// The tree `src` parses into, with every span left out, since those move around when the source is formatted
+    fn shape(src: &str) -> String {
+        let mut scanner = Scanner::with_file(src.to_string(), "<fmt>");
+        let scanned = scanner.scan_tokens();
+        let statements = crate::syntax(scanned, scanner.tokens).unwrap();
+        let tree = format!("{:#?}", statements);
+        let mut shape = String::new();
+        let mut rest = tree.as_str();
+        while let Some(start) = rest.find("<fmt>:") {
+            shape.push_str(&rest[..start]);
+            rest = &rest[start..];
+            rest = &rest[rest.find(')').unwrap() + 1..];
+        }
+        shape.push_str(rest);
+        shape
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = r#"
+var a = 1;var b;
+print -a * (2 + 3) - - -a;
+a += 2; a -= 1; a *= 3; a /= 4; a = a + 1;
+var s = "x ${a + 1} y ${"${a}"} \"quoted\" \n \$not {} done";
+var t = "${a}" + "b";
+if (a < 2 and !false or nil == nil) print "yes"; else if (a) print "maybe"; else { print "no"; }
+while (a > 0) { a--; --a; ++a; if (a == 3) break; else continue; }
+for (var i = 0; i < 3; i++) print i;
+for (a = 0;;) { break; }
+for (; a < 10;) a++;
+for (;; a++) break;
+{ var i = 0; for (; i < 3; i = i + 1) {} }
+func add(x, y) { return x + y; }
+func nothing() { return; }
+class Point { init(x) { this.x = x; } get() { return this.x >= 1 ? this.x : [1, 2][0]; } }
+var m = {"a": {"b": [1, 2.5, true]}, 1: nil};
+m["a"]["b"] = Point(1).get();
+Point(2).x = m["a"];
+import "std/math.lox";
+import "other.lox" as other;
+try { throw "no"; } catch (e) { print e; } finally { print "done"; }
+try { print 1; } finally {}
+"#;
+        let formatted = format(src, "<fmt>").unwrap();
+        assert_eq!(shape(src), shape(&formatted), "{}", formatted);
+        // Formatting something that's already formatted changes nothing
+        assert_eq!(format(&formatted, "<fmt>").unwrap(), formatted);
+    }
+
+    #[test]
+    fn layout() {
+        let src = "if(a){print 1;}else print 2;\nwhile(true)a=a+1;\nfor(var i=0;i<1;i+=1){}\nclass A{f(){}}\n";
+        assert_eq!(
+            format(src, "<fmt>").unwrap(),
+            "if (a) {\n    print 1;\n} else print 2;\nwhile (true) a = a + 1;\nfor (var i = 0; i < 1; i += 1) {}\nclass A {\n    f() {}\n}\n"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let src = "// leading\nvar a = 1; // trailing\n\n\n\n/* block */\n{\n// inside\nprint a;\n\n// last\n}\nclass A {\n    // method\n    f() {}\n}\n// end";
+        assert_eq!(
+            format(src, "<fmt>").unwrap(),
+            "// leading\nvar a = 1; // trailing\n\n/* block */\n{\n    // inside\n    print a;\n\n    // last\n}\nclass A {\n    // method\n    f() {}\n}\n// end\n"
+        );
+    }
+
+    #[test]
+    fn comment_after_line_comment() {
+        let src = "var l = [\n  1, // one\n  2 /* two\n  lines */\n];\n";
+        let formatted = format(src, "<fmt>").unwrap();
+        assert_eq!(formatted, "var l = [1, 2]; // one\n/* two\n  lines */\n");
+        assert_eq!(shape(src), shape(&formatted));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(format("print (1;", "<fmt>").is_err());
     }
 }
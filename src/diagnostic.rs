@@ -0,0 +1,337 @@
+//! Turns errors into something a person can read: the message, where it happened with the offending source
+//! underlined, and whatever notes and help the error has to offer.
+//! Loosely modelled after rustc's output, e.g.
+//!
+//! ```text
+//! error[E0401]: undefined variable `nope`
+//!  --> main.lox:2:11
+//!   |
+//! 2 | print x + nope;
+//!   |           ^^^^ not defined anywhere
+//!   |
+//!   = help: variables have to be declared with `var` before they're used
+//! ```
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, IsTerminal},
+    rc::Rc,
+};
+
+use crate::{interner::Symbol, token::Span, Error};
+
+thread_local! {
+    // Spans only know which file they're in, so every file the scanner sees is kept around to show excerpts from.
+    // Like the interner, it's per thread, since spans hold symbols
+    static SOURCES: RefCell<HashMap<Symbol, Rc<str>>> = RefCell::new(HashMap::new());
+}
+
+/// Remembers `src` as the contents of `file`, replacing whatever was there before
+pub fn add_source(file: Symbol, src: &str) {
+    SOURCES.with(|sources| sources.borrow_mut().insert(file, src.into()));
+}
+
+fn source(file: Symbol) -> Option<Rc<str>> {
+    SOURCES.with(|sources| sources.borrow().get(&file).cloned())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// See [Error::code]
+    pub code: Option<&'static str>,
+    pub message: String,
+    /// Where the error is, errors that don't come from any source (like an io error) don't have one
+    pub span: Option<Span>,
+    /// Shown right next to the underline
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            code: None,
+            message: message.into(),
+            span: None,
+            label: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: Option<&'static str>) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// The whole diagnostic, ending in a newline. `color` wraps the important bits in ANSI escapes
+    pub fn render(&self, color: bool) -> String {
+        let style = Style { color };
+        let mut out = String::new();
+        let error = match self.code {
+            Some(code) => format!("error[{}]: ", code),
+            None => "error: ".into(),
+        };
+        let _ = writeln!(
+            out,
+            "{}{}",
+            style.paint(ERROR, &error),
+            style.paint(BOLD, &self.message)
+        );
+
+        let excerpt = self
+            .span
+            .and_then(|span| Excerpt::new(&source(span.file)?, span));
+        // Wide enough for the line number, so the `|`s all line up
+        let gutter = " ".repeat(excerpt.as_ref().map_or(0, |e| e.line.to_string().len()));
+
+        if let Some(span) = self.span {
+            let _ = writeln!(out, "{}{} {}", gutter, style.paint(GUTTER, "-->"), span);
+        }
+        if let Some(excerpt) = &excerpt {
+            let bar = style.paint(GUTTER, "|");
+            let _ = writeln!(out, "{} {}", gutter, bar);
+            let _ = writeln!(
+                out,
+                "{} {} {}",
+                style.paint(GUTTER, &excerpt.line.to_string()),
+                bar,
+                excerpt.text
+            );
+            let underline = style.paint(ERROR, &"^".repeat(excerpt.width));
+            let _ = match &self.label {
+                Some(label) => writeln!(
+                    out,
+                    "{} {} {}{} {}",
+                    gutter,
+                    bar,
+                    excerpt.indent,
+                    underline,
+                    style.paint(ERROR, label)
+                ),
+                None => writeln!(out, "{} {} {}{}", gutter, bar, excerpt.indent, underline),
+            };
+            if !self.notes.is_empty() || self.help.is_some() {
+                let _ = writeln!(out, "{} {}", gutter, bar);
+            }
+        }
+
+        for note in &self.notes {
+            let _ = writeln!(out, "{} = {}{}", gutter, style.paint(BOLD, "note: "), note);
+        }
+        if let Some(help) = &self.help {
+            let _ = writeln!(out, "{} = {}{}", gutter, style.paint(BOLD, "help: "), help);
+        }
+        out
+    }
+}
+
+/// Prints every diagnostic in `e` to stderr, in color if stderr is a terminal (and `NO_COLOR` isn't set)
+pub fn report(e: &Error) {
+    let color = io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let diagnostics = e.diagnostics();
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.render(color));
+    }
+    if diagnostics.len() > 1 {
+        eprintln!(
+            "{}",
+            Style { color }.paint(ERROR, &format!("{} errors", diagnostics.len()))
+        );
+    }
+    if let Some(code) = diagnostics.iter().find_map(|d| d.code) {
+        eprintln!("For more about an error, try `nenia explain {}`.", code);
+    }
+}
+
+/// The source line a span starts on, and how to underline the span in it.
+/// Spans that run over more than one line are only underlined up to the end of the first one
+struct Excerpt {
+    line: usize,
+    text: String,
+    /// Whitespace up to where the underline starts, tabs are kept so it lines up with the source
+    indent: String,
+    width: usize,
+}
+
+impl Excerpt {
+    fn new(src: &str, span: Span) -> Option<Excerpt> {
+        // The source could have been replaced since the span was made, e.g. a file read again, so check it still fits
+        src.get(span.start..)?;
+        let line_start = src[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[span.start..]
+            .find('\n')
+            .map_or(src.len(), |i| span.start + i);
+        let text = src[line_start..line_end].trim_end_matches('\r');
+        let end = span.end.clamp(span.start, line_start + text.len());
+
+        Some(Excerpt {
+            line: span.line,
+            text: text.to_string(),
+            indent: src[line_start..span.start]
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect(),
+            width: src.get(span.start..end)?.chars().count().max(1),
+        })
+    }
+}
+
+const ERROR: &str = "1;31";
+const GUTTER: &str = "1;34";
+const BOLD: &str = "1";
+
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn paint(&self, code: &str, text: &str) -> String {
+        match self.color {
+            true => format!("\x1b[{}m{}\x1b[0m", code, text),
+            false => text.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::InterpreterVisitor;
+
+    fn render(src: &str) -> String {
+        let mut interpreter = InterpreterVisitor::new();
+        let e = crate::run(src.into(), &mut interpreter).unwrap_err();
+        e.diagnostics()
+            .iter()
+            .map(|d| d.render(false))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn renders_excerpts() {
+        assert_eq!(
+            render("var x = 1;\nprint x + nope;"),
+            "\
+error[E0401]: undefined variable `nope`
+ --> <script>:2:11
+  |
+2 | print x + nope;
+  |           ^^^^ not defined anywhere
+  |
+  = help: variables have to be declared with `var` before they're used
+"
+        );
+        // the underline stops at the end of the line, and tabs stay tabs so it lines up
+        assert_eq!(
+            render("\tprint \"abc\n"),
+            "\
+error[E0102]: unterminated string
+ --> <script>:1:8
+  |
+1 | \tprint \"abc
+  | \t      ^ string starts here
+  |
+  = help: add a `\"` where the string should end
+"
+        );
+        assert_eq!(
+            render("print (1;"),
+            "\
+error[E0201]: expected `)` after expression, found `;`
+ --> <script>:1:9
+  |
+1 | print (1;
+  |         ^ expected `)`
+"
+        );
+    }
+
+    #[test]
+    fn one_error_per_typo() {
+        let src = "var a = @;\nvar b = 1 $ 2;\nprint ~;\nvar c = 1;`\nprint (1;";
+        let e = crate::run(src.into(), &mut InterpreterVisitor::new()).unwrap_err();
+        let codes = e
+            .diagnostics()
+            .into_iter()
+            .map(|d| d.code.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(codes, ["E0103", "E0103", "E0103", "E0103", "E0201"]);
+    }
+
+    #[test]
+    fn assignment_target_help() {
+        let help = |src: &str| {
+            let e = crate::run(src.into(), &mut InterpreterVisitor::new()).unwrap_err();
+            e.diagnostics()[0].help.clone().unwrap()
+        };
+        assert_eq!(help("var l = [1]; l[0]++;"), "`++` only works on variables");
+        assert_eq!(
+            help("var l = [1]; l[0] += 2;"),
+            "`+=` only works on variables"
+        );
+        assert_eq!(
+            help("1 = 2;"),
+            "only variables, properties and indexes can be assigned to"
+        );
+    }
+
+    #[test]
+    fn reports_every_syntax_error() {
+        let src = "
+            var a = 1
+            print a;
+            func f() {
+                var = 2;
+                print #;
+                return a +;
+            }
+            print \"\\q\";
+            class A { m() { print (1; } }
+            print \"still here\";
+        ";
+        let mut interpreter = InterpreterVisitor::new();
+        let e = crate::run(src.into(), &mut interpreter).unwrap_err();
+        let messages = e
+            .diagnostics()
+            .into_iter()
+            .map(|d| format!("{}: {}", d.span.unwrap().line, d.message))
+            .collect::<Vec<_>>();
+        // scanner errors come first, then the parser's, and the parser picks back up inside of blocks.
+        // `print #;` only gets the scanner's error, the missing expression is the same mistake
+        assert_eq!(
+            messages,
+            [
+                "6: unexpected character `#`",
+                "9: unknown escape sequence `\\q`",
+                "3: expected `;` after variable declaration, found `print`",
+                "5: expected an identifier for the variable name, found `=`",
+                "7: expected an expression, found `;`",
+                "10: expected `)` after expression, found `;`",
+            ]
+        );
+    }
+}
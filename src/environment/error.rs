@@ -1,6 +1,6 @@
 use std::{error, fmt};
 
-use crate::token::Token;
+use crate::token::{Span, Token};
 
 #[derive(Debug)]
 pub struct EnvironmentError {
@@ -13,20 +13,32 @@ impl EnvironmentError {
     pub fn new(kind: ErrorKind) -> EnvironmentError {
         EnvironmentError { kind }
     }
+
+    pub fn span(&self) -> Span {
+        match &self.kind {
+            ErrorKind::UndefinedVariable(t, _) => t.span,
+        }
+    }
 }
 
 impl fmt::Display for EnvironmentError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self.kind {
-            ErrorKind::UndefinedVariable(t, s) => {
-                write!(f, "undefined variable `{:#?}` duing {}", t, s)
-            }
+        write!(f, "{}: {}", self.span(), self.kind)
+    }
+}
+
+/// Just the message, without where it happened
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UndefinedVariable(t, _) => write!(f, "undefined variable `{}`", t.lexeme),
         }
     }
 }
 
 #[derive(Debug)]
 pub enum ErrorKind {
+    /// The variable, and what the environment was doing when it didn't find it
     UndefinedVariable(Token, String),
 }
 
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::{interpreter::Object, token::Token};
+use crate::{interner::Symbol, interpreter::Object, token::Token};
 
 use self::error::env_error;
 
@@ -9,12 +9,16 @@ pub mod error;
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 /// Code following tutorial from https://dev.to/deciduously/no-more-tears-no-more-knots-arena-allocated-trees-in-rust-44k6
+/// Unlike the tutorial, nodes can be removed from anywhere in the arena, not just the end.
+/// Removed slots are remembered and handed out again by the next `push`, so ids stay stable
 #[derive(Debug)]
 pub struct Arena<T>
 where
     T: PartialEq,
 {
-    arena: Vec<Node<T>>,
+    arena: Vec<Option<Node<T>>>,
+    /// Slots that have been removed and can be reused
+    free: Vec<usize>,
 }
 
 impl<T> Arena<T>
@@ -22,22 +26,27 @@ where
     T: PartialEq,
 {
     pub fn new() -> Self {
-        Self { arena: Vec::new() }
+        Self {
+            arena: Vec::new(),
+            free: Vec::new(),
+        }
     }
     pub fn get(&self, id: usize) -> Option<&Node<T>> {
-        self.arena.get(id)
-    }
-    pub fn pop(&mut self) {
-        self.arena.pop();
+        self.arena.get(id).and_then(Option::as_ref)
     }
     pub fn remove(&mut self, id: usize) {
-        self.arena.remove(id);
+        if let Some(slot) = self.arena.get_mut(id) {
+            if slot.take().is_some() {
+                self.free.push(id);
+            }
+        }
     }
     pub fn get_mut(&mut self, id: usize) -> Option<&mut Node<T>> {
-        self.arena.get_mut(id)
+        self.arena.get_mut(id).and_then(Option::as_mut)
     }
     pub fn push(&mut self, val: T) -> usize {
-        let idx = self.arena.len();
+        // Reuse a removed slot if there is one, otherwise grow the arena
+        let idx = self.free.pop().unwrap_or(self.arena.len());
 
         self.push_node(Node {
             idx,
@@ -47,8 +56,27 @@ where
 
         idx
     }
+    /// Number of nodes that are currently alive
+    pub fn len(&self) -> usize {
+        self.arena.len() - self.free.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Ids of every node that is currently alive
+    pub fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.arena
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, slot)| slot.as_ref().map(|_| idx))
+    }
     fn push_node(&mut self, node: Node<T>) {
-        self.arena.push(node)
+        let idx = node.idx;
+        if idx == self.arena.len() {
+            self.arena.push(Some(node))
+        } else {
+            self.arena[idx] = Some(node)
+        }
     }
 }
 
@@ -77,7 +105,72 @@ impl Cactus {
         Cactus { arena, cur_env }
     }
 
-    pub fn define(&mut self, name: &str, obj: Object, cur_env: usize) {
+    /// Creates a new, empty environment without a parent, i.e. another global scope
+    pub fn push_root(&mut self) -> usize {
+        self.arena.push(Environment::new())
+    }
+
+    /// Creates a new, empty environment whose enclosing scope is `parent`
+    pub fn push_child(&mut self, parent: usize) -> usize {
+        let idx = self.arena.push(Environment::new());
+        self.arena.get_mut(idx).unwrap().parent = Some(parent);
+        idx
+    }
+
+    /// Marks `env` and every environment enclosing it as captured by a closure, which keeps them from being released
+    pub fn capture(&mut self, env: usize) {
+        let mut cur = Some(env);
+        while let Some(idx) = cur {
+            let node = self.arena.get_mut(idx).unwrap();
+            // If this one's already captured then so are all of its parents
+            if node.val.captured {
+                break;
+            }
+            node.val.captured = true;
+            cur = node.parent;
+        }
+    }
+
+    /// Called when the block (or function call) that owns `env` is done with it.
+    /// The environment is only actually removed if no closure could still be looking at it, which is what's returned.
+    /// Captured environments are left for the garbage collector
+    pub fn release(&mut self, env: usize) -> bool {
+        let captured = self.arena.get(env).unwrap().val.captured;
+        if !captured {
+            self.arena.remove(env);
+        }
+        !captured
+    }
+
+    /// The environment enclosing `env`, `None` for the globals
+    pub fn parent(&self, env: usize) -> Option<usize> {
+        self.arena.get(env).and_then(|node| node.parent)
+    }
+
+    /// Every value defined directly in `env`
+    pub fn values(&self, env: usize) -> impl Iterator<Item = &Object> {
+        self.arena
+            .get(env)
+            .into_iter()
+            .flat_map(|node| node.val.values.values())
+    }
+
+    /// Every name defined directly in `env`, along with its value
+    pub fn bindings(&self, env: usize) -> impl Iterator<Item = (Symbol, &Object)> {
+        self.arena
+            .get(env)
+            .into_iter()
+            .flat_map(|node| node.val.values.iter().map(|(name, value)| (*name, value)))
+    }
+
+    /// Removes every environment `keep` says no, returning how many were removed
+    pub fn sweep(&mut self, keep: impl Fn(usize) -> bool) -> usize {
+        let dead = self.arena.ids().filter(|&id| !keep(id)).collect::<Vec<_>>();
+        dead.iter().for_each(|&id| self.arena.remove(id));
+        dead.len()
+    }
+
+    pub fn define(&mut self, name: Symbol, obj: Object, cur_env: usize) {
         // dbg!(&self);
         let node = self.arena.get_mut(cur_env).unwrap();
         node.define(name, obj);
@@ -100,13 +193,56 @@ impl Cactus {
             Err(Box::new(env_error(name, "retrieving variable in get()")))
         }
     }
-    /// This func is essentially the same as `.get()` except we don't return anything so we don't have to worry about lifetimes
-    pub fn assign(&mut self, name: &Token, obj: Object, cur_env: usize) -> Result<()> {
+    /// Walks exactly `distance` parents up from `cur_env`, the resolver has already figured out how far away the variable is
+    fn ancestor(&self, cur_env: usize, distance: usize) -> usize {
+        (0..distance).fold(cur_env, |env, _| {
+            // unwrap, because the resolver guarantees the environment is that deep
+            self.arena.get(env).unwrap().parent.unwrap()
+        })
+    }
+
+    /// Like `.get()`, but only looks in the environment `distance` parents up instead of searching every one
+    pub fn get_at(&self, name: &Token, cur_env: usize, distance: usize) -> Result<&Object> {
+        let env = self.arena.get(self.ancestor(cur_env, distance)).unwrap();
+        env.val
+            .values
+            .get(&name.lexeme)
+            .ok_or_else(|| env_error(name, "retrieving resolved variable in get_at()").into())
+    }
+
+    /// Like `.assign()`, but only looks in the environment `distance` parents up
+    pub fn assign_at(
+        &mut self,
+        name: &Token,
+        obj: Object,
+        cur_env: usize,
+        distance: usize,
+    ) -> Result<usize> {
+        let env = self.ancestor(cur_env, distance);
+        match self
+            .arena
+            .get_mut(env)
+            .unwrap()
+            .val
+            .values
+            .get_mut(&name.lexeme)
+        {
+            Some(enclosing) => {
+                *enclosing = obj;
+                Ok(env)
+            }
+            None => Err(Box::new(env_error(name, "during resolved assign"))),
+        }
+    }
+
+    /// This func is essentially the same as `.get()` except we only return the index of the environment the variable lives in,
+    /// so we don't have to worry about lifetimes
+    pub fn assign(&mut self, name: &Token, obj: Object, cur_env: usize) -> Result<usize> {
         let env = self.arena.get_mut(cur_env).unwrap();
         // first check if the current environment holds the variable
         if let Some(enclosing) = env.val.values.get_mut(&name.lexeme) {
             *enclosing = obj;
-            Ok(())
+            Ok(cur_env)
         } else if let Some(p) = env.parent {
             // unwrap, because we assume arena holds the parent
             self.assign(name, obj, p)
@@ -131,20 +267,23 @@ pub struct Node<T> {
 
 #[derive(Debug, PartialEq)]
 pub struct Environment {
-    values: HashMap<String, Object>,
+    values: HashMap<Symbol, Object>,
+    /// Whether a function value closes over this environment, in which case it has to outlive its block
+    captured: bool,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Environment {
             values: HashMap::new(),
+            captured: false,
         }
     }
 }
 
 impl Node<Environment> {
-    pub fn define(&mut self, name: &str, obj: Object) {
-        self.val.values.insert(name.to_string(), obj);
+    pub fn define(&mut self, name: Symbol, obj: Object) {
+        self.val.values.insert(name, obj);
     }
 }
 
@@ -1,56 +1,352 @@
-use std::fmt::Display;
+use std::{fmt::Display, io};
+
+use crate::{
+    diagnostic::Diagnostic, environment::error::EnvironmentError,
+    interpreter::error::InterpreterError, parser::error::ParseError, resolver::error::ResolveError,
+    token::Span, vm::error::VmError,
+};
 
 #[derive(Debug)]
-pub struct Error {
-    kind: ErrorKind,
+pub struct ScanError {
+    pub kind: ScanErrorKind,
 }
 
-// impl std::error::Error for Error { }
-impl Error {
-    pub fn new(kind: ErrorKind) -> Self {
+impl ScanError {
+    pub fn new(kind: ScanErrorKind) -> Self {
         Self { kind }
     }
+
+    /// Where in the source the error is
+    pub fn span(&self) -> Span {
+        match &self.kind {
+            ScanErrorKind::UnterminatedComment(s)
+            | ScanErrorKind::UnterminatedString(s)
+            | ScanErrorKind::UnexpectedCharacter(_, s)
+            | ScanErrorKind::EmptyInterpolation(s)
+            | ScanErrorKind::UnknownEscape(_, s)
+            | ScanErrorKind::InvalidUnicodeEscape(s) => *s,
+        }
+    }
+
+    /// See [Error::code]
+    pub fn code(&self) -> &'static str {
+        match &self.kind {
+            ScanErrorKind::UnterminatedComment(_) => "E0101",
+            ScanErrorKind::UnterminatedString(_) => "E0102",
+            ScanErrorKind::UnexpectedCharacter(..) => "E0103",
+            ScanErrorKind::EmptyInterpolation(_) => "E0104",
+            ScanErrorKind::UnknownEscape(..) => "E0105",
+            ScanErrorKind::InvalidUnicodeEscape(_) => "E0106",
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.kind.to_string()).with_span(Some(self.span()));
+        match &self.kind {
+            ScanErrorKind::UnterminatedComment(_) => diagnostic
+                .with_label("comment starts here")
+                .with_help("close it with `*/`, comments nest so every `/*` needs its own"),
+            ScanErrorKind::UnterminatedString(_) => diagnostic
+                .with_label("string starts here")
+                .with_help("add a `\"` where the string should end"),
+            ScanErrorKind::UnexpectedCharacter(..) => {
+                diagnostic.with_label("not part of the language")
+            }
+            ScanErrorKind::EmptyInterpolation(_) => {
+                diagnostic.with_label("nothing to interpolate").with_help(
+                    "put an expression inside of the `${}`, or write `\\$` for a literal `$`",
+                )
+            }
+            ScanErrorKind::UnknownEscape(..) => diagnostic
+                .with_label("unknown escape")
+                .with_note("the escapes are `\\n`, `\\t`, `\\\"`, `\\\\`, `\\$` and `\\u{..}`"),
+            ScanErrorKind::InvalidUnicodeEscape(_) => diagnostic
+                .with_label("invalid escape")
+                .with_help("write the code point as 1 to 6 hex digits, e.g. `\\u{1F600}`"),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+impl Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span(), self.kind)
+    }
+}
+
+/// Just the message, without where it happened
+impl Display for ScanErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanErrorKind::UnterminatedComment(_) => write!(f, "unterminated multi-line comment"),
+            ScanErrorKind::UnterminatedString(_) => write!(f, "unterminated string"),
+            ScanErrorKind::UnexpectedCharacter(c, _) => write!(f, "unexpected character `{}`", c),
+            ScanErrorKind::EmptyInterpolation(_) => write!(f, "empty `${{}}` in string"),
+            ScanErrorKind::UnknownEscape(c, _) => write!(f, "unknown escape sequence `\\{}`", c),
+            ScanErrorKind::InvalidUnicodeEscape(_) => write!(f, "invalid unicode escape"),
+        }
+    }
+}
+
+/// Every kind holds the span of the source it's about, e.g. the opening quote of an unterminated string
+#[derive(Debug)]
+pub enum ScanErrorKind {
+    UnterminatedComment(Span),
+    UnterminatedString(Span),
+    UnexpectedCharacter(char, Span),
+    EmptyInterpolation(Span),
+    UnknownEscape(char, Span),
+    InvalidUnicodeEscape(Span),
+}
+
+/// `sysexits.h`'s exit status for input that isn't valid, which is what the Lox spec wants for compile errors
+pub const EX_DATAERR: u8 = 65;
+/// `sysexits.h`'s exit status for an input file that can't be read
+pub const EX_NOINPUT: u8 = 66;
+/// `sysexits.h`'s exit status for an internal error, which is what the Lox spec wants for runtime errors
+pub const EX_SOFTWARE: u8 = 70;
+/// `sysexits.h`'s exit status for an output file that can't be created or written to
+pub const EX_CANTCREAT: u8 = 73;
+/// `sysexits.h`'s exit status for reading or writing that failed without a file to blame, like on the terminal
+pub const EX_IOERR: u8 = 74;
+
+/// Anything that can go wrong running a script, from scanning it to running it.
+/// Every error has a stable code that `nenia explain` knows more about, see [crate::explain]
+#[derive(Debug)]
+pub enum Error {
+    Scan(ScanError),
+    Parse(ParseError),
+    Resolve(ResolveError),
+    /// Anything that went wrong while the tree-walker was running, undefined variables included.
+    /// Boxed since it's by far the biggest, and every `Result` in the pipeline would pay for it
+    Runtime(Box<InterpreterError>),
+    /// Anything that went wrong compiling to or running bytecode
+    Vm(VmError),
+    /// The file at the path couldn't be read, like the script itself or one passed to `:load`
+    Read(String, io::Error),
+    /// The file at the path couldn't be created or written to, like the one `--trace` writes to
+    Write(String, io::Error),
+    /// Reading or writing failed, but not on a file of ours, like reading input from the terminal
+    Io(io::Error),
+    /// An error that isn't one of ours, like one a native function made up itself
+    Other(Box<dyn std::error::Error>),
+    /// More than one error at once, like every syntax error in a file, in the order they were found
+    Many(Vec<Error>),
+}
+
+impl Error {
+    /// Just the one error if there's only one, [Error::Many] otherwise
+    pub fn many(mut errors: Vec<Error>) -> Error {
+        match errors.len() {
+            1 => errors.pop().unwrap(),
+            _ => Error::Many(errors),
+        }
+    }
+
+    /// A code like `E0102` that never changes for the same kind of error. The second digit is the phase
+    /// the error comes from: 1 for scanning, 2 for parsing, 3 for resolving, 4 for running and 5 for bytecode.
+    /// Only [Error::Many] doesn't have one, since every error in it has its own
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            Error::Scan(e) => Some(e.code()),
+            Error::Parse(e) => Some(e.code()),
+            Error::Resolve(e) => Some(e.code()),
+            Error::Runtime(e) => Some(e.kind.code()),
+            Error::Vm(e) => Some(e.kind.code()),
+            Error::Read(..) => Some("E0001"),
+            Error::Write(..) => Some("E0002"),
+            Error::Io(_) => Some("E0003"),
+            Error::Other(_) | Error::Many(_) => None,
+        }
+    }
+
+    /// What the process should exit with: [EX_DATAERR] if the script didn't compile, [EX_SOFTWARE] if it failed while running.
+    /// Files that can't be read are [EX_NOINPUT], and ones that can't be written are [EX_CANTCREAT]
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Scan(_) | Error::Parse(_) | Error::Resolve(_) => EX_DATAERR,
+            Error::Runtime(_) | Error::Other(_) => EX_SOFTWARE,
+            Error::Vm(e) if e.kind.is_compile_error() => EX_DATAERR,
+            Error::Vm(_) => EX_SOFTWARE,
+            Error::Read(..) => EX_NOINPUT,
+            Error::Write(..) => EX_CANTCREAT,
+            Error::Io(_) => EX_IOERR,
+            Error::Many(errors) => errors
+                .iter()
+                .map(Error::exit_code)
+                .max()
+                .unwrap_or(EX_DATAERR),
+        }
+    }
+
+    /// Every diagnostic in the error, each one tagged with its code
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let diagnostics = match self {
+            Error::Scan(e) => vec![e.diagnostic()],
+            Error::Parse(e) => vec![e.diagnostic()],
+            Error::Resolve(e) => vec![e.diagnostic()],
+            Error::Runtime(e) => return e.diagnostics(),
+            Error::Vm(e) => vec![e.diagnostic()],
+            Error::Read(..) | Error::Write(..) | Error::Io(_) | Error::Other(_) => {
+                vec![Diagnostic::error(self.to_string())]
+            }
+            Error::Many(errors) => return errors.iter().flat_map(Error::diagnostics).collect(),
+        };
+        diagnostics
+            .into_iter()
+            .map(|d| d.with_code(self.code()))
+            .collect()
+    }
 }
 
 impl std::error::Error for Error {}
 
+/// Every error on a line of its own
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.kind {
-            ErrorKind::UnterminatedComment(p) => write!(
-                f,
-                "Unterminated multi-line comment. Start begins at line {}, char {}",
-                p.line, p.char
-            ),
-            ErrorKind::UnterminatedString(p) => write!(
-                f,
-                "Unterminated string. Quote begins at line {}, char {}",
-                p.line, p.char
-            ),
-            ErrorKind::UnexpectedCharacter(p) => write!(
-                f,
-                "Unexpected character at line {}, char {}",
-                p.line, p.char
-            ),
+        match self {
+            Error::Scan(e) => write!(f, "{}", e),
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Resolve(e) => write!(f, "{}", e),
+            Error::Runtime(e) => write!(f, "{}", e),
+            Error::Vm(e) => write!(f, "{}", e),
+            Error::Read(path, e) => write!(f, "couldn't read `{}`: {}", path, e),
+            Error::Write(path, e) => write!(f, "couldn't write to `{}`: {}", path, e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Other(e) => write!(f, "{}", e),
+            Error::Many(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
-#[derive(Debug)]
-pub struct Position {
-    line: usize,
-    char: usize,
+impl From<ScanError> for Error {
+    fn from(e: ScanError) -> Self {
+        Error::Scan(e)
+    }
 }
 
-impl Position {
-    pub fn new(line: usize, char: usize) -> Self {
-        Position { line, char }
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Parse(e)
     }
 }
 
-#[derive(Debug)]
-pub enum ErrorKind {
-    UnterminatedComment(Position),
-    UnterminatedString(Position),
-    UnexpectedCharacter(Position),
+impl From<ResolveError> for Error {
+    fn from(e: ResolveError) -> Self {
+        Error::Resolve(e)
+    }
+}
+
+impl From<InterpreterError> for Error {
+    fn from(e: InterpreterError) -> Self {
+        Error::Runtime(Box::new(e))
+    }
+}
+
+impl From<VmError> for Error {
+    fn from(e: VmError) -> Self {
+        Error::Vm(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl<E: Into<Error>> From<Vec<E>> for Error {
+    fn from(errors: Vec<E>) -> Self {
+        Error::many(errors.into_iter().map(Into::into).collect())
+    }
+}
+
+/// The resolver, the tree-walker and the VM pass their errors around boxed, this finds out which one it is
+impl From<Box<dyn std::error::Error>> for Error {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        let e = match e.downcast::<Error>() {
+            Ok(e) => return *e,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<ResolveError>() {
+            Ok(e) => return Error::Resolve(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<InterpreterError>() {
+            Ok(e) => return Error::Runtime(e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<EnvironmentError>() {
+            Ok(e) => return Error::Runtime(Box::new((*e).into())),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<VmError>() {
+            Ok(e) => return Error::Vm(*e),
+            Err(e) => e,
+        };
+        match e.downcast::<io::Error>() {
+            Ok(e) => Error::Io(*e),
+            Err(e) => Error::Other(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::InterpreterVisitor;
+
+    fn run(src: &str) -> Error {
+        crate::run(src.into(), &mut InterpreterVisitor::new()).unwrap_err()
+    }
+
+    #[test]
+    fn codes_and_exit_statuses() {
+        let e = run("print \"a;");
+        assert_eq!((e.code(), e.exit_code()), (Some("E0102"), EX_DATAERR));
+        let e = run("print (1;");
+        assert_eq!((e.code(), e.exit_code()), (Some("E0201"), EX_DATAERR));
+        let e = run("return;");
+        assert_eq!((e.code(), e.exit_code()), (Some("E0303"), EX_DATAERR));
+        let e = run("print nope;");
+        assert_eq!((e.code(), e.exit_code()), (Some("E0401"), EX_SOFTWARE));
+        // every error is reported, but a file that won't compile is a compile error no matter what else went wrong
+        let e = run("print #; print (1;");
+        assert!(matches!(&e, Error::Many(errors) if errors.len() == 2));
+        assert_eq!(e.exit_code(), EX_DATAERR);
+    }
+
+    #[test]
+    fn io_errors() {
+        let options = crate::Options::default();
+        let e = crate::run_file("/no/such/dir/main.lox", &options).unwrap_err();
+        assert_eq!((e.code(), e.exit_code()), (Some("E0001"), EX_NOINPUT));
+        assert!(e
+            .to_string()
+            .starts_with("couldn't read `/no/such/dir/main.lox`: "));
+
+        let options = crate::Options {
+            trace: Some(crate::Trace::File("/no/such/dir/trace".into())),
+            ..options
+        };
+        let e = options.interpreter().err().unwrap();
+        assert_eq!((e.code(), e.exit_code()), (Some("E0002"), EX_CANTCREAT));
+        assert!(e
+            .to_string()
+            .starts_with("couldn't write to `/no/such/dir/trace`: "));
+
+        // errors that aren't ours are still a failure while running
+        let e = Error::from(Box::<dyn std::error::Error>::from("made up"));
+        assert_eq!((e.code(), e.exit_code()), (None, EX_SOFTWARE));
+        assert_eq!(e.to_string(), "made up");
+    }
 }
@@ -0,0 +1,391 @@
+//! The longer story behind every error code, which is what `nenia explain <code>` prints.
+//! Codes never change meaning once they're handed out, see [crate::Error::code]
+
+/// Every code, in order, with what it means
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "E0001",
+        "A file couldn't be read.
+
+The file passed to `nenia`, `nenia fmt` or the REPL's `:load` doesn't exist,
+isn't a file, or can't be read by the current user. The message says which file
+it was, and what the operating system had to say about it.",
+    ),
+    (
+        "E0002",
+        "A file couldn't be written.
+
+The file `--trace=<file>`, `nenia fmt` or the REPL's `:save` writes to couldn't
+be created or written to, usually because the directory it's in doesn't exist or
+can't be written to by the current user. The message says which file it was, and
+what the operating system had to say about it.",
+    ),
+    (
+        "E0003",
+        "Reading input or writing output failed.
+
+Something went wrong with the terminal, or whatever's standing in for it, like a
+pipe that was closed on the other end. The message says what the operating
+system had to say about it.",
+    ),
+    (
+        "E0101",
+        "A multi-line comment is never closed.
+
+Every `/*` needs a `*/` after it. Comments nest, so a `/*` inside of a comment
+needs its own `*/` too:
+
+    /* outer /* inner */ still a comment */",
+    ),
+    (
+        "E0102",
+        "A string is never closed.
+
+The string runs all the way to the end of the file without a closing `\"`.
+Strings can span lines, so the missing quote can be anywhere after the one the
+error points at. To put a quote inside of a string, escape it:
+
+    print \"she said \\\"hi\\\"\";",
+    ),
+    (
+        "E0103",
+        "A character that isn't part of the language.
+
+Outside of strings and comments, only letters, digits, whitespace and the
+operators and punctuation the language uses can appear. Identifiers can't contain
+`_` either.",
+    ),
+    (
+        "E0104",
+        "A `${}` with nothing inside of it.
+
+String interpolation needs an expression to interpolate:
+
+    print \"1 + 1 = ${1 + 1}\";
+
+To write a literal `${` in a string, escape the dollar sign: `\"\\${}\"`.",
+    ),
+    (
+        "E0105",
+        "An escape sequence that doesn't exist.
+
+The escapes strings understand are `\\n` (newline), `\\t` (tab), `\\\"` (quote),
+`\\\\` (backslash), `\\$` (dollar sign) and `\\u{..}` (a unicode code point).",
+    ),
+    (
+        "E0106",
+        "A unicode escape that isn't valid.
+
+A unicode escape is `\\u` followed by 1 to 6 hex digits in braces, which have to
+be a valid code point that isn't a surrogate:
+
+    print \"\\u{1F600}\";",
+    ),
+    (
+        "E0201",
+        "A token that doesn't belong where it is.
+
+The parser knew exactly what had to come next, like the `;` at the end of a
+statement or the `)` that closes a `(`, and found something else. The error says
+what it expected and what it found instead:
+
+    var a = 1    // missing `;`
+    print a;",
+    ),
+    (
+        "E0202",
+        "An expression is missing.
+
+Something that can't start an expression is where one has to be, e.g. the right
+side of an operator, an argument or a condition:
+
+    print 1 + ;",
+    ),
+    (
+        "E0203",
+        "A binary operator is missing its left operand.
+
+    print * 2;
+
+`-` is the only operator that can be used without a left operand, to negate a
+number.",
+    ),
+    (
+        "E0204",
+        "A function is called with, or declared with, more than 255 arguments.",
+    ),
+    (
+        "E0205",
+        "Something that can't be assigned to is on the left of an `=`, or is
+being incremented, decremented or compound-assigned.
+
+Only variables, properties and indexes can be assigned to:
+
+    a = 1;
+    point.x = 1;
+    list[0] = 1;
+    1 = a;      // not allowed
+
+`++`, `--`, `+=`, `-=`, `*=` and `/=` only work on variables:
+
+    a++;
+    a += 2;
+    list[0]++;  // not allowed, write `list[0] = list[0] + 1;`",
+    ),
+    (
+        "E0206",
+        "`break` or `continue` outside of a loop.
+
+Both have to be inside of a `while` or `for` loop, in the same function. A
+function declared inside of a loop can't break out of it.",
+    ),
+    (
+        "E0207",
+        "A `try` without a `catch` or a `finally`.
+
+A `try` has to do something with the errors in it, so at least one of the two
+has to come after it:
+
+    try { risky(); } catch (e) { print e.message; }
+    try { risky(); } finally { cleanup(); }",
+    ),
+    (
+        "E0208",
+        "A module whose file name can't be used as a name.
+
+`import \"path.lox\";` names the module after its file, which only works if the
+file name is a valid identifier. Otherwise, give it a name with `as`:
+
+    import \"my-module.lox\" as mine;",
+    ),
+    (
+        "E0301",
+        "A local variable is read in its own initializer.
+
+    var a = 1;
+    {
+        var a = a + 1;
+    }
+
+The inner `a` shadows the outer one as soon as it's declared, so the `a` in the
+initializer would be the variable that's still being initialized. Give the new
+variable a different name.",
+    ),
+    (
+        "E0302",
+        "A variable is declared twice in the same scope.
+
+Globals can be redeclared, but inside of a block or function every name can
+only be declared once. Function parameters count as declarations too.",
+    ),
+    (
+        "E0303",
+        "`return` outside of a function.
+
+Top-level code isn't inside of a function, so there's nothing to return from.",
+    ),
+    (
+        "E0304",
+        "A value is returned from an initializer.
+
+`init` always hands back the new instance, so it can't return anything else. A
+bare `return;` still works to leave it early.",
+    ),
+    (
+        "E0305",
+        "`this` outside of a class.
+
+`this` only means something inside of a method, where it's the instance the
+method was called on.",
+    ),
+    (
+        "E0401",
+        "A variable that was never declared.
+
+Variables have to be declared with `var` before they're used. Check for typos,
+and that the declaration runs before the variable is used.",
+    ),
+    (
+        "E0402",
+        "A value of the wrong type.
+
+The operation needs a value of one type, like a number for `-` or `<`, and got
+a value of another:
+
+    print \"a\" - 1;",
+    ),
+    (
+        "E0403",
+        "Division by zero.
+
+Dividing by zero is an error instead of infinity. Check the divisor first if it
+can be zero.",
+    ),
+    ("E0404", "A variable is used before it was given a value."),
+    (
+        "E0405",
+        "Something that isn't a function or a class is called.
+
+    var a = 1;
+    a();",
+    ),
+    (
+        "E0406",
+        "A function is called with the wrong number of arguments.
+
+Functions have to be called with exactly as many arguments as they have
+parameters. Classes take as many as their `init` does.",
+    ),
+    (
+        "E0407",
+        "A property is read or set on something that isn't an instance.
+
+Only instances of classes, which includes modules, have properties.",
+    ),
+    (
+        "E0408",
+        "A property that the instance doesn't have.
+
+The instance has no field with that name, and its class has no method with it.",
+    ),
+    ("E0409", "Something that isn't a list or a map is indexed."),
+    (
+        "E0410",
+        "A list index that isn't a whole number.
+
+    var list = [1, 2];
+    print list[0.5];",
+    ),
+    (
+        "E0411",
+        "A list index that's out of bounds.
+
+List indices start at 0, so the last element of a list of length n is at n - 1.
+Negative indices aren't allowed.",
+    ),
+    ("E0412", "An element is popped off of an empty list."),
+    (
+        "E0413",
+        "A map key that can't be hashed.
+
+Only nil, booleans, numbers and strings can be map keys.",
+    ),
+    ("E0414", "A map is read at a key it doesn't have."),
+    (
+        "E0415",
+        "An exception is never caught.
+
+A value was thrown with `throw`, or a runtime error happened, and no `try` with
+a `catch` was around to catch it:
+
+    try {
+        throw \"oops\";
+    } catch (e) {
+        print e;
+    }",
+    ),
+    (
+        "E0416",
+        "A module couldn't be imported.
+
+The file couldn't be found or read, or it doesn't compile. Import paths are
+relative to the file doing the importing.",
+    ),
+    (
+        "E0417",
+        "Modules that import each other.
+
+A module can't import itself, not even through other modules, since it would
+have to finish running before it could start. Move what both modules need into a
+third one that they both import.",
+    ),
+    (
+        "E0418",
+        "A `return` escaped its function.
+
+This can't happen in a program that compiled, and is a bug in the interpreter.",
+    ),
+    (
+        "E0419",
+        "A `break` escaped its loop.
+
+This can't happen in a program that compiled, and is a bug in the interpreter.",
+    ),
+    (
+        "E0420",
+        "A `continue` escaped its loop.
+
+This can't happen in a program that compiled, and is a bug in the interpreter.",
+    ),
+    (
+        "E0501",
+        "A function has more constants than the bytecode can refer to.
+
+Every literal in a function is a constant, and one function can only have 256
+of them. Split the function up.",
+    ),
+    (
+        "E0502",
+        "A function has more local variables than the bytecode can refer to.
+
+One function can only have 256 locals in scope at once, parameters included.",
+    ),
+    (
+        "E0503",
+        "A function captures more variables than the bytecode can refer to.
+
+One function can only capture 256 variables from the functions around it.",
+    ),
+    (
+        "E0504",
+        "Too much code to jump over.
+
+An `if`, loop or `and`/`or` has a body that's too big for the bytecode to jump
+over. Move some of it into a function.",
+    ),
+    (
+        "E0505",
+        "A feature the bytecode backend doesn't support yet.
+
+Run the script without `--vm` to use the tree-walker instead, which supports the
+whole language.",
+    ),
+    (
+        "E0506",
+        "The call stack overflowed.
+
+Too many function calls were in progress at once, which usually means a
+recursive function that never stops calling itself.",
+    ),
+];
+
+/// What the error with `code` means, codes are case insensitive
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn explanations() {
+        // sorted, so no code is ever handed out twice
+        assert!(EXPLANATIONS.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(explain("e0102")
+            .unwrap()
+            .starts_with("A string is never closed."));
+        assert_eq!(explain("E9999"), None);
+
+        // every error the pipeline hands out has an explanation
+        for src in ["\"", "print 1", "return 1;", "print nope;", "throw 1;"] {
+            let mut interpreter = crate::interpreter::InterpreterVisitor::new();
+            let e = crate::run(src.into(), &mut interpreter).unwrap_err();
+            let code = e.code().unwrap();
+            assert!(explain(code).is_some(), "{}", code);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+/// A cheap handle to an interned string. Two symbols are equal exactly when their strings are,
+/// so comparing and hashing one is as cheap as comparing and hashing a `u32`.
+///
+/// Every identifier and string literal the scanner sees is interned, so the interpreter never
+/// has to hash or clone a name to look up a variable, field or method
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// Hands out [Symbol]s, and remembers which string each one stands for.
+/// Strings are never removed, but only source text is interned, so the interner can't grow past the size of the program
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    strings: Vec<Rc<str>>,
+}
+
+impl Interner {
+    fn intern(&mut self, string: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(string) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.strings.len() as u32);
+        let string: Rc<str> = string.into();
+        self.strings.push(Rc::clone(&string));
+        self.symbols.insert(string, symbol);
+        symbol
+    }
+}
+
+thread_local! {
+    // Symbols are only meaningful on the thread that interned them, which is fine since the interpreter is single threaded
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Symbol {
+    pub fn intern(string: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(string))
+    }
+
+    /// The interned string. Every call hands out the same allocation,
+    /// which is what lets string equality short-circuit on a pointer comparison
+    pub fn as_str(self) -> Rc<str> {
+        INTERNER.with(|interner| Rc::clone(&interner.borrow().strings[self.0 as usize]))
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Self {
+        Symbol::intern(string)
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Shows the string instead of the number, which wouldn't tell anybody anything
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        let a = Symbol::intern("hello");
+        let b = Symbol::from("hello");
+        let c = Symbol::intern("world");
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(Rc::ptr_eq(&a.as_str(), &b.as_str()));
+        assert_eq!(a, "hello");
+        assert_eq!(c.to_string(), "world");
+    }
+}
@@ -0,0 +1,122 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    ast::FunctionDecl,
+    token::{Literal, Token, TokenType},
+};
+
+use super::{
+    class::Instance,
+    error::{ErrorKind, InterpreterError},
+    InterpreterVisitor, Object, Result,
+};
+
+/// Anything that can be called with `(..)` in nenia
+pub trait Callable {
+    /// How many arguments the callable expects
+    fn arity(&self) -> usize;
+    /// Runs the callable with arguments that have already been evaluated (and arity checked)
+    fn call(&self, interpreter: &mut InterpreterVisitor, arguments: Vec<Object>) -> Result<Object>;
+}
+
+/// The runtime representation of a function declared with `func`
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub declaration: Rc<FunctionDecl>,
+    /// Index of the environment the function was declared in
+    pub closure: usize,
+    /// The global environment of the module the function was declared in, which is where its globals are looked up
+    pub globals: usize,
+    /// Whether this is a class's `init` method, which always returns `this`
+    pub is_initializer: bool,
+}
+
+impl Function {
+    pub fn new(
+        declaration: Rc<FunctionDecl>,
+        closure: usize,
+        globals: usize,
+        is_initializer: bool,
+    ) -> Self {
+        Self {
+            declaration,
+            closure,
+            globals,
+            is_initializer,
+        }
+    }
+
+    /// Creates a copy of this method whose closure has `this` bound to `instance`
+    pub fn bind(
+        &self,
+        instance: Rc<RefCell<Instance>>,
+        interpreter: &mut InterpreterVisitor,
+    ) -> Function {
+        let env = interpreter.push_env(self.closure);
+        interpreter.define("this".into(), Object::Instance(instance), env);
+        // The bound method can be called any time later, so the environment holding `this` has to stick around
+        interpreter.cactus.capture(env);
+        Function::new(
+            Rc::clone(&self.declaration),
+            env,
+            self.globals,
+            self.is_initializer,
+        )
+    }
+
+    /// `this` lives in the closure of a bound method
+    fn this(&self, interpreter: &InterpreterVisitor) -> Result<Object> {
+        let this = Token::new(
+            TokenType::This,
+            "this".into(),
+            Literal::Nil,
+            self.declaration.name.span,
+        );
+        Ok(interpreter.cactus.get_at(&this, self.closure, 0)?.clone())
+    }
+}
+
+/// Two function values are only equal if they're the exact same declaration, closing over the exact same environment
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.declaration, &other.declaration) && self.closure == other.closure
+    }
+}
+
+impl Callable for Function {
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    fn call(&self, interpreter: &mut InterpreterVisitor, arguments: Vec<Object>) -> Result<Object> {
+        // Every call gets its own environment, whose parent is the environment the function was declared in
+        let env = interpreter.push_env(self.closure);
+
+        // Bind each parameter to its argument
+        self.declaration
+            .params
+            .iter()
+            .zip(arguments)
+            .for_each(|(param, arg)| interpreter.define(param.lexeme, arg, env));
+
+        // A function imported from another module still sees that module's globals
+        let res = interpreter.with_globals(self.globals, |interpreter| {
+            interpreter.execute_block(&self.declaration.body, env)
+        });
+        match res {
+            // An initializer hands back the instance no matter how it ends
+            Ok(()) if self.is_initializer => self.this(interpreter),
+            // Falling off the end of a function returns nil
+            Ok(()) => Ok(Object::Nil),
+            // `return` unwinds through the interpreter as an error, so catch it here
+            Err(e) => match e.downcast::<InterpreterError>() {
+                Ok(e) => match e.kind {
+                    ErrorKind::Return(_) if self.is_initializer => self.this(interpreter),
+                    ErrorKind::Return(value) => Ok(value),
+                    _ => Err(e),
+                },
+                Err(e) => Err(e),
+            },
+        }
+    }
+}
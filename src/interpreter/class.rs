@@ -0,0 +1,125 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use crate::{interner::Symbol, token::Token};
+
+use super::{
+    callable::{Callable, Function},
+    error::{ErrorKind, InterpreterError},
+    InterpreterVisitor, Object, Result,
+};
+
+/// The runtime representation of a `class` declaration. Calling it creates a new [Instance]
+#[derive(Debug)]
+pub struct Class {
+    pub name: Symbol,
+    pub methods: HashMap<Symbol, Function>,
+}
+
+impl Class {
+    pub fn new(name: Symbol, methods: HashMap<Symbol, Function>) -> Self {
+        Self { name, methods }
+    }
+
+    pub fn find_method(&self, name: Symbol) -> Option<&Function> {
+        self.methods.get(&name)
+    }
+}
+
+/// Classes have reference semantics, so two classes are only equal if they're the same class
+impl PartialEq for Class {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Callable for Rc<Class> {
+    /// A class takes however many arguments its `init` method does
+    fn arity(&self) -> usize {
+        self.find_method("init".into())
+            .map_or(0, |init| init.arity())
+    }
+
+    fn call(&self, interpreter: &mut InterpreterVisitor, arguments: Vec<Object>) -> Result<Object> {
+        let instance = interpreter.alloc_instance(Rc::clone(self));
+        let object = Object::Instance(Rc::clone(&instance));
+
+        // Run the initializer (if there is one) on the brand new instance,
+        // which has to stay rooted since nothing but us knows about it yet
+        if let Some(init) = self.find_method("init".into()) {
+            interpreter.rooted(&object, |interpreter| {
+                init.bind(instance, interpreter)
+                    .call(interpreter, arguments)
+            })?;
+        }
+
+        Ok(object)
+    }
+}
+
+/// An object created by calling a [Class], it's really just a bag of fields that knows its class
+pub struct Instance {
+    pub class: Rc<Class>,
+    fields: HashMap<Symbol, Object>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Fields shadow methods, and methods are bound to the instance before being handed out
+    pub fn get(
+        instance: &Rc<RefCell<Instance>>,
+        name: &Token,
+        interpreter: &mut InterpreterVisitor,
+    ) -> Result<Object> {
+        if let Some(field) = instance.borrow().fields.get(&name.lexeme) {
+            return Ok(field.clone());
+        }
+
+        let method = instance.borrow().class.find_method(name.lexeme).cloned();
+        match method {
+            Some(method) => Ok(Object::Function(
+                method.bind(Rc::clone(instance), interpreter),
+            )),
+            None => Err(Box::new(InterpreterError::new(
+                ErrorKind::UndefinedProperty(name.lexeme),
+            ))),
+        }
+    }
+
+    pub fn set(&mut self, name: &Token, value: Object) {
+        self.insert(name.lexeme, value);
+    }
+
+    /// Same as [Self::set], for fields the interpreter fills in itself
+    pub fn insert(&mut self, name: Symbol, value: Object) {
+        self.fields.insert(name, value);
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &Object> {
+        self.fields.values()
+    }
+
+    /// Drops every field, used by the garbage collector to break cycles between unreachable instances
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+/// Like classes, instances are only equal if they're the same instance
+impl PartialEq for Instance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Written by hand because an instance can hold itself in one of its fields, which would make a derived `Debug` recurse forever
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Instance({})", self.class.name)
+    }
+}
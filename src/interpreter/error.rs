@@ -1,37 +1,239 @@
 use std::{error, fmt};
 
+use crate::{
+    diagnostic::Diagnostic, environment::error::EnvironmentError, interner::Symbol, token::Span,
+};
+
 use super::Object;
 
 #[derive(Debug)]
 pub struct InterpreterError {
     pub kind: ErrorKind,
+    /// Span of the innermost expression or statement the error came out of,
+    /// filled in by [super::InterpreterVisitor::evaluate] and [super::InterpreterVisitor::execute]
+    pub span: Option<Span>,
 }
 
 impl error::Error for InterpreterError {}
 
 impl InterpreterError {
     pub fn new(kind: ErrorKind) -> InterpreterError {
-        InterpreterError { kind }
+        InterpreterError { kind, span: None }
+    }
+
+    /// The diagnostics for a module that doesn't compile are better shown where it went wrong,
+    /// than where it was imported, so those are handed back instead with a note about the import
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        if let ErrorKind::ImportFailed(path, e) = &self.kind {
+            let inner = e.diagnostics();
+            if inner.iter().all(|d| d.span.is_some()) {
+                let imported = match self.span {
+                    Some(span) => format!("while importing `{}` at {}", path, span),
+                    None => format!("while importing `{}`", path),
+                };
+                return inner
+                    .into_iter()
+                    .map(|d| d.with_note(imported.clone()))
+                    .collect();
+            }
+        }
+        vec![self.diagnostic()]
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.kind.to_string())
+            .with_span(self.span)
+            .with_code(Some(self.kind.code()));
+        match &self.kind {
+            ErrorKind::UndefinedVariable(_) => diagnostic
+                .with_label("not defined anywhere")
+                .with_help("variables have to be declared with `var` before they're used"),
+            ErrorKind::Thrown(_) => diagnostic
+                .with_label("thrown here")
+                .with_help("catch it with `try { .. } catch (e) { .. }`"),
+            ErrorKind::DivideByZero(_) => diagnostic.with_label("divided by zero"),
+            ErrorKind::NotCallable(_) | ErrorKind::WrongArity(..) => {
+                diagnostic.with_label("in this call")
+            }
+            ErrorKind::ImportCycle(_) => diagnostic
+                .with_label("closes the cycle")
+                .with_note("a module can't import itself, not even through other modules"),
+            _ => diagnostic,
+        }
+    }
+}
+
+/// Undefined variables are found by the environment, but they're runtime errors like any other
+impl From<EnvironmentError> for InterpreterError {
+    fn from(e: EnvironmentError) -> Self {
+        let span = e.span();
+        let crate::environment::error::ErrorKind::UndefinedVariable(name, _) = e.kind;
+        InterpreterError {
+            kind: ErrorKind::UndefinedVariable(name.lexeme),
+            span: Some(span),
+        }
     }
 }
 
 impl fmt::Display for InterpreterError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self.kind {
-            ErrorKind::FailedCast(t, o) => {
-                write!(f, "failed to cast {} to {}", t, o)
+        if let Some(span) = self.span {
+            write!(f, "{}: ", span)?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+/// Just the message, without where it happened. It's what a caught error's `message` is set to
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UndefinedVariable(name) => write!(f, "undefined variable `{}`", name),
+            ErrorKind::FailedCast(value, to) => {
+                write!(f, "failed to cast {} to {}", value, to)
             }
             ErrorKind::DivideByZero(n) => write!(f, "attempt to divide {} by 0", n),
             ErrorKind::UnitializedVariable => {
                 write!(f, "unitialized variable (too lazy to write name lol")
             }
+            ErrorKind::NotCallable(o) => {
+                write!(f, "can only call functions, but tried to call {}", o)
+            }
+            ErrorKind::WrongArity(expected, found) => {
+                write!(f, "expected {} arguments but got {}", expected, found)
+            }
+            ErrorKind::NotAnInstance(o) => {
+                write!(f, "only instances have properties, but {} isn't one", o)
+            }
+            ErrorKind::UndefinedProperty(name) => write!(f, "undefined property `{}`", name),
+            ErrorKind::NotIndexable(o) => {
+                write!(f, "only lists and maps can be indexed, but {} isn't one", o)
+            }
+            ErrorKind::InvalidIndex(o) => {
+                write!(f, "list indices have to be whole numbers, but got {}", o)
+            }
+            ErrorKind::IndexOutOfBounds(i, len) => {
+                write!(
+                    f,
+                    "index {} is out of bounds for a list of length {}",
+                    i, len
+                )
+            }
+            ErrorKind::EmptyList => write!(f, "can't pop from an empty list"),
+            ErrorKind::UnhashableKey(o) => {
+                write!(
+                    f,
+                    "only nil, booleans, numbers and strings can be map keys, not {}",
+                    o
+                )
+            }
+            ErrorKind::MissingKey(o) => write!(f, "map has no key {}", o),
+            ErrorKind::Return(_) => write!(f, "can't `return` from outside of a function"),
+            ErrorKind::Break => write!(f, "can't `break` outside of a loop"),
+            ErrorKind::Continue => write!(f, "can't `continue` outside of a loop"),
+            ErrorKind::Thrown(value) => write!(f, "uncaught exception {}", value),
+            ErrorKind::ImportFailed(path, reason) => {
+                write!(f, "couldn't import `{}`: {}", path, reason)
+            }
+            ErrorKind::ImportCycle(files) => write!(f, "import cycle: {}", files.join(" -> ")),
+            ErrorKind::StackOverflow => write!(f, "stack overflow"),
         }
     }
 }
 
 #[derive(Debug)]
 pub enum ErrorKind {
-    FailedCast(Object, Object),
+    UndefinedVariable(Symbol),
+    /// The value, and the name of the type it should've been
+    FailedCast(Object, &'static str),
     DivideByZero(f32),
     UnitializedVariable,
+    NotCallable(Object),
+    WrongArity(usize, usize),
+    NotAnInstance(Object),
+    UndefinedProperty(Symbol),
+    NotIndexable(Object),
+    InvalidIndex(Object),
+    IndexOutOfBounds(f32, usize),
+    EmptyList,
+    UnhashableKey(Object),
+    MissingKey(Object),
+    /// Not really an error, `return` unwinds the call stack by pretending to be one
+    Return(Object),
+    /// Same idea as `Return`, caught by the closest loop
+    Break,
+    Continue,
+    /// A value passed to `throw`, caught by the closest `try` with a `catch`
+    Thrown(Object),
+    /// The module's path, and why it couldn't be read or compiled
+    ImportFailed(String, Box<crate::Error>),
+    /// Every module in the cycle, starting and ending with the same one
+    ImportCycle(Vec<String>),
+    /// Too many calls in progress at once, see [super::MAX_CALL_DEPTH]
+    StackOverflow,
+}
+
+impl ErrorKind {
+    /// See [crate::Error::code]
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::UndefinedVariable(_) => "E0401",
+            ErrorKind::FailedCast(..) => "E0402",
+            ErrorKind::DivideByZero(_) => "E0403",
+            ErrorKind::UnitializedVariable => "E0404",
+            ErrorKind::NotCallable(_) => "E0405",
+            ErrorKind::WrongArity(..) => "E0406",
+            ErrorKind::NotAnInstance(_) => "E0407",
+            ErrorKind::UndefinedProperty(_) => "E0408",
+            ErrorKind::NotIndexable(_) => "E0409",
+            ErrorKind::InvalidIndex(_) => "E0410",
+            ErrorKind::IndexOutOfBounds(..) => "E0411",
+            ErrorKind::EmptyList => "E0412",
+            ErrorKind::UnhashableKey(_) => "E0413",
+            ErrorKind::MissingKey(_) => "E0414",
+            ErrorKind::Thrown(_) => "E0415",
+            ErrorKind::ImportFailed(..) => "E0416",
+            ErrorKind::ImportCycle(_) => "E0417",
+            ErrorKind::Return(_) => "E0418",
+            ErrorKind::Break => "E0419",
+            ErrorKind::Continue => "E0420",
+            // same as the VM's, it's the same mistake
+            ErrorKind::StackOverflow => "E0506",
+        }
+    }
+
+    /// What a caught error's `kind` is set to
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::UndefinedVariable(_) => "UndefinedVariable",
+            ErrorKind::FailedCast(..) => "FailedCast",
+            ErrorKind::DivideByZero(_) => "DivideByZero",
+            ErrorKind::UnitializedVariable => "UnitializedVariable",
+            ErrorKind::NotCallable(_) => "NotCallable",
+            ErrorKind::WrongArity(..) => "WrongArity",
+            ErrorKind::NotAnInstance(_) => "NotAnInstance",
+            ErrorKind::UndefinedProperty(_) => "UndefinedProperty",
+            ErrorKind::NotIndexable(_) => "NotIndexable",
+            ErrorKind::InvalidIndex(_) => "InvalidIndex",
+            ErrorKind::IndexOutOfBounds(..) => "IndexOutOfBounds",
+            ErrorKind::EmptyList => "EmptyList",
+            ErrorKind::UnhashableKey(_) => "UnhashableKey",
+            ErrorKind::MissingKey(_) => "MissingKey",
+            ErrorKind::Return(_) => "Return",
+            ErrorKind::Break => "Break",
+            ErrorKind::Continue => "Continue",
+            ErrorKind::Thrown(_) => "Thrown",
+            ErrorKind::ImportFailed(..) => "ImportFailed",
+            ErrorKind::ImportCycle(_) => "ImportCycle",
+            ErrorKind::StackOverflow => "StackOverflow",
+        }
+    }
+
+    /// `return`, `break` and `continue` are only errors so they can unwind, a `catch` should never see them
+    pub fn is_control_flow(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::Return(_) | ErrorKind::Break | ErrorKind::Continue
+        )
+    }
 }
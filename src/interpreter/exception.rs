@@ -0,0 +1,64 @@
+use std::{collections::HashMap, error::Error, rc::Rc};
+
+use crate::{environment::error::EnvironmentError, interner::Symbol};
+
+use super::{
+    class::Class,
+    error::{ErrorKind, InterpreterError},
+    InterpreterVisitor, Object, Result,
+};
+
+/// The class of every error object a `catch` hands out for a runtime error.
+/// It's a global, so scripts can make their own with `Error()` and throw them too
+pub fn error_class() -> Rc<Class> {
+    Rc::new(Class::new("Error".into(), HashMap::new()))
+}
+
+/// Turns an error that's unwinding through a `try` into the value its `catch` gets to see.
+/// Thrown values are caught as is, runtime errors become an `Error` instance with a `kind`, `message`, `line` and `column`.
+/// Anything that can't be caught, like a `return` on its way out, is handed back untouched
+pub fn catch(
+    interpreter: &mut InterpreterVisitor,
+    e: Box<dyn Error>,
+) -> std::result::Result<Object, Box<dyn Error>> {
+    let e = match e.downcast::<EnvironmentError>() {
+        Ok(e) => Box::new(InterpreterError::from(*e)),
+        Err(e) => e,
+    };
+    let caught = match &e.downcast_ref::<InterpreterError>() {
+        Some(e) => match &e.kind {
+            ErrorKind::Thrown(value) => return Ok(value.clone()),
+            kind if kind.is_control_flow() => None,
+            kind => Some((kind.name(), kind.to_string(), e.span)),
+        },
+        None => None,
+    };
+    let Some((kind, message, span)) = caught else {
+        return Err(e);
+    };
+
+    let class = Rc::clone(&interpreter.error_class);
+    let instance = interpreter.alloc_instance(class);
+    {
+        let mut instance = instance.borrow_mut();
+        instance.insert(Symbol::intern("kind"), Object::String(kind.into()));
+        instance.insert(Symbol::intern("message"), Object::String(message.into()));
+        instance.insert(
+            Symbol::intern("line"),
+            span.map_or(Object::Nil, |span| Object::Number(span.line as f32)),
+        );
+        instance.insert(
+            Symbol::intern("column"),
+            span.map_or(Object::Nil, |span| Object::Number(span.column as f32)),
+        );
+    }
+    Ok(Object::Instance(instance))
+}
+
+/// The value a `return` or `throw` is carrying, which has to stay rooted while a `finally` runs
+pub fn unwinding_value(res: &Result<()>) -> Option<&Object> {
+    match &res.as_ref().err()?.downcast_ref::<InterpreterError>()?.kind {
+        ErrorKind::Return(value) | ErrorKind::Thrown(value) => Some(value),
+        _ => None,
+    }
+}
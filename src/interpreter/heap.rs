@@ -0,0 +1,468 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    mem,
+    rc::{Rc, Weak},
+};
+
+use crate::environment::{Cactus, Environment, Node};
+
+use super::{
+    class::{Class, Instance},
+    list::List,
+    map::Map,
+    Object,
+};
+
+/// Decides when the collector runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcConfig {
+    /// How many bytes can be allocated before the first collection
+    pub initial_threshold: usize,
+    /// After a collection, the next one happens once the heap is this many times bigger than what survived
+    pub growth_factor: usize,
+    /// Collect on every single allocation, which shakes out anything that forgot to root its values
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            initial_threshold: 1024 * 1024,
+            growth_factor: 2,
+            stress: false,
+        }
+    }
+}
+
+/// What a single collection got rid of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Collection {
+    pub environments: usize,
+    pub instances: usize,
+    pub lists: usize,
+    pub maps: usize,
+}
+
+const ENVIRONMENT_SIZE: usize = mem::size_of::<Node<Environment>>();
+const INSTANCE_SIZE: usize = mem::size_of::<RefCell<Instance>>();
+const LIST_SIZE: usize = mem::size_of::<RefCell<List>>();
+const MAP_SIZE: usize = mem::size_of::<RefCell<Map>>();
+
+impl Object {
+    /// Whether the value can keep something the [Heap] manages alive
+    pub fn is_managed(&self) -> bool {
+        matches!(
+            self,
+            Object::Function(_)
+                | Object::Class(_)
+                | Object::Instance(_)
+                | Object::List(_)
+                | Object::Map(_)
+        )
+    }
+}
+
+/// Keeps track of everything the interpreter allocates that can end up in a cycle:
+/// environments (which closures point back into by index), instances (which can hold themselves in a field), lists and maps.
+///
+/// Both are still owned the usual way, environments by the [Cactus] and instances by `Rc`s,
+/// the heap just knows about all of them so a tracing mark-and-sweep can find the ones nothing reaches anymore.
+/// Unreachable environments are removed from the [Cactus], unreachable instances, lists and maps are emptied out,
+/// which breaks whatever cycle was keeping their `Rc` alive
+#[derive(Debug)]
+pub struct Heap {
+    config: GcConfig,
+    /// Every instance that's been allocated and hasn't been collected (or dropped) yet
+    instances: Vec<Weak<RefCell<Instance>>>,
+    lists: Vec<Weak<RefCell<List>>>,
+    maps: Vec<Weak<RefCell<Map>>>,
+    /// Rough number of bytes in use, only environments, instances, lists and maps themselves are counted
+    allocated: usize,
+    next_gc: usize,
+    collections: usize,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Self {
+            config,
+            instances: Vec::new(),
+            lists: Vec::new(),
+            maps: Vec::new(),
+            allocated: 0,
+            next_gc: config.initial_threshold,
+            collections: 0,
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.allocated > self.next_gc
+    }
+
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// How many times the collector has run so far
+    pub fn collections(&self) -> usize {
+        self.collections
+    }
+
+    /// Called for every environment pushed onto the [Cactus]
+    pub fn track_environment(&mut self) {
+        self.allocated += ENVIRONMENT_SIZE;
+    }
+
+    /// Called when [Cactus::release] removes an environment without the collector's help
+    pub fn untrack_environment(&mut self) {
+        self.allocated = self.allocated.saturating_sub(ENVIRONMENT_SIZE);
+    }
+
+    pub fn alloc_instance(&mut self, class: Rc<Class>) -> Rc<RefCell<Instance>> {
+        self.allocated += INSTANCE_SIZE;
+        let instance = Rc::new(RefCell::new(Instance::new(class)));
+        self.instances.push(Rc::downgrade(&instance));
+        instance
+    }
+
+    pub fn alloc_list(&mut self, items: Vec<Object>) -> Rc<RefCell<List>> {
+        self.allocated += LIST_SIZE;
+        let list = Rc::new(RefCell::new(List::new(items)));
+        self.lists.push(Rc::downgrade(&list));
+        list
+    }
+
+    pub fn alloc_map(&mut self, map: Map) -> Rc<RefCell<Map>> {
+        self.allocated += MAP_SIZE;
+        let map = Rc::new(RefCell::new(map));
+        self.maps.push(Rc::downgrade(&map));
+        map
+    }
+
+    /// Marks everything reachable from `envs` and `values`, then sweeps whatever wasn't reached
+    pub fn collect(
+        &mut self,
+        cactus: &mut Cactus,
+        envs: Vec<usize>,
+        values: Vec<Object>,
+    ) -> Collection {
+        let mut marker = Marker {
+            envs,
+            values,
+            ..Default::default()
+        };
+        marker.mark(cactus);
+
+        let environments = cactus.sweep(|env| marker.marked_envs.contains(&env));
+
+        let mut instances = 0;
+        self.instances.retain(|weak| match weak.upgrade() {
+            Some(instance) if marker.marked_instances.contains(&Rc::as_ptr(&instance)) => true,
+            Some(instance) => {
+                instance.borrow_mut().clear();
+                instances += 1;
+                false
+            }
+            // Nothing pointed at it in the first place, `Rc` already took care of it
+            None => false,
+        });
+
+        let mut lists = 0;
+        self.lists.retain(|weak| match weak.upgrade() {
+            Some(list) if marker.marked_lists.contains(&Rc::as_ptr(&list)) => true,
+            Some(list) => {
+                list.borrow_mut().items.clear();
+                lists += 1;
+                false
+            }
+            None => false,
+        });
+
+        let mut maps = 0;
+        self.maps.retain(|weak| match weak.upgrade() {
+            Some(map) if marker.marked_maps.contains(&Rc::as_ptr(&map)) => true,
+            Some(map) => {
+                map.borrow_mut().clear();
+                maps += 1;
+                false
+            }
+            None => false,
+        });
+
+        self.allocated = cactus.arena.len() * ENVIRONMENT_SIZE
+            + self.instances.len() * INSTANCE_SIZE
+            + self.lists.len() * LIST_SIZE
+            + self.maps.len() * MAP_SIZE;
+        self.next_gc =
+            (self.allocated * self.config.growth_factor).max(self.config.initial_threshold);
+        self.collections += 1;
+
+        Collection {
+            environments,
+            instances,
+            lists,
+            maps,
+        }
+    }
+}
+
+/// The state of a single mark phase. `envs` and `values` are the gray worklists
+#[derive(Default)]
+struct Marker {
+    envs: Vec<usize>,
+    values: Vec<Object>,
+    marked_envs: HashSet<usize>,
+    marked_classes: HashSet<*const Class>,
+    marked_instances: HashSet<*const RefCell<Instance>>,
+    marked_lists: HashSet<*const RefCell<List>>,
+    marked_maps: HashSet<*const RefCell<Map>>,
+}
+
+impl Marker {
+    fn mark(&mut self, cactus: &Cactus) {
+        loop {
+            if let Some(env) = self.envs.pop() {
+                if self.marked_envs.insert(env) {
+                    self.envs.extend(cactus.parent(env));
+                    self.gray(cactus.values(env));
+                }
+            } else if let Some(value) = self.values.pop() {
+                self.blacken(value);
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Queues up every value that points at something on the heap, plain values can't keep anything alive
+    fn gray<'a>(&mut self, values: impl Iterator<Item = &'a Object>) {
+        self.values
+            .extend(values.filter(|value| value.is_managed()).cloned());
+    }
+
+    fn blacken(&mut self, value: Object) {
+        match value {
+            Object::Function(function) => self.envs.push(function.closure),
+            Object::Class(class) if self.marked_classes.insert(Rc::as_ptr(&class)) => {
+                self.envs
+                    .extend(class.methods.values().map(|method| method.closure));
+            }
+            Object::Instance(instance) if self.marked_instances.insert(Rc::as_ptr(&instance)) => {
+                let instance = instance.borrow();
+                self.values.push(Object::Class(Rc::clone(&instance.class)));
+                self.gray(instance.fields());
+            }
+            Object::List(list) if self.marked_lists.insert(Rc::as_ptr(&list)) => {
+                self.gray(list.borrow().items.iter());
+            }
+            Object::Map(map) if self.marked_maps.insert(Rc::as_ptr(&map)) => {
+                self.gray(map.borrow().values());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        interpreter::InterpreterVisitor,
+        token::{Literal, Token, TokenType},
+    };
+
+    fn run(src: &str, config: GcConfig) -> InterpreterVisitor {
+        let mut interpreter = InterpreterVisitor::with_gc(config);
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+        interpreter
+    }
+
+    fn get(interpreter: &mut InterpreterVisitor, name: &str) -> Object {
+        interpreter
+            .evaluate(&crate::ast::Expr::variable(Token::new(
+                TokenType::Identifier,
+                name.into(),
+                Literal::Nil,
+                crate::token::Span::default(),
+            )))
+            .unwrap()
+    }
+
+    fn stress() -> GcConfig {
+        GcConfig {
+            stress: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stress_keeps_everything_reachable() {
+        let src = "
+            func counter() {
+                var count = 0;
+                func inc() { count++; return count; }
+                return inc;
+            }
+            class Node {
+                init(value) { this.value = value; this.next = this; }
+                get() { return this.value; }
+            }
+            var c = counter();
+            c();
+            var total = 0;
+            for (var i = 0; i < 20; i++) {
+                var node = Node(i);
+                total = total + node.next.get() + c();
+            }
+            var getter = Node(5).get;
+            var lists = [[c(), [c()]], Node(1)];
+            lists[0][1][0] = [c()];
+        ";
+        let mut interpreter = run(src, stress());
+        assert!(interpreter.heap().collections() > 20);
+        // sum(0..20) + sum(2..22)
+        assert_eq!(get(&mut interpreter, "total"), Object::Number(420.0));
+        assert_eq!(
+            crate::run("var five = getter();".into(), &mut interpreter).ok(),
+            Some(())
+        );
+        assert_eq!(get(&mut interpreter, "five"), Object::Number(5.0));
+        assert_eq!(
+            get(&mut interpreter, "lists").to_string(),
+            "[[22, [[24]]], Node instance]"
+        );
+    }
+
+    #[test]
+    fn cycles_are_collected() {
+        let src = "
+            class Pair {}
+            func make() {
+                var a = Pair();
+                var b = Pair();
+                a.other = b;
+                b.other = a;
+            }
+            for (var i = 0; i < 10; i++) { make(); }
+            var kept = Pair();
+            kept.self = kept;
+        ";
+        let mut interpreter = run(src, GcConfig::default());
+        assert_eq!(interpreter.heap.instances.len(), 21);
+
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.instances.len(), 1);
+        assert!(matches!(get(&mut interpreter, "kept"), Object::Instance(_)));
+    }
+
+    #[test]
+    fn list_cycles_are_collected() {
+        let src = "
+            for (var i = 0; i < 10; i++) {
+                var xs = [i];
+                push(xs, xs);
+            }
+            var kept = [[1, 2], 3];
+            push(kept, kept);
+        ";
+        let mut interpreter = run(src, GcConfig::default());
+        assert_eq!(interpreter.heap.lists.len(), 12);
+
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.lists.len(), 2);
+        assert_eq!(
+            get(&mut interpreter, "kept").to_string(),
+            "[[1, 2], 3, [...]]"
+        );
+    }
+
+    #[test]
+    fn unwinding_values_survive_finally() {
+        let src = "
+            func leave() {
+                try { return [1, [2]]; } finally { var garbage = [[], [], []]; }
+            }
+            var returned = leave();
+            var caught;
+            try {
+                try { throw {\"a\": [3]}; } finally { var garbage = [[], []]; }
+            } catch (e) {
+                caught = e;
+            }
+            var error;
+            try { [][0]; } catch (e) { var garbage = [[]]; error = e; }
+        ";
+        let mut interpreter = run(src, stress());
+        assert_eq!(get(&mut interpreter, "returned").to_string(), "[1, [2]]");
+        assert_eq!(get(&mut interpreter, "caught").to_string(), "{a: [3]}");
+        assert!(crate::run("var kind = error.kind;".into(), &mut interpreter).is_ok());
+        assert_eq!(
+            get(&mut interpreter, "kind"),
+            Object::String("IndexOutOfBounds".into())
+        );
+    }
+
+    #[test]
+    fn map_cycles_are_collected() {
+        let src = "
+            for (var i = 0; i < 10; i++) {
+                var m = {\"i\": i};
+                m[\"self\"] = m;
+            }
+            var kept = {\"inner\": {}};
+            kept[\"self\"] = kept;
+        ";
+        let mut interpreter = run(src, GcConfig::default());
+        assert_eq!(interpreter.heap.maps.len(), 12);
+
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.maps.len(), 2);
+        assert_eq!(
+            get(&mut interpreter, "kept").to_string(),
+            "{inner: {}, self: {...}}"
+        );
+    }
+
+    #[test]
+    fn captured_environments_are_collected() {
+        let src = "
+            func make() {
+                var x = 1;
+                func get() { return x; }
+                return get;
+            }
+            for (var i = 0; i < 10; i++) { make(); }
+            var kept = make();
+        ";
+        let mut interpreter = run(src, GcConfig::default());
+        assert!(interpreter.cactus.arena.len() > 10);
+
+        interpreter.collect_garbage();
+        // the globals, plus the call that `kept` closes over
+        assert_eq!(interpreter.cactus.arena.len(), 2);
+        assert_eq!(
+            crate::run("var one = kept();".into(), &mut interpreter).ok(),
+            Some(())
+        );
+        assert_eq!(get(&mut interpreter, "one"), Object::Number(1.0));
+    }
+
+    #[test]
+    fn thresholds() {
+        let src = "
+            func make() { func f() {} return f; }
+            for (var i = 0; i < 100; i++) { make(); }
+        ";
+        assert_eq!(run(src, GcConfig::default()).heap().collections(), 0);
+
+        let small = GcConfig {
+            initial_threshold: ENVIRONMENT_SIZE * 10,
+            growth_factor: 2,
+            stress: false,
+        };
+        let interpreter = run(src, small);
+        assert!(interpreter.heap().collections() > 0);
+        assert!(interpreter.heap().allocated() <= ENVIRONMENT_SIZE * 20);
+    }
+}
@@ -0,0 +1,116 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use super::{
+    error::{ErrorKind, InterpreterError},
+    InterpreterVisitor, Object, Result,
+};
+
+/// The values of a `[..]` literal. Lists are shared by reference, so assigning one to another variable doesn't copy it
+pub struct List {
+    pub items: Vec<Object>,
+}
+
+impl List {
+    pub fn new(items: Vec<Object>) -> Self {
+        Self { items }
+    }
+
+    /// Turns `index` into a position in the list, as long as it's a whole number that's in bounds
+    fn position(&self, index: &Object) -> Result<usize> {
+        let n = match index {
+            Object::Number(n) if n.fract() == 0.0 => *n,
+            _ => {
+                return Err(Box::new(InterpreterError::new(ErrorKind::InvalidIndex(
+                    index.clone(),
+                ))))
+            }
+        };
+        if n < 0.0 || n as usize >= self.items.len() {
+            return Err(Box::new(InterpreterError::new(
+                ErrorKind::IndexOutOfBounds(n, self.items.len()),
+            )));
+        }
+        Ok(n as usize)
+    }
+
+    pub fn get(&self, index: &Object) -> Result<Object> {
+        Ok(self.items[self.position(index)?].clone())
+    }
+
+    pub fn set(&mut self, index: &Object, value: Object) -> Result<()> {
+        let position = self.position(index)?;
+        self.items[position] = value;
+        Ok(())
+    }
+}
+
+/// Like instances, lists are only equal if they're the same list
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Written by hand because a list can contain itself
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "List({})", self.items.len())
+    }
+}
+
+thread_local! {
+    /// Lists and maps that are in the middle of being displayed, so one that contains itself prints `[...]` instead of recursing forever
+    static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `body` to display the collection at `ptr`, unless we're already inside of displaying it, in which case `placeholder` is shown
+pub(crate) fn display_once(
+    ptr: *const (),
+    placeholder: &str,
+    f: &mut fmt::Formatter<'_>,
+    body: impl FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result,
+) -> fmt::Result {
+    if DISPLAYING.with(|displaying| displaying.borrow().contains(&ptr)) {
+        return write!(f, "{}", placeholder);
+    }
+
+    DISPLAYING.with(|displaying| displaying.borrow_mut().push(ptr));
+    let res = body(f);
+    DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+    res
+}
+
+/// Displays as `[1, 2, 3]`, the same way it would be written
+pub fn display(list: &Rc<RefCell<List>>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    display_once(Rc::as_ptr(list) as *const (), "[...]", f, |f| {
+        write!(f, "[")?;
+        for (i, item) in list.borrow().items.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", item)?;
+        }
+        write!(f, "]")
+    })
+}
+
+/// `push(list, value)`, `pop(list)` and `len(list)`, which also works on strings and maps
+pub fn define_natives(interpreter: &mut InterpreterVisitor) {
+    interpreter.define_native("push", 2, |args| {
+        args[0].as_list()?.borrow_mut().items.push(args[1].clone());
+        Ok(Object::Nil)
+    });
+    interpreter.define_native("pop", 1, |args| {
+        args[0]
+            .as_list()?
+            .borrow_mut()
+            .items
+            .pop()
+            .ok_or_else(|| InterpreterError::new(ErrorKind::EmptyList).into())
+    });
+    interpreter.define_native("len", 1, |args| match &args[0] {
+        Object::String(s) => Ok(Object::Number(s.chars().count() as f32)),
+        Object::Map(map) => Ok(Object::Number(map.borrow().len() as f32)),
+        o => Ok(Object::Number(o.as_list()?.borrow().items.len() as f32)),
+    });
+}
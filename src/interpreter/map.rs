@@ -0,0 +1,181 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use super::{
+    error::{ErrorKind, InterpreterError},
+    list, InterpreterVisitor, Object, Result,
+};
+
+/// The subset of [Object]s that can be used as a map key.
+/// Only plain values are hashable, lists, maps, instances and functions can all change (or are only equal to themselves),
+/// so using one as a key is an error instead
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Nil,
+    Boolean(bool),
+    /// The bits of the number, with `-0` turned into `0` so the two are the same key like they're `==`
+    Number(u32),
+    String(Rc<str>),
+}
+
+impl Key {
+    pub fn new(value: &Object) -> Result<Key> {
+        match value {
+            Object::Nil => Ok(Key::Nil),
+            Object::Boolean(b) => Ok(Key::Boolean(*b)),
+            // NaN isn't equal to itself, so it could never be looked up again
+            Object::Number(n) if n.is_nan() => Err(Box::new(InterpreterError::new(
+                ErrorKind::UnhashableKey(value.clone()),
+            ))),
+            Object::Number(n) => Ok(Key::Number(if *n == 0.0 { 0.0f32 } else { *n }.to_bits())),
+            Object::String(s) => Ok(Key::String(Rc::clone(s))),
+            _ => Err(Box::new(InterpreterError::new(ErrorKind::UnhashableKey(
+                value.clone(),
+            )))),
+        }
+    }
+
+    pub fn to_object(&self) -> Object {
+        match self {
+            Key::Nil => Object::Nil,
+            Key::Boolean(b) => Object::Boolean(*b),
+            Key::Number(bits) => Object::Number(f32::from_bits(*bits)),
+            Key::String(s) => Object::String(Rc::clone(s)),
+        }
+    }
+}
+
+/// The values of a `{key: value}` literal. Like lists they're shared by reference.
+/// Entries always come back out in the order their keys were first inserted in
+#[derive(Default)]
+pub struct Map {
+    entries: Vec<(Key, Object)>,
+    /// Where each key lives in `entries`
+    indices: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Object) -> Result<Object> {
+        match self.indices.get(&Key::new(key)?) {
+            Some(&i) => Ok(self.entries[i].1.clone()),
+            None => Err(Box::new(InterpreterError::new(ErrorKind::MissingKey(
+                key.clone(),
+            )))),
+        }
+    }
+
+    pub fn contains(&self, key: &Object) -> Result<bool> {
+        Ok(self.indices.contains_key(&Key::new(key)?))
+    }
+
+    /// Overwriting a key keeps its original position
+    pub fn insert(&mut self, key: &Object, value: Object) -> Result<()> {
+        let key = Key::new(key)?;
+        match self.indices.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes `key` and hands back its value, everything after it moves up one spot
+    pub fn remove(&mut self, key: &Object) -> Result<Object> {
+        let i = match self.indices.remove(&Key::new(key)?) {
+            Some(i) => i,
+            None => {
+                return Err(Box::new(InterpreterError::new(ErrorKind::MissingKey(
+                    key.clone(),
+                ))))
+            }
+        };
+        let (_, value) = self.entries.remove(i);
+        self.indices
+            .values_mut()
+            .filter(|j| **j > i)
+            .for_each(|j| *j -= 1);
+        Ok(value)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Object> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    /// Used by the garbage collector to break cycles between unreachable maps
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.indices.clear();
+    }
+}
+
+/// Like lists, maps are only equal if they're the same map
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Written by hand because a map can contain itself
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Map({})", self.entries.len())
+    }
+}
+
+/// Displays as `{a: 1, b: 2}`, in insertion order
+pub fn display(map: &Rc<RefCell<Map>>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    list::display_once(Rc::as_ptr(map) as *const (), "{...}", f, |f| {
+        write!(f, "{{")?;
+        for (i, (key, value)) in map.borrow().entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", key.to_object(), value)?;
+        }
+        write!(f, "}}")
+    })
+}
+
+/// `keys(map)`, `values(map)`, `has(map, key)` and `remove(map, key)`, `len` is shared with lists.
+/// `keys` and `values` hand back a new list, in insertion order
+pub fn define_natives(interpreter: &mut InterpreterVisitor) {
+    interpreter.define_native_with_interpreter("keys", 1, |interpreter, args| {
+        let keys = args[0]
+            .as_map()?
+            .borrow()
+            .keys()
+            .map(Key::to_object)
+            .collect();
+        Ok(Object::List(interpreter.alloc_list(keys)))
+    });
+    interpreter.define_native_with_interpreter("values", 1, |interpreter, args| {
+        // The map is a rooted argument, so the values stay reachable while the list is allocated
+        let values = args[0].as_map()?.borrow().values().cloned().collect();
+        Ok(Object::List(interpreter.alloc_list(values)))
+    });
+    interpreter.define_native("has", 2, |args| {
+        Ok(Object::Boolean(
+            args[0].as_map()?.borrow().contains(&args[1])?,
+        ))
+    });
+    interpreter.define_native("remove", 2, |args| {
+        args[0].as_map()?.borrow_mut().remove(&args[1])
+    });
+}
@@ -1,14 +1,44 @@
+pub mod callable;
+pub mod class;
 pub mod error;
-use std::fmt::Display;
+pub mod exception;
+pub mod heap;
+pub mod list;
+pub mod map;
+pub mod module;
+pub mod native;
+pub mod trace;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::Display,
+    path::PathBuf,
+    rc::Rc,
+    time::Instant,
+};
 
 use crate::{
-    ast::{Expr, Stmt},
-    environment::{Cactus, Environment},
+    ast::{Expr, FunctionDecl, Stmt},
+    environment::Cactus,
+    interner::Symbol,
     token::{Literal, Token, TokenType},
 };
 
-use self::error::{ErrorKind, InterpreterError};
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use self::{
+    callable::{Callable, Function},
+    class::{Class, Instance},
+    error::{ErrorKind, InterpreterError},
+    heap::{GcConfig, Heap},
+    list::List,
+    map::Map,
+    native::NativeFunction,
+    trace::Tracer,
+};
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// How deep calls can nest before we call it a stack overflow, the same as the VM's `FRAMES_MAX`.
+/// Every call takes a few recursive `evaluate`s on the native stack, so this has to stay well below what fits in it
+const MAX_CALL_DEPTH: usize = 256;
 
 /// It might seem like objects are equivalent to literals
 /// but the distinction is important to make because literals are in the parser's
@@ -16,10 +46,17 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 /// We could theoretically also have classes and arbirary objects in the future
 #[derive(Debug, PartialEq, Clone)]
 pub enum Object {
-    String(String),
+    /// String literals are interned, strings built at runtime (e.g. by `+`) aren't
+    String(Rc<str>),
     Number(f32),
     Boolean(bool),
     Nil,
+    Function(Function),
+    Native(NativeFunction),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
+    List(Rc<RefCell<List>>),
+    Map(Rc<RefCell<Map>>),
 }
 
 impl Display for Object {
@@ -30,6 +67,12 @@ impl Display for Object {
             Object::Number(n) => write!(f, "{}", *n),
             Object::Boolean(b) => write!(f, "{}", *b),
             Object::Nil => write!(f, "null"),
+            Object::Function(func) => write!(f, "<fn {}>", func.declaration.name),
+            Object::Native(native) => write!(f, "<native fn {}>", native.name),
+            Object::Class(class) => write!(f, "{}", class.name),
+            Object::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Object::List(list) => list::display(list, f),
+            Object::Map(map) => map::display(map, f),
         }
     }
 }
@@ -37,7 +80,7 @@ impl Display for Object {
 impl From<&Literal> for Object {
     fn from(l: &Literal) -> Self {
         match l {
-            Literal::String(s) => Self::String(s.clone()),
+            Literal::String(s) => Self::String(s.as_str()),
             Literal::Number(n) => Self::Number(*n),
             Literal::Boolean(b) => Self::Boolean(*b),
             Literal::Nil => Self::Nil,
@@ -48,7 +91,7 @@ impl From<&Literal> for Object {
 impl ExprVisitor<Object> for InterpreterVisitor {
     fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<Object> {
         let left = self.evaluate(left)?;
-        let right = self.evaluate(right)?;
+        let right = self.rooted(&left, |interpreter| interpreter.evaluate(right))?;
 
         use TokenType::*;
 
@@ -71,15 +114,15 @@ impl ExprVisitor<Object> for InterpreterVisitor {
                 match left {
                     // Could use + operator to add numbers
                     Object::Number(n) => match right {
-                        Object::String(r) => Object::String(format!("{}{}", n, r)),
+                        Object::String(r) => Object::String(format!("{}{}", n, r).into()),
                         _ => Object::Number(n + Self::try_num(right)?),
                     },
                     // Could also use + operator to concatenate strings
-                    Object::String(l) => Object::String(format!("{}{}", l, right)),
+                    Object::String(l) => Object::String(format!("{}{}", l, right).into()),
                     _ => {
                         return Err(Box::new(InterpreterError::new(ErrorKind::FailedCast(
                             right.clone(),
-                            Object::String("".into()),
+                            "string",
                         ))))
                     }
                 }
@@ -89,15 +132,15 @@ impl ExprVisitor<Object> for InterpreterVisitor {
             Less => Object::Boolean(Self::try_num(left)? < Self::try_num(right)?),
             LessEqual => Object::Boolean(Self::try_num(left)? <= Self::try_num(right)?),
             // TODO TODO TODO Not sure if derivce(PartialEq) handles enum comparisons automatically
-            BangEqual => Object::Boolean(left != right),
-            EqualEqual => Object::Boolean(left == right),
+            BangEqual => Object::Boolean(!Self::is_equal(&left, &right)),
+            EqualEqual => Object::Boolean(Self::is_equal(&left, &right)),
             _ => panic!(),
         })
     }
 
     fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Result<Object> {
         // format!("({} {})", expr.operator, expr.right.accept_str(self))
-        let right = right.accept(self)?;
+        let right = self.evaluate(right)?;
 
         match operator.token_type {
             TokenType::Minus => Ok(Object::Number(-Self::try_num(right)?)),
@@ -112,17 +155,26 @@ impl ExprVisitor<Object> for InterpreterVisitor {
         Ok(Object::from(expr))
     }
 
-    fn visit_variable(&self, name: &Token) -> Result<Object> {
-        Ok(self.cactus.get(name, self.curr_env)?.clone())
+    /// Locals are looked up exactly `depth` environments up, anything the resolver didn't find is a global
+    fn visit_variable(&self, name: &Token, depth: &Cell<Option<usize>>) -> Result<Object> {
+        match depth.get() {
+            Some(depth) => Ok(self.cactus.get_at(name, self.curr_env, depth)?.clone()),
+            None => Ok(self.cactus.get(name, self.globals)?.clone()),
+        }
     }
 
-    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> Result<Object> {
+    fn visit_assign_expr(
+        &mut self,
+        name: &Token,
+        value: &Expr,
+        depth: &Cell<Option<usize>>,
+    ) -> Result<Object> {
         let value = self.evaluate(value)?;
-        self.cactus.assign(name, value.clone(), self.curr_env)?;
+        self.assign(name, value.clone(), depth)?;
         Ok(value)
     }
 
-    ///
+    /// Evaluates `and` and `or`, which short-circuit unlike [Expr::Binary]
     fn visit_logical_expr(
         &mut self,
         left: &Expr,
@@ -145,16 +197,179 @@ impl ExprVisitor<Object> for InterpreterVisitor {
         // Otherwise, our only choice is to evaluate right
         self.evaluate(right)
     }
+
+    /// Evaluates the callee and then every argument from left to right, before actually calling
+    fn visit_call_expr(
+        &mut self,
+        callee: &Expr,
+        _paren: &Token,
+        arguments: &[Expr],
+    ) -> Result<Object> {
+        let callee = self.evaluate(callee)?;
+
+        // The callee and every argument stay rooted until the call is over, nothing else is holding on to them
+        let base = self.stack.len();
+        self.root(&callee);
+        let arguments = arguments
+            .iter()
+            .map(|a| {
+                let argument = self.evaluate(a)?;
+                self.root(&argument);
+                Ok(argument)
+            })
+            .collect::<Result<Vec<Object>>>();
+        let result = arguments.and_then(|arguments| self.call(callee, arguments));
+        self.stack.truncate(base);
+        result
+    }
+
+    /// Only the branch that was picked gets evaluated
+    fn visit_ternary_expr(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Result<Object> {
+        if Self::is_truthy(&self.evaluate(condition)?) {
+            self.evaluate(then_branch)
+        } else {
+            self.evaluate(else_branch)
+        }
+    }
+
+    /// `++a` returns the new value, `a++` returns the old one
+    fn visit_update_expr(
+        &mut self,
+        name: &Token,
+        operator: &Token,
+        prefix: bool,
+        depth: &Cell<Option<usize>>,
+    ) -> Result<Object> {
+        let old = Self::try_num(self.visit_variable(name, depth)?)?;
+        let new = match operator.token_type {
+            TokenType::PlusPlus => old + 1.0,
+            _ => old - 1.0,
+        };
+
+        self.assign(name, Object::Number(new), depth)?;
+
+        Ok(Object::Number(if prefix { new } else { old }))
+    }
+
+    fn visit_get_expr(&mut self, object: &Expr, name: &Token) -> Result<Object> {
+        let instance = match self.evaluate(object)? {
+            Object::Instance(instance) => instance,
+            o => return Err(Box::new(InterpreterError::new(ErrorKind::NotAnInstance(o)))),
+        };
+        // Binding a method allocates, so the instance has to stay rooted
+        self.rooted(&Object::Instance(Rc::clone(&instance)), |interpreter| {
+            Instance::get(&instance, name, interpreter)
+        })
+    }
+
+    /// Setting a property that doesn't exist yet just creates it
+    fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr) -> Result<Object> {
+        let instance = match self.evaluate(object)? {
+            Object::Instance(instance) => instance,
+            o => return Err(Box::new(InterpreterError::new(ErrorKind::NotAnInstance(o)))),
+        };
+
+        let value = self.rooted(&Object::Instance(Rc::clone(&instance)), |interpreter| {
+            interpreter.evaluate(value)
+        })?;
+        instance.borrow_mut().set(name, value.clone());
+        Ok(value)
+    }
+
+    /// Every element is evaluated from left to right before the list is allocated
+    fn visit_list_expr(&mut self, _bracket: &Token, elements: &[Expr]) -> Result<Object> {
+        // Nothing but the stack knows about the elements until the list exists
+        let base = self.stack.len();
+        let items = elements
+            .iter()
+            .map(|e| {
+                let item = self.evaluate(e)?;
+                self.root(&item);
+                Ok(item)
+            })
+            .collect::<Result<Vec<Object>>>();
+        let list = items.map(|items| self.alloc_list(items));
+        self.stack.truncate(base);
+        Ok(Object::List(list?))
+    }
+
+    /// Keys and values are evaluated in the order they're written, a key that's repeated keeps its first position
+    fn visit_map_expr(&mut self, _brace: &Token, entries: &[(Expr, Expr)]) -> Result<Object> {
+        // Allocate the map up front, so the entries can go straight into it
+        let map = self.alloc_map(Map::new());
+        let object = Object::Map(Rc::clone(&map));
+        self.rooted(&object, |interpreter| {
+            entries.iter().try_for_each(|(key, value)| {
+                let key = interpreter.evaluate(key)?;
+                let value = interpreter.rooted(&key, |interpreter| interpreter.evaluate(value))?;
+                map.borrow_mut().insert(&key, value)
+            })
+        })?;
+        Ok(object)
+    }
+
+    fn visit_index_expr(
+        &mut self,
+        object: &Expr,
+        _bracket: &Token,
+        index: &Expr,
+    ) -> Result<Object> {
+        let object = self.evaluate(object)?;
+        let index = self.rooted(&object, |interpreter| interpreter.evaluate(index))?;
+        match object {
+            Object::List(list) => list.borrow().get(&index),
+            Object::Map(map) => map.borrow().get(&index),
+            o => Err(Box::new(InterpreterError::new(ErrorKind::NotIndexable(o)))),
+        }
+    }
+
+    /// Like [Expr::Set], the object is evaluated first, then the index, then the value.
+    /// Setting a key that isn't in a map yet adds it
+    fn visit_set_index_expr(
+        &mut self,
+        object: &Expr,
+        _bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<Object> {
+        let object = self.evaluate(object)?;
+        if !matches!(object, Object::List(_) | Object::Map(_)) {
+            return Err(Box::new(InterpreterError::new(ErrorKind::NotIndexable(
+                object,
+            ))));
+        }
+        let (index, value) = self.rooted(&object, |interpreter| {
+            let index = interpreter.evaluate(index)?;
+            let value = interpreter.rooted(&index, |interpreter| interpreter.evaluate(value))?;
+            Ok::<_, Box<dyn std::error::Error>>((index, value))
+        })?;
+        match &object {
+            Object::List(list) => list.borrow_mut().set(&index, value.clone())?,
+            Object::Map(map) => map.borrow_mut().insert(&index, value.clone())?,
+            _ => unreachable!("checked above"),
+        }
+        Ok(value)
+    }
+
+    /// `this` is looked up just like any other local variable, the resolver made sure it exists
+    fn visit_this_expr(&self, keyword: &Token, depth: &Cell<Option<usize>>) -> Result<Object> {
+        self.visit_variable(keyword, depth)
+    }
 }
 
 impl Expr {
-    /// Duplicate method but I can't figure out how to separate into String and Objects atm
-    pub fn accept<T>(&self, visitor: &mut T) -> Result<Object>
+    /// Dispatches to the matching `visit_` method of any [ExprVisitor], e.g. the interpreter or the resolver
+    pub fn accept<T, V>(&self, visitor: &mut V) -> Result<T>
     where
-        T: ExprVisitor<Object>,
+        V: ExprVisitor<T>,
     {
         match self {
-            Expr::Literal(e) => visitor.visit_literal(e),
+            Expr::Literal(e, _) => visitor.visit_literal(e),
             Expr::Grouping { expression } => visitor.visit_grouping(expression),
             Expr::Binary {
                 left,
@@ -162,14 +377,89 @@ impl Expr {
                 right,
             } => visitor.visit_binary(left, operator, right),
             Expr::Unary { operator, right } => visitor.visit_unary(operator, right),
-            Expr::Variable { name } => visitor.visit_variable(name),
+            Expr::Variable { name, depth } => visitor.visit_variable(name, depth),
             Expr::Null => panic!("shouldn't be null expr"),
-            Expr::Assign { name, value } => visitor.visit_assign_expr(name, value),
+            Expr::Assign { name, value, depth } => visitor.visit_assign_expr(name, value, depth),
             Expr::Logical {
                 left,
                 operator,
                 right,
             } => visitor.visit_logical_expr(left, operator, right),
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => visitor.visit_call_expr(callee, paren, arguments),
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => visitor.visit_ternary_expr(condition, then_branch, else_branch),
+            Expr::Update {
+                name,
+                operator,
+                prefix,
+                depth,
+            } => visitor.visit_update_expr(name, operator, *prefix, depth),
+            Expr::Get { object, name } => visitor.visit_get_expr(object, name),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => visitor.visit_set_expr(object, name, value),
+            Expr::This { keyword, depth } => visitor.visit_this_expr(keyword, depth),
+            Expr::List { bracket, elements } => visitor.visit_list_expr(bracket, elements),
+            Expr::Map { brace, entries } => visitor.visit_map_expr(brace, entries),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => visitor.visit_index_expr(object, bracket, index),
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => visitor.visit_set_index_expr(object, bracket, index, value),
+        }
+    }
+}
+
+impl Stmt {
+    /// Dispatches to the matching `visit_` method of any [StatementVisitor]
+    pub fn accept<V>(&self, visitor: &mut V) -> Result<()>
+    where
+        V: StatementVisitor,
+    {
+        match self {
+            Stmt::Expr(e) => visitor.visit_expression_stmt(e),
+            Stmt::Print(e) => visitor.visit_print_stmt(e),
+            Stmt::Var { name, initializer } => visitor.visit_var_stmt(name, initializer),
+            Stmt::Block { statements } => visitor.visit_block(statements),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => visitor.visit_if_stmt(condition, then_branch, else_branch.as_deref()),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => visitor.visit_while_stmt(condition, body, increment.as_ref()),
+            Stmt::Function(declaration) => visitor.visit_function_stmt(declaration),
+            Stmt::Class { name, methods } => visitor.visit_class_stmt(name, methods),
+            Stmt::Return { keyword, value } => visitor.visit_return_stmt(keyword, value),
+            Stmt::Break { keyword } => visitor.visit_break_stmt(keyword),
+            Stmt::Continue { keyword } => visitor.visit_continue_stmt(keyword),
+            Stmt::Import { path, name, .. } => visitor.visit_import_stmt(path, name),
+            Stmt::Throw { keyword, value } => visitor.visit_throw_stmt(keyword, value),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => visitor.visit_try_stmt(body, catch.as_ref(), finally.as_deref()),
         }
     }
 }
@@ -180,9 +470,41 @@ pub trait ExprVisitor<T> {
     fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Result<T>;
     fn visit_grouping(&mut self, expr: &Expr) -> Result<T>;
     fn visit_literal(&self, expr: &Literal) -> Result<T>;
-    fn visit_variable(&self, name: &Token) -> Result<T>;
-    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> Result<T>;
+    fn visit_variable(&self, name: &Token, depth: &Cell<Option<usize>>) -> Result<T>;
+    fn visit_assign_expr(
+        &mut self,
+        name: &Token,
+        value: &Expr,
+        depth: &Cell<Option<usize>>,
+    ) -> Result<T>;
     fn visit_logical_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<T>;
+    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Result<T>;
+    fn visit_ternary_expr(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Result<T>;
+    fn visit_update_expr(
+        &mut self,
+        name: &Token,
+        operator: &Token,
+        prefix: bool,
+        depth: &Cell<Option<usize>>,
+    ) -> Result<T>;
+    fn visit_get_expr(&mut self, object: &Expr, name: &Token) -> Result<T>;
+    fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr) -> Result<T>;
+    fn visit_list_expr(&mut self, bracket: &Token, elements: &[Expr]) -> Result<T>;
+    fn visit_map_expr(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> Result<T>;
+    fn visit_index_expr(&mut self, object: &Expr, bracket: &Token, index: &Expr) -> Result<T>;
+    fn visit_set_index_expr(
+        &mut self,
+        object: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<T>;
+    fn visit_this_expr(&self, keyword: &Token, depth: &Cell<Option<usize>>) -> Result<T>;
 }
 
 /// Statement visitor, which evaluates things like `if {..}` and `while {}` that don't necessarily evaluate into an [Object].
@@ -191,13 +513,32 @@ pub trait StatementVisitor {
     fn visit_expression_stmt(&mut self, stmt: &Expr) -> Result<()>;
     fn visit_print_stmt(&mut self, stmt: &Expr) -> Result<()>;
     fn visit_var_stmt(&mut self, name: &Token, initializer: &Expr) -> Result<()>;
+    fn visit_block(&mut self, statements: &[Stmt]) -> Result<()>;
     fn visit_if_stmt(
         &mut self,
         condition: &Expr,
         then_branch: &Stmt,
         else_branch: Option<&Stmt>,
     ) -> Result<()>;
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> Result<()>;
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+    ) -> Result<()>;
+    fn visit_function_stmt(&mut self, declaration: &Rc<FunctionDecl>) -> Result<()>;
+    fn visit_class_stmt(&mut self, name: &Token, methods: &[Rc<FunctionDecl>]) -> Result<()>;
+    fn visit_return_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<()>;
+    fn visit_break_stmt(&mut self, keyword: &Token) -> Result<()>;
+    fn visit_continue_stmt(&mut self, keyword: &Token) -> Result<()>;
+    fn visit_import_stmt(&mut self, path: &Token, name: &Token) -> Result<()>;
+    fn visit_throw_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<()>;
+    fn visit_try_stmt(
+        &mut self,
+        body: &[Stmt],
+        catch: Option<&(Token, Vec<Stmt>)>,
+        finally: Option<&[Stmt]>,
+    ) -> Result<()>;
 }
 
 impl StatementVisitor for InterpreterVisitor {
@@ -226,12 +567,18 @@ impl StatementVisitor for InterpreterVisitor {
             _ => self.evaluate(initializer)?,
         };
 
-        // dbg!(self.curr_env);
-        self.cactus.define(&name.lexeme, obj, self.curr_env);
+        self.define(name.lexeme, obj, self.curr_env);
 
         Ok(())
     }
 
+    fn visit_block(&mut self, statements: &[Stmt]) -> Result<()> {
+        // Create a new environment for the current block, whose parent is the current environment
+        let env = self.push_env(self.curr_env);
+
+        self.execute_block(statements, env)
+    }
+
     /// Given a `condition` and `left branch` and `right branch`, if the `condition` evaluates to `true`,
     /// execute() `left branch`, otherwise execute `right branch`
     fn visit_if_stmt(
@@ -254,53 +601,381 @@ impl StatementVisitor for InterpreterVisitor {
     }
 
     /// Executes a [Stmt::While]
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> Result<()> {
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+    ) -> Result<()> {
         // Keep in mind that we shouldn't put the `evaluate` function outside of the loop, because otherwise it would always be true or false
         while Self::is_truthy(&self.evaluate(condition)?) {
-            self.execute(body)?;
+            // `break` and `continue` unwind out of the body the same way `return` does
+            if let Err(e) = self.execute(body) {
+                match e.downcast_ref::<InterpreterError>().map(|e| &e.kind) {
+                    Some(ErrorKind::Break) => break,
+                    Some(ErrorKind::Continue) => (),
+                    _ => return Err(e),
+                }
+            }
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
         }
 
         Ok(())
     }
+
+    /// Turns a [Stmt::Function] into an [Object::Function] that remembers where it was declared, and binds it to its name
+    fn visit_function_stmt(&mut self, declaration: &Rc<FunctionDecl>) -> Result<()> {
+        // The function can be called long after the current block is gone, so its environment has to stay alive
+        self.cactus.capture(self.curr_env);
+        let function = Function::new(Rc::clone(declaration), self.curr_env, self.globals, false);
+        self.define(
+            declaration.name.lexeme,
+            Object::Function(function),
+            self.curr_env,
+        );
+        Ok(())
+    }
+
+    /// Every method closes over the environment the class was declared in, same as a function would
+    fn visit_class_stmt(&mut self, name: &Token, methods: &[Rc<FunctionDecl>]) -> Result<()> {
+        self.cactus.capture(self.curr_env);
+        let methods = methods
+            .iter()
+            .map(|m| {
+                let is_initializer = m.name.lexeme == "init";
+                (
+                    m.name.lexeme,
+                    Function::new(Rc::clone(m), self.curr_env, self.globals, is_initializer),
+                )
+            })
+            .collect::<HashMap<Symbol, Function>>();
+
+        let class = Class::new(name.lexeme, methods);
+        self.define(name.lexeme, Object::Class(Rc::new(class)), self.curr_env);
+        Ok(())
+    }
+
+    /// `return` has to jump out of however many blocks and loops we're nested in,
+    /// so it's thrown as an error and caught again by [Function::call]
+    fn visit_return_stmt(&mut self, _keyword: &Token, value: &Expr) -> Result<()> {
+        let value = match value {
+            Expr::Null => Object::Nil,
+            _ => self.evaluate(value)?,
+        };
+
+        Err(Box::new(InterpreterError::new(ErrorKind::Return(value))))
+    }
+
+    fn visit_break_stmt(&mut self, _keyword: &Token) -> Result<()> {
+        Err(Box::new(InterpreterError::new(ErrorKind::Break)))
+    }
+
+    fn visit_continue_stmt(&mut self, _keyword: &Token) -> Result<()> {
+        Err(Box::new(InterpreterError::new(ErrorKind::Continue)))
+    }
+
+    /// Runs the module (unless it already ran), then binds its namespace to `name`
+    fn visit_import_stmt(&mut self, path: &Token, name: &Token) -> Result<()> {
+        let namespace = self.import(path)?;
+        self.define(name.lexeme, namespace, self.curr_env);
+        Ok(())
+    }
+
+    /// Thrown values unwind just like `return` does, until a `catch` picks them up
+    fn visit_throw_stmt(&mut self, _keyword: &Token, value: &Expr) -> Result<()> {
+        let value = self.evaluate(value)?;
+        Err(Box::new(InterpreterError::new(ErrorKind::Thrown(value))))
+    }
+
+    /// The body, handler and cleanup each get their own environment, like any other block.
+    /// `finally` runs no matter how the rest ended, and if it errors itself that error wins
+    fn visit_try_stmt(
+        &mut self,
+        body: &[Stmt],
+        catch: Option<&(Token, Vec<Stmt>)>,
+        finally: Option<&[Stmt]>,
+    ) -> Result<()> {
+        let res = match (self.visit_block(body), catch) {
+            (Err(e), Some((name, handler))) => match exception::catch(self, e) {
+                // The caught value stays rooted until the handler's environment holds on to it
+                Ok(caught) => self.rooted(&caught, |interpreter| {
+                    let env = interpreter.push_env(interpreter.curr_env);
+                    interpreter.define(name.lexeme, caught.clone(), env);
+                    interpreter.execute_block(handler, env)
+                }),
+                Err(e) => Err(e),
+            },
+            (res, _) => res,
+        };
+
+        if let Some(finally) = finally {
+            // Whatever is still unwinding isn't reachable from anywhere else while the cleanup runs
+            let base = self.stack.len();
+            if let Some(value) = exception::unwinding_value(&res).cloned() {
+                self.root(&value);
+            }
+            let cleanup = self.visit_block(finally);
+            self.stack.truncate(base);
+            cleanup?;
+        }
+
+        res
+    }
 }
 
 pub struct InterpreterVisitor {
-    cactus: Cactus,
-    // global_env: usize,
+    pub(crate) cactus: Cactus,
+    globals: usize,
     curr_env: usize,
+    /// Only set when running with `--trace`
+    tracer: Option<Tracer>,
+    heap: Heap,
+    /// Values that are only held by Rust locals right now, e.g. the left side of a binary expression while the right side runs.
+    /// The garbage collector treats them as roots
+    stack: Vec<Object>,
+    /// The environments of every block and call we're currently inside of, except for `curr_env` itself
+    env_stack: Vec<usize>,
+    /// What runtime errors turn into once they're caught
+    error_class: Rc<Class>,
+    /// Natives and other globals the interpreter defines itself, every module gets its own copy of them
+    builtins: Vec<(Symbol, Object)>,
+    /// The file being run, followed by every module it's in the middle of importing
+    files: Vec<PathBuf>,
+    /// The namespace of every module that's been imported, by canonical path
+    modules: HashMap<PathBuf, Object>,
+    /// How many calls are in progress, so runaway recursion fails before the native stack does
+    call_depth: usize,
 }
 
 impl InterpreterVisitor {
     pub fn new() -> Self {
+        Self::with_gc(GcConfig::default())
+    }
+
+    pub fn with_gc(config: GcConfig) -> Self {
         let cactus = Cactus::new();
         // println!("setting curr env to {}", cactus.cur_env);
         let curr_env = cactus.cur_env;
         // let mut tree = Arena {arena: Vec::new()};
         // let idx = tree.push(Environment::new());
 
-        InterpreterVisitor {
+        let mut interpreter = InterpreterVisitor {
             cactus,
-            // global_env: curr_env,
+            globals: curr_env,
             curr_env,
+            tracer: None,
+            heap: Heap::new(config),
+            stack: Vec::new(),
+            env_stack: Vec::new(),
+            error_class: exception::error_class(),
+            builtins: Vec::new(),
+            files: Vec::new(),
+            modules: HashMap::new(),
+            call_depth: 0,
+        };
+
+        // Seconds since the interpreter was created, handy for benchmarking scripts
+        let start = Instant::now();
+        interpreter.define_native("clock", 0, move |_| {
+            Ok(Object::Number(start.elapsed().as_secs_f32()))
+        });
+        let error_class = Object::Class(Rc::clone(&interpreter.error_class));
+        interpreter.define_builtin("Error".into(), error_class);
+        list::define_natives(&mut interpreter);
+        map::define_natives(&mut interpreter);
+
+        interpreter
+    }
+
+    /// Exposes a Rust function to scripts as a global called `name`.
+    /// The interpreter checks that it's called with exactly `arity` arguments before `function` runs
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Object]) -> Result<Object> + 'static,
+    {
+        let native = NativeFunction::new(name, arity, function);
+        self.define_builtin(name.into(), Object::Native(native));
+    }
+
+    /// Same as [Self::define_native], but `function` also gets the interpreter, e.g. to allocate a list with
+    pub fn define_native_with_interpreter<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&mut InterpreterVisitor, &[Object]) -> Result<Object> + 'static,
+    {
+        let native = NativeFunction::with_interpreter(name, arity, function);
+        self.define_builtin(name.into(), Object::Native(native));
+    }
+
+    fn define_builtin(&mut self, name: Symbol, value: Object) {
+        self.cactus.define(name, value.clone(), self.globals);
+        self.builtins.push((name, value));
+    }
+
+    /// Tells the interpreter which file it's running, so `import`s inside of it are resolved relative to it.
+    /// Without one, they're resolved relative to the working directory
+    pub fn set_script(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        self.files = vec![path.canonicalize().unwrap_or(path)];
+    }
+
+    /// What error messages call the file that's running right now: its path relative to the working directory if it's
+    /// inside of it, `<script>` if there's no file at all
+    pub fn script_name(&self) -> String {
+        self.files
+            .last()
+            .map_or_else(|| "<script>".into(), |path| module::source_name(path))
+    }
+
+    /// Starts logging every statement, expression result, define and assign to `tracer`
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// Every variable that code running right now could see, innermost scope first.
+    /// Names shadowed by a closer scope are left out
+    pub fn visible_bindings(&self) -> Vec<(Symbol, Object)> {
+        let mut bindings: Vec<(Symbol, Object)> = Vec::new();
+        let mut env = Some(self.curr_env);
+        while let Some(idx) = env {
+            let mut scope = self
+                .cactus
+                .bindings(idx)
+                .filter(|(name, _)| !bindings.iter().any(|(seen, _)| seen == name))
+                .map(|(name, value)| (name, value.clone()))
+                .collect::<Vec<_>>();
+            scope.sort_by_key(|(name, _)| name.as_str());
+            bindings.extend(scope);
+            env = self.cactus.parent(idx);
         }
+        bindings
     }
-    pub fn accept(&mut self, stmt: &Stmt) -> Result<()> {
-        match stmt {
-            Stmt::Expr(e) => self.visit_expression_stmt(e),
-            Stmt::Print(e) => self.visit_print_stmt(e),
-            Stmt::Var { name, initializer } => self.visit_var_stmt(name, initializer),
-            Stmt::Block { statements } => self.visit_block(statements),
-            Stmt::If {
-                condition,
-                then_branch,
-                else_branch,
-            } => self.visit_if_stmt(condition, then_branch, else_branch.as_deref()),
-            Stmt::While { condition, body } => self.visit_while_stmt(condition, body),
+
+    /// Runs the garbage collector right now, whether or not the heap has grown enough
+    pub fn collect_garbage(&mut self) {
+        self.collect(&[]);
+    }
+
+    /// Everything reachable from the globals, the environments we're currently in,
+    /// the rooted temporaries and `extra_envs` survives
+    fn collect(&mut self, extra_envs: &[usize]) {
+        let mut envs = vec![self.globals, self.curr_env];
+        envs.extend(&self.env_stack);
+        envs.extend(extra_envs);
+        let mut values = self.stack.clone();
+        values.extend(self.modules.values().cloned());
+        let collection = self.heap.collect(&mut self.cactus, envs, values);
+        if let Some(tracer) = &mut self.tracer {
+            tracer.collection(&collection);
+        }
+    }
+
+    /// Every environment is created through here, which is what gives the garbage collector a chance to run
+    pub(crate) fn push_env(&mut self, parent: usize) -> usize {
+        if self.heap.should_collect() {
+            self.collect(&[parent]);
+        }
+        self.heap.track_environment();
+        self.cactus.push_child(parent)
+    }
+
+    /// Same as [Self::push_env], but for instances
+    pub(crate) fn alloc_instance(&mut self, class: Rc<Class>) -> Rc<RefCell<Instance>> {
+        if self.heap.should_collect() {
+            self.stack.push(Object::Class(Rc::clone(&class)));
+            self.collect(&[]);
+            self.stack.pop();
         }
+        self.heap.alloc_instance(class)
+    }
+
+    /// Same as [Self::push_env], but for lists. `items` have to be rooted already
+    pub fn alloc_list(&mut self, items: Vec<Object>) -> Rc<RefCell<List>> {
+        if self.heap.should_collect() {
+            self.collect(&[]);
+        }
+        self.heap.alloc_list(items)
+    }
+
+    /// Same as [Self::push_env], but for maps. Anything already in `map` has to be rooted
+    pub fn alloc_map(&mut self, map: Map) -> Rc<RefCell<Map>> {
+        if self.heap.should_collect() {
+            self.collect(&[]);
+        }
+        self.heap.alloc_map(map)
+    }
+
+    /// Keeps `value` alive through any collection that happens while `f` runs
+    pub(crate) fn rooted<R>(&mut self, value: &Object, f: impl FnOnce(&mut Self) -> R) -> R {
+        let base = self.stack.len();
+        self.root(value);
+        let res = f(self);
+        self.stack.truncate(base);
+        res
+    }
+
+    /// Runs `f` with `globals` as the global environment. The one it replaces has to stay a root,
+    /// since whoever switched to it is still in the middle of using it
+    pub(crate) fn with_globals<R>(&mut self, globals: usize, f: impl FnOnce(&mut Self) -> R) -> R {
+        if globals == self.globals {
+            return f(self);
+        }
+        let enclosing = std::mem::replace(&mut self.globals, globals);
+        self.env_stack.push(enclosing);
+        let res = f(self);
+        self.env_stack.pop();
+        self.globals = enclosing;
+        res
+    }
+
+    /// Pushes `value` onto the root stack if it can point at anything the collector manages,
+    /// it's up to the caller to truncate the stack again
+    fn root(&mut self, value: &Object) {
+        if value.is_managed() {
+            self.stack.push(value.clone());
+        }
+    }
+
+    /// [Cactus::define], but traced
+    pub(crate) fn define(&mut self, name: Symbol, obj: Object, env: usize) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.define(name, &obj, env);
+        }
+        self.cactus.define(name, obj, env);
+    }
+
+    /// Assigns to a variable the resolver found `depth` environments up, or to a global if it didn't find it
+    fn assign(&mut self, name: &Token, obj: Object, depth: &Cell<Option<usize>>) -> Result<()> {
+        let value = self.tracer.is_some().then(|| obj.clone());
+        let env = match depth.get() {
+            Some(depth) => self.cactus.assign_at(name, obj, self.curr_env, depth)?,
+            None => self.cactus.assign(name, obj, self.globals)?,
+        };
+        if let (Some(tracer), Some(value)) = (&mut self.tracer, value) {
+            tracer.assign(name.lexeme, &value, env);
+        }
+        Ok(())
+    }
+
+    pub fn accept(&mut self, stmt: &Stmt) -> Result<()> {
+        stmt.accept(self)
     }
     pub fn execute(&mut self, stmt: &Stmt) -> Result<()> {
-        // println!("[dbg] calling execute()");
-        self.accept(stmt)
+        if let Some(tracer) = &mut self.tracer {
+            tracer.statement(stmt);
+        }
+        self.accept(stmt).map_err(|mut e| {
+            // Errors that didn't come out of an expression, like an uncaught `throw`, point at the statement
+            if let Some(e) = e.downcast_mut::<InterpreterError>() {
+                e.span = e.span.or_else(|| stmt.span());
+            }
+            e
+        })
     }
     pub fn interpret(&mut self, stmts: Vec<Stmt>) -> Result<()> {
         // let value = self.evaluate(expr)?;
@@ -308,7 +983,24 @@ impl InterpreterVisitor {
         stmts.iter().try_for_each(|s| self.execute(s))
     }
     pub fn evaluate(&mut self, expr: &crate::ast::Expr) -> Result<Object> {
-        expr.accept(self)
+        let value = expr.accept(self).map_err(|mut e| {
+            // The innermost expression is the closest we can get to where an error happened
+            if let Some(e) = e.downcast_mut::<InterpreterError>() {
+                e.span = e.span.or_else(|| expr.span());
+            }
+            e
+        })?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.expression(expr, &value);
+        }
+        Ok(value)
+    }
+    /// Equal interned strings are the same allocation, so comparing their pointers is usually enough
+    fn is_equal(left: &Object, right: &Object) -> bool {
+        match (left, right) {
+            (Object::String(l), Object::String(r)) => Rc::ptr_eq(l, r) || l == r,
+            _ => left == right,
+        }
     }
     fn is_truthy(ob: &Object) -> bool {
         match ob {
@@ -322,8 +1014,7 @@ impl InterpreterVisitor {
             Ok(n)
         } else {
             Err(Box::new(InterpreterError::new(ErrorKind::FailedCast(
-                value,
-                Object::Number(0.0),
+                value, "number",
             ))))
         }
     }
@@ -342,31 +1033,63 @@ impl InterpreterVisitor {
     //     }
     // }
 
-    fn visit_block(&mut self, statements: &[Stmt]) -> Result<()> {
+    /// Arity checks and then calls `callee`, which [Self::visit_call_expr] has already rooted
+    fn call(&mut self, callee: Object, arguments: Vec<Object>) -> Result<Object> {
+        let callable: &dyn Callable = match &callee {
+            Object::Function(f) => f,
+            Object::Native(n) => n,
+            Object::Class(c) => c,
+            _ => {
+                return Err(Box::new(InterpreterError::new(ErrorKind::NotCallable(
+                    callee,
+                ))))
+            }
+        };
+
+        if arguments.len() != callable.arity() {
+            return Err(Box::new(InterpreterError::new(ErrorKind::WrongArity(
+                callable.arity(),
+                arguments.len(),
+            ))));
+        }
+
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(Box::new(InterpreterError::new(ErrorKind::StackOverflow)));
+        }
+        self.call_depth += 1;
+        let res = callable.call(self, arguments);
+        self.call_depth -= 1;
+        res
+    }
+
+    /// Executes `statements` inside of `env`, then releases `env`.
+    /// Used by blocks, but also by function calls, whose environment isn't a child of the current one
+    pub fn execute_block(&mut self, statements: &[Stmt], env: usize) -> Result<()> {
         // remember current environment
         let previous = self.curr_env;
-        // Create a new environment for the current block
-        // TODO could probably have push return `Node<ID>`
-        self.curr_env = self.cactus.arena.push(Environment::new());
-        // set current environment's parent to previous
-        let n = self.cactus.arena.get_mut(self.curr_env).unwrap();
-        n.parent = Some(previous);
-
-        // Execute all the statements
-        statements.iter().try_for_each(|s| self.accept(s))?;
+        self.env_stack.push(previous);
+        self.curr_env = env;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.enter();
+        }
 
-        // println!("actually executing statmenet");
+        // Execute all the statements, but hold on to the result instead of `?`ing it,
+        // because the environment has to be reset even if something errored (or returned)
+        let res = statements.iter().try_for_each(|s| self.execute(s));
 
-        // Reset environment
+        if let Some(tracer) = &mut self.tracer {
+            tracer.exit();
+        }
 
-        // Remove latest node from vec. Because of how a parent-pointer tree (or cactus stack works) this probably always pops the child
-        // **probably**
-        self.cactus.arena.pop();
+        // We're done with the environment, but a closure created inside of it might not be
+        if self.cactus.release(env) {
+            self.heap.untrack_environment();
+        }
         // Reset parent
         self.curr_env = previous;
+        self.env_stack.pop();
 
-        // Set environment to self.current
-        Ok(())
+        res
     }
 }
 
@@ -375,3 +1098,733 @@ impl Default for InterpreterVisitor {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs `src` in a fresh interpreter, then reads back the global variable `name`
+    fn global(src: &str, name: &str) -> Object {
+        let mut interpreter = InterpreterVisitor::new();
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+        get(&mut interpreter, name)
+    }
+
+    /// Reads the global variable `name` out of an existing interpreter
+    fn get(interpreter: &mut InterpreterVisitor, name: &str) -> Object {
+        interpreter
+            .evaluate(&Expr::variable(Token::new(
+                TokenType::Identifier,
+                name.into(),
+                Literal::Nil,
+                crate::token::Span::default(),
+            )))
+            .unwrap()
+    }
+
+    /// Like [global], but expects running `src` to fail
+    fn fails(src: &str) -> bool {
+        let mut interpreter = InterpreterVisitor::new();
+        crate::run(src.to_string(), &mut interpreter).is_err()
+    }
+
+    #[test]
+    fn function_call() {
+        let src = "
+            func add(a, b) { return a + b; }
+            var c = add(1, 2);
+        ";
+        assert_eq!(global(src, "c"), Object::Number(3.0));
+    }
+
+    #[test]
+    fn return_without_value() {
+        let src = "
+            func nothing() { return; }
+            func nothing2() { }
+            var a = nothing();
+            var b = nothing2();
+        ";
+        assert_eq!(global(src, "a"), Object::Nil);
+        assert_eq!(global(src, "b"), Object::Nil);
+    }
+
+    #[test]
+    fn return_from_nested_blocks() {
+        let src = "
+            func find(n) {
+                var i = 0;
+                while (true) {
+                    if (i == n) { return i * 10; }
+                    i = i + 1;
+                }
+            }
+            var a = find(4);
+        ";
+        assert_eq!(global(src, "a"), Object::Number(40.0));
+    }
+
+    #[test]
+    fn recursion() {
+        let src = "
+            func fib(n) {
+                if (n < 2) return n;
+                return fib(n - 1) + fib(n - 2);
+            }
+            var a = fib(10);
+        ";
+        assert_eq!(global(src, "a"), Object::Number(55.0));
+    }
+
+    #[test]
+    fn counter_closure() {
+        let src = "
+            func makeCounter() {
+                var i = 0;
+                func count() {
+                    i = i + 1;
+                    return i;
+                }
+                return count;
+            }
+            var counter = makeCounter();
+            counter();
+            counter();
+            var a = counter();
+            var b = makeCounter()();
+        ";
+        assert_eq!(global(src, "a"), Object::Number(3.0));
+        assert_eq!(global(src, "b"), Object::Number(1.0));
+    }
+
+    #[test]
+    fn closure_from_nested_blocks() {
+        let src = "
+            var f;
+            {
+                var outer = 1;
+                {
+                    var inner = 2;
+                    func g() { return outer + inner; }
+                    f = g;
+                }
+            }
+            // Make some new environments that would reuse the old slots
+            { var x = 100; { var y = 200; } }
+            var a = f();
+        ";
+        assert_eq!(global(src, "a"), Object::Number(3.0));
+    }
+
+    #[test]
+    fn closures_from_loops() {
+        let src = "
+            var first;
+            var second;
+            var i = 0;
+            while (i < 2) {
+                var j = i * 10;
+                func get() { return j; }
+                if (i == 0) first = get; else second = get;
+                i = i + 1;
+            }
+            var a = first();
+            var b = second();
+        ";
+        assert_eq!(global(src, "a"), Object::Number(0.0));
+        assert_eq!(global(src, "b"), Object::Number(10.0));
+    }
+
+    #[test]
+    fn closures_share_environment() {
+        let src = "
+            var get;
+            var set;
+            {
+                var value = 1;
+                func g() { return value; }
+                func s(v) { value = v; }
+                get = g;
+                set = s;
+            }
+            set(5);
+            var a = get();
+        ";
+        assert_eq!(global(src, "a"), Object::Number(5.0));
+    }
+
+    #[test]
+    fn uncaptured_environments_are_released() {
+        let mut interpreter = InterpreterVisitor::new();
+        let src = "
+            func add(a, b) { { var c = a + b; } return a + b; }
+            var i = 0;
+            while (i < 10) { var x = add(i, 1); i = i + 1; }
+        ";
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+        // only the global environment should be left
+        assert_eq!(interpreter.cactus.arena.len(), 1);
+    }
+
+    #[test]
+    fn closures_bind_statically() {
+        let src = "
+            var a = \"global\";
+            var first;
+            var second;
+            {
+                func show() { return a; }
+                first = show();
+                var a = \"block\";
+                second = show();
+            }
+        ";
+        assert_eq!(global(src, "first"), Object::String("global".into()));
+        assert_eq!(global(src, "second"), Object::String("global".into()));
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let src = "
+            var skipped = \"\";
+            for (var i = 0; i < 10; i++) {
+                if (i == 2) continue;
+                if (i == 5) break;
+                skipped = skipped + i;
+            }
+            var count = 0;
+            while (true) {
+                count++;
+                { var deep = count; if (deep < 3) continue; }
+                break;
+            }
+            var pairs = 0;
+            for (var a = 0; a < 3; a++) {
+                for (var b = 0; b < 3; b++) {
+                    if (b == a) break;
+                    pairs++;
+                }
+            }
+            func firstOver(limit) {
+                for (var n = 0; ; n++) {
+                    while (true) break;
+                    if (n * n > limit) return n;
+                }
+            }
+            var over = firstOver(10);
+        ";
+        let mut interpreter = InterpreterVisitor::new();
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+        // `continue` still runs the increment of a `for` loop, otherwise this would never finish
+        assert_eq!(
+            get(&mut interpreter, "skipped"),
+            Object::String("0134".into())
+        );
+        assert_eq!(get(&mut interpreter, "count"), Object::Number(3.0));
+        assert_eq!(get(&mut interpreter, "pairs"), Object::Number(3.0));
+        assert_eq!(get(&mut interpreter, "over"), Object::Number(4.0));
+        // every block we broke out of gave back its environment
+        assert_eq!(interpreter.cactus.arena.len(), 1);
+    }
+
+    #[test]
+    fn break_outside_of_loop() {
+        assert!(fails("break;"));
+        assert!(fails("continue;"));
+        assert!(fails("if (true) { break; }"));
+        // a function body starts over, even if it's declared inside of a loop
+        assert!(fails("while (true) { func f() { break; } }"));
+        assert!(fails("for (;;) { break }"));
+    }
+
+    #[test]
+    fn exceptions() {
+        let src = "
+            var log = \"\";
+            try { throw \"a\"; } catch (e) { log = log + e; } finally { log = log + \"b\"; }
+            func leave() {
+                try { return \"c\"; } finally { log = log + \"d\"; }
+            }
+            var left = leave();
+            log = log + left;
+            for (var i = 0; i < 2; i++) {
+                try { if (i == 0) continue; break; } finally { log = log + i; }
+            }
+            try {
+                try { throw \"e\"; } finally { log = log + \"f\"; }
+            } catch (e) {
+                log = log + e;
+            }
+            try {
+                try { throw 1; } catch (e) { throw e + 1; }
+            } catch (e) {
+                log = log + e;
+            }
+
+            var kind;
+            var message;
+            var line;
+            try {
+                var x = 1;
+                x = x / 0;
+            } catch (e) {
+                kind = e.kind;
+                message = e.message;
+                line = e.line;
+            }
+            var undefined;
+            try { nope; } catch (e) { undefined = e.kind; }
+            var native;
+            try { pop([]); } catch (e) { native = e.kind; }
+        ";
+        let mut interpreter = InterpreterVisitor::new();
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+        assert_eq!(
+            get(&mut interpreter, "log"),
+            Object::String("abdc01fe2".into())
+        );
+        assert_eq!(
+            get(&mut interpreter, "kind"),
+            Object::String("DivideByZero".into())
+        );
+        assert_eq!(
+            get(&mut interpreter, "message"),
+            Object::String("attempt to divide 1 by 0".into())
+        );
+        assert_eq!(get(&mut interpreter, "line"), Object::Number(28.0));
+        assert_eq!(
+            get(&mut interpreter, "undefined"),
+            Object::String("UndefinedVariable".into())
+        );
+        assert_eq!(
+            get(&mut interpreter, "native"),
+            Object::String("EmptyList".into())
+        );
+        assert_eq!(interpreter.cactus.arena.len(), 1);
+    }
+
+    #[test]
+    fn failed_casts() {
+        let error = |src: &str| {
+            let e = crate::run(src.into(), &mut InterpreterVisitor::new()).unwrap_err();
+            // the VM says the same thing, if not at the same place
+            let vm = crate::run_vm(src.into(), "<script>", &mut crate::vm::Vm::new()).unwrap_err();
+            let message = |e: &crate::Error| e.to_string().split_once(": ").unwrap().1.to_string();
+            assert_eq!(message(&e), message(&vm));
+            e.to_string()
+        };
+        assert_eq!(
+            error("print 1 < \"x1\";"),
+            "<script>:1:7: failed to cast x1 to number"
+        );
+        assert_eq!(
+            error("print nil + 2;"),
+            "<script>:1:7: failed to cast 2 to string"
+        );
+        let e = crate::run("pop(5);".into(), &mut InterpreterVisitor::new()).unwrap_err();
+        assert_eq!(e.to_string(), "<script>:1:1: failed to cast 5 to list");
+    }
+
+    #[test]
+    fn stack_overflow() {
+        // test threads get less stack than the main thread does, which the limit is made for
+        let run = || {
+            let src = "
+                func forever(n) { return forever(n + 1); }
+                var kind;
+                try { forever(0); } catch (e) { kind = e.kind; }
+                func deep(n) { if (n == 0) return 0; return 1 + deep(n - 1); }
+                var depth = deep(255);
+            ";
+            let mut interpreter = InterpreterVisitor::new();
+            crate::run(src.to_string(), &mut interpreter).unwrap();
+            assert_eq!(
+                get(&mut interpreter, "kind"),
+                Object::String("StackOverflow".into())
+            );
+            assert_eq!(get(&mut interpreter, "depth"), Object::Number(255.0));
+            assert_eq!(interpreter.cactus.arena.len(), 1);
+            let e = crate::run("forever(0);".into(), &mut interpreter).unwrap_err();
+            // it points at the call that went one too deep
+            assert_eq!(e.to_string(), "<script>:2:42: stack overflow");
+        };
+        std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn uncaught_exceptions() {
+        let mut interpreter = InterpreterVisitor::new();
+        let e = crate::run("\nthrow \"up\";".into(), &mut interpreter).unwrap_err();
+        assert_eq!(e.to_string(), "<script>:2:1: uncaught exception up");
+        // runtime errors point at the innermost expression they came out of
+        let e = crate::run("var a = 1;\nprint a + (2 / 0);".into(), &mut interpreter).unwrap_err();
+        assert_eq!(e.to_string(), "<script>:2:12: attempt to divide 2 by 0");
+        // a `finally` doesn't stop the error, and an error in a `catch` isn't caught by it
+        assert!(fails("try { throw 1; } finally {}"));
+        assert!(fails("try { throw 1; } catch (e) { throw e; }"));
+        assert!(fails("try { 1 / 0; } catch (e) { 1 / 0; }"));
+        assert!(fails("try {}"));
+        assert!(fails("try {} catch {}"));
+    }
+
+    #[test]
+    fn class_fields_and_methods() {
+        let src = "
+            class Point {
+                init(x, y) {
+                    this.x = x;
+                    this.y = y;
+                }
+                sum() { return this.x + this.y; }
+            }
+            var p = Point(1, 2);
+            p.y = 10;
+            var a = p.sum();
+            var b = p.x;
+        ";
+        assert_eq!(global(src, "a"), Object::Number(11.0));
+        assert_eq!(global(src, "b"), Object::Number(1.0));
+    }
+
+    #[test]
+    fn bound_methods_remember_this() {
+        let src = "
+            class Counter {
+                init() { this.n = 0; }
+                inc() { this.n = this.n + 1; return this.n; }
+            }
+            var c = Counter();
+            var inc = c.inc;
+            inc();
+            inc();
+            var a = c.n;
+        ";
+        assert_eq!(global(src, "a"), Object::Number(2.0));
+    }
+
+    #[test]
+    fn instances_are_references() {
+        let src = "
+            class Box {}
+            var a = Box();
+            var b = a;
+            b.value = 3;
+            var c = a.value;
+            var same = a == b;
+            var different = a == Box();
+        ";
+        assert_eq!(global(src, "c"), Object::Number(3.0));
+        assert_eq!(global(src, "same"), Object::Boolean(true));
+        assert_eq!(global(src, "different"), Object::Boolean(false));
+    }
+
+    #[test]
+    fn initializer_returns_this() {
+        let src = "
+            class A {
+                init() { this.v = 1; return; }
+            }
+            var a = A();
+            var b = a.init();
+            var same = a == b;
+        ";
+        assert_eq!(global(src, "same"), Object::Boolean(true));
+    }
+
+    #[test]
+    fn class_errors() {
+        assert!(fails("class A {} A().missing;"));
+        assert!(fails("var a = 1; a.b = 2;"));
+        assert!(fails("class A { init(a) {} } A();"));
+    }
+
+    #[test]
+    fn ternary() {
+        let src = "
+            var calls = 0;
+            func side() { calls = calls + 1; return 1; }
+            var a = true ? 1 : side();
+            var b = false ? side() : 2;
+            var c = false ? 1 : true ? 2 : 3;
+            var d = 1 < 2 or false ? \"yes\" : \"no\";
+        ";
+        assert_eq!(global(src, "a"), Object::Number(1.0));
+        assert_eq!(global(src, "b"), Object::Number(2.0));
+        assert_eq!(global(src, "c"), Object::Number(2.0));
+        assert_eq!(global(src, "d"), Object::String("yes".into()));
+        assert_eq!(global(src, "calls"), Object::Number(0.0));
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let src = "
+            var a = 10;
+            a += 5;
+            a -= 3;
+            a *= 2;
+            a /= 4;
+            var s = \"a\";
+            s += \"b\";
+            var b;
+            { var local = 1; local += 1; b = local; }
+        ";
+        assert_eq!(global(src, "a"), Object::Number(6.0));
+        assert_eq!(global(src, "s"), Object::String("ab".into()));
+        assert_eq!(global(src, "b"), Object::Number(2.0));
+    }
+
+    #[test]
+    fn increment_and_decrement() {
+        let src = "
+            var i = 0;
+            var a = i++;
+            var b = ++i;
+            var c = i--;
+            var d = --i;
+            var sum = 0;
+            for (var j = 0; j < 4; j++) { sum += j; }
+        ";
+        assert_eq!(global(src, "a"), Object::Number(0.0));
+        assert_eq!(global(src, "b"), Object::Number(2.0));
+        assert_eq!(global(src, "c"), Object::Number(2.0));
+        assert_eq!(global(src, "d"), Object::Number(0.0));
+        assert_eq!(global(src, "i"), Object::Number(0.0));
+        assert_eq!(global(src, "sum"), Object::Number(6.0));
+    }
+
+    #[test]
+    fn invalid_update_targets() {
+        assert!(fails("1++;"));
+        assert!(fails("var a = 1; (a) += 1;"));
+        assert!(fails("var a = \"s\"; a++;"));
+    }
+
+    #[test]
+    fn native_functions() {
+        let mut interpreter = InterpreterVisitor::new();
+        interpreter.define_native("add", 2, |args| {
+            Ok(Object::Number(args[0].as_number()? + args[1].as_number()?))
+        });
+        interpreter.define_native("shout", 1, |args| {
+            Ok(Object::String(args[0].as_str()?.to_uppercase().into()))
+        });
+        let src = "
+            var a = add(1, 2);
+            var b = shout(\"hi\");
+            var c = clock();
+        ";
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+
+        assert_eq!(get(&mut interpreter, "a"), Object::Number(3.0));
+        assert_eq!(get(&mut interpreter, "b"), Object::String("HI".into()));
+        assert!(get(&mut interpreter, "c").as_number().is_ok());
+
+        // wrong arity and wrong argument types are both errors
+        assert!(crate::run("add(1);".to_string(), &mut interpreter).is_err());
+        assert!(crate::run("add(1, \"2\");".to_string(), &mut interpreter).is_err());
+    }
+
+    #[test]
+    fn string_equality() {
+        let src = "
+            var a = \"same\";
+            var b = \"same\";
+            var c = \"sa\" + \"me\";
+            var literals = a == b;
+            var built = a == c;
+            var different = a != \"other\";
+        ";
+        let mut interpreter = InterpreterVisitor::new();
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+        let (Object::String(a), Object::String(b), Object::String(c)) = (
+            get(&mut interpreter, "a"),
+            get(&mut interpreter, "b"),
+            get(&mut interpreter, "c"),
+        ) else {
+            panic!("expected strings")
+        };
+        // literals are interned, strings built at runtime aren't
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(!Rc::ptr_eq(&a, &c));
+        for name in ["literals", "built", "different"] {
+            assert_eq!(get(&mut interpreter, name), Object::Boolean(true));
+        }
+    }
+
+    #[test]
+    fn string_interpolation() {
+        let src = "
+            var x = 2;
+            var xs = [1, 2];
+            var simple = \"x is ${x}\";
+            var grouped = \"${x + 1}${x}\";
+            var nested = \"<${\"[${xs[0]} \\\"${xs}\\\"]\"}>\";
+            var mixed = \"${ {\"k\": \"v\"}[\"k\"] }\\t\\${x}\";
+        ";
+        let mut interpreter = InterpreterVisitor::new();
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+        assert_eq!(
+            get(&mut interpreter, "simple"),
+            Object::String("x is 2".into())
+        );
+        assert_eq!(
+            get(&mut interpreter, "grouped"),
+            Object::String("32".into())
+        );
+        assert_eq!(
+            get(&mut interpreter, "nested"),
+            Object::String("<[1 \"[1, 2]\"]>".into())
+        );
+        assert_eq!(
+            get(&mut interpreter, "mixed"),
+            Object::String("v\t${x}".into())
+        );
+    }
+
+    #[test]
+    fn lists() {
+        let src = "
+            var xs = [1, 2, 1 + 2];
+            var ys = xs;
+            push(ys, 4);
+            xs[0] = \"a\";
+            var first = ys[0];
+            var length = len(xs);
+            var last = pop(xs);
+            var nested = [[1], []];
+            nested[1] = nested;
+            var empty = [];
+        ";
+        let mut interpreter = InterpreterVisitor::new();
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+
+        // both names point at the same list
+        assert_eq!(get(&mut interpreter, "xs"), get(&mut interpreter, "ys"));
+        assert_eq!(get(&mut interpreter, "xs").to_string(), "[a, 2, 3]");
+        assert_eq!(get(&mut interpreter, "first"), Object::String("a".into()));
+        assert_eq!(get(&mut interpreter, "length"), Object::Number(4.0));
+        assert_eq!(get(&mut interpreter, "last"), Object::Number(4.0));
+        assert_eq!(get(&mut interpreter, "nested").to_string(), "[[1], [...]]");
+        assert_eq!(get(&mut interpreter, "empty").to_string(), "[]");
+        // lists are compared by identity, not by their contents
+        assert!(crate::run("var same = [] == [];".into(), &mut interpreter).is_ok());
+        assert_eq!(get(&mut interpreter, "same"), Object::Boolean(false));
+    }
+
+    #[test]
+    fn list_errors() {
+        assert!(fails("var xs = [1]; xs[1];"));
+        assert!(fails("var xs = [1]; xs[-1];"));
+        assert!(fails("var xs = [1]; xs[0.5];"));
+        assert!(fails("var xs = [1]; xs[\"0\"];"));
+        assert!(fails("var xs = [1]; xs[1] = 2;"));
+        assert!(fails("var a = 1; a[0];"));
+        assert!(fails("var a = 1; a[0] = 1;"));
+        assert!(fails("pop([]);"));
+        assert!(fails("push(1, 2);"));
+    }
+
+    #[test]
+    fn maps() {
+        let src = "
+            var m = {\"b\": 1, \"a\": 2, 3: [], true: nil};
+            var alias = m;
+            alias[\"c\"] = 3;
+            m[\"b\"] = 10;
+            m[-0] = \"zero\";
+            var b = m[\"b\"];
+            var zero = m[0];
+            var found = has(m, \"a\");
+            var removed = remove(m, \"a\");
+            var missing = has(m, \"a\");
+            var length = len(m);
+            var order = keys(m);
+            var vals = values(m);
+            var empty = {};
+            {\"statement\": 1};
+            { var block = 1; }
+        ";
+        let mut interpreter = InterpreterVisitor::new();
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+
+        assert_eq!(get(&mut interpreter, "m"), get(&mut interpreter, "alias"));
+        assert_eq!(get(&mut interpreter, "b"), Object::Number(10.0));
+        // -0 and 0 are ==, so they're the same key too
+        assert_eq!(get(&mut interpreter, "zero"), Object::String("zero".into()));
+        assert_eq!(get(&mut interpreter, "found"), Object::Boolean(true));
+        assert_eq!(get(&mut interpreter, "removed"), Object::Number(2.0));
+        assert_eq!(get(&mut interpreter, "missing"), Object::Boolean(false));
+        assert_eq!(get(&mut interpreter, "length"), Object::Number(5.0));
+        // overwriting a key keeps its spot, new keys go at the end
+        assert_eq!(
+            get(&mut interpreter, "order").to_string(),
+            "[b, 3, true, c, 0]"
+        );
+        assert_eq!(
+            get(&mut interpreter, "vals").to_string(),
+            "[10, [], null, 3, zero]"
+        );
+        assert_eq!(
+            get(&mut interpreter, "m").to_string(),
+            "{b: 10, 3: [], true: null, c: 3, 0: zero}"
+        );
+        assert_eq!(get(&mut interpreter, "empty").to_string(), "{}");
+    }
+
+    #[test]
+    fn map_errors() {
+        assert!(fails("var m = {}; m[\"a\"];"));
+        assert!(fails("var m = {}; remove(m, 1);"));
+        assert!(fails("var m = {[]: 1};"));
+        assert!(fails("var m = {}; m[m] = 1;"));
+        assert!(fails("class A {} var m = {}; m[A()] = 1;"));
+        assert!(fails("var m = {}; m[0 / 0] = 1;"));
+        assert!(fails("has([], 1);"));
+        assert!(fails("var m = {1: 2 3: 4};"));
+    }
+
+    #[test]
+    fn call_errors() {
+        assert!(fails("func f(a) {} f();"));
+        assert!(fails("func f(a) {} f(1, 2);"));
+        assert!(fails("var a = 1; a();"));
+        assert!(fails("return 1;"));
+    }
+
+    /// Hands out a copy of everything written to it, so a test can read back a trace
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace() {
+        let out = Shared::default();
+        let mut interpreter = InterpreterVisitor::new();
+        interpreter.set_tracer(Tracer::new(Box::new(out.clone())));
+        let src = "var a = 1;\n{\n  var b = a + 2;\n  a = b;\n}";
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+
+        let trace = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let lines = trace.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "[line 1] var a");
+        assert!(lines.contains(&"  define a = 1 in env 0"));
+        assert!(lines.contains(&"  [line 3] var b"));
+        assert!(lines.contains(&"    [line 3] binary => 3"));
+        assert!(lines.contains(&"    define b = 3 in env 1"));
+        assert!(lines.contains(&"    assign a = 3 in env 0"));
+    }
+}
@@ -0,0 +1,285 @@
+use std::{collections::HashMap, path::Path, rc::Rc};
+
+use crate::token::{Literal, Token};
+
+use super::{
+    class::Class,
+    error::{ErrorKind, InterpreterError},
+    InterpreterVisitor, Object, Result,
+};
+
+fn import_error(kind: ErrorKind) -> Box<dyn std::error::Error> {
+    Box::new(InterpreterError::new(kind))
+}
+
+/// How a module shows up in an import cycle error
+fn display_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+/// How a module shows up in spans, see [InterpreterVisitor::script_name]
+pub(super) fn source_name(path: &Path) -> String {
+    let relative = std::env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf));
+    relative.as_deref().unwrap_or(path).display().to_string()
+}
+
+impl InterpreterVisitor {
+    /// Runs the module `path` points at in a global environment of its own, and hands back its namespace:
+    /// an instance with a field for every global the module defined.
+    /// Each module only ever runs once, importing it again hands back the same namespace
+    pub(crate) fn import(&mut self, path: &Token) -> Result<Object> {
+        let Literal::String(file) = &path.literal else {
+            unreachable!("the parser only lets string tokens be import paths")
+        };
+        let file = file.to_string();
+
+        // Paths are relative to the file doing the importing
+        let base = match self.files.last().and_then(|f| f.parent()) {
+            Some(dir) => dir.to_path_buf(),
+            None => std::env::current_dir()?,
+        };
+        let canonical = base
+            .join(&file)
+            .canonicalize()
+            .map_err(|e| import_error(ErrorKind::ImportFailed(file.clone(), Box::new(e.into()))))?;
+
+        if let Some(start) = self.files.iter().position(|f| *f == canonical) {
+            let cycle = self.files[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|f| display_name(f))
+                .collect();
+            return Err(import_error(ErrorKind::ImportCycle(cycle)));
+        }
+        if let Some(namespace) = self.modules.get(&canonical) {
+            return Ok(namespace.clone());
+        }
+
+        let statements = std::fs::read_to_string(&canonical)
+            .map_err(crate::Error::from)
+            .and_then(|src| crate::parse(src, &source_name(&canonical)))
+            .map_err(|e| import_error(ErrorKind::ImportFailed(file, Box::new(e))))?;
+
+        let env = self.push_global_env();
+        self.files.push(canonical.clone());
+        self.env_stack.push(self.curr_env);
+        let enclosing = std::mem::replace(&mut self.curr_env, env);
+
+        let res: Result<Object> = self.with_globals(env, |interpreter| {
+            statements.iter().try_for_each(|s| interpreter.execute(s))?;
+            let name = canonical.file_stem().unwrap_or_default().to_string_lossy();
+            Ok(interpreter.namespace(env, &name))
+        });
+
+        self.curr_env = enclosing;
+        self.env_stack.pop();
+        self.files.pop();
+
+        let namespace = res?;
+        self.modules.insert(canonical, namespace.clone());
+        Ok(namespace)
+    }
+
+    /// Runs the file at `path` right in the current environment, like it was typed in, e.g. by the REPL's `:load`.
+    /// Its imports are relative to it, not to whatever's running now
+    pub fn load(&mut self, path: &Path) -> std::result::Result<(), crate::Error> {
+        let read = |e| crate::Error::Read(path.display().to_string(), e);
+        let canonical = path.canonicalize().map_err(read)?;
+        let statements = crate::parse(
+            std::fs::read_to_string(&canonical).map_err(read)?,
+            &source_name(&canonical),
+        )?;
+
+        self.files.push(canonical);
+        let res = statements.iter().try_for_each(|s| self.execute(s));
+        self.files.pop();
+        Ok(res?)
+    }
+
+    /// Same as [Self::push_env], but for a module's globals, which start out with nothing but the builtins
+    fn push_global_env(&mut self) -> usize {
+        if self.heap.should_collect() {
+            self.collect(&[]);
+        }
+        self.heap.track_environment();
+        let env = self.cactus.push_root();
+        for (name, value) in self.builtins.clone() {
+            self.cactus.define(name, value, env);
+        }
+        env
+    }
+
+    /// Bundles up everything the module defined in `env` that isn't one of the builtins it started out with.
+    /// `env` has to be rooted, since that's what keeps the exports alive while the namespace is allocated
+    fn namespace(&mut self, env: usize, name: &str) -> Object {
+        let exports = self
+            .cactus
+            .bindings(env)
+            .filter(|(name, value)| !self.builtins.iter().any(|b| (b.0, &b.1) == (*name, *value)))
+            .map(|(name, value)| (name, value.clone()))
+            .collect::<Vec<_>>();
+
+        let class = Rc::new(Class::new(name.into(), HashMap::new()));
+        let namespace = self.alloc_instance(class);
+        for (name, value) in exports {
+            namespace.borrow_mut().insert(name, value);
+        }
+        Object::Instance(namespace)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use crate::interpreter::heap::GcConfig;
+
+    use super::*;
+
+    /// Writes every `(path, source)` into a fresh directory, and hands back where `main.lox` ended up
+    fn write(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nenia-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, src) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, src).unwrap();
+        }
+        dir.join("main.lox")
+    }
+
+    fn run(
+        main: &PathBuf,
+        config: GcConfig,
+    ) -> (InterpreterVisitor, std::result::Result<(), crate::Error>) {
+        let mut interpreter = InterpreterVisitor::with_gc(config);
+        interpreter.set_script(main);
+        let res = crate::run(fs::read_to_string(main).unwrap(), &mut interpreter);
+        (interpreter, res)
+    }
+
+    fn get(interpreter: &mut InterpreterVisitor, name: &str) -> String {
+        let src = format!("var result = {};", name);
+        crate::run(src, interpreter).unwrap();
+        let token = Token::new(
+            crate::token::TokenType::Identifier,
+            "result".into(),
+            Literal::Nil,
+            crate::token::Span::default(),
+        );
+        interpreter
+            .cactus
+            .get(&token, interpreter.globals)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn imports() {
+        let main = write(
+            "imports",
+            &[
+                (
+                    "main.lox",
+                    "
+                    import \"lib/shapes.lox\";
+                    import \"lib/shapes.lox\" as again;
+                    var helper = \"main\";
+                    var area = shapes.Square(3).area();
+                    var which = shapes.which();
+                    var same = shapes == again;
+                    shapes.bump();
+                    again.bump();
+                    var count = shapes.count();
+                    ",
+                ),
+                (
+                    "lib/shapes.lox",
+                    "
+                    import \"counter.lox\";
+                    var helper = \"shapes\";
+                    func which() { return helper; }
+                    class Square {
+                        init(side) { this.side = side; }
+                        area() { return this.side * this.side; }
+                    }
+                    var bump = counter.bump;
+                    var count = counter.get;
+                    ",
+                ),
+                (
+                    "lib/counter.lox",
+                    "var n = 0; func bump() { n++; } func get() { return n; }",
+                ),
+            ],
+        );
+        let stress = GcConfig {
+            stress: true,
+            ..GcConfig::default()
+        };
+        for config in [GcConfig::default(), stress] {
+            let (mut interpreter, res) = run(&main, config);
+            res.unwrap();
+            assert_eq!(get(&mut interpreter, "area"), "9");
+            // a module's functions see its own globals, not the importer's
+            assert_eq!(get(&mut interpreter, "which"), "shapes");
+            // both imports share the one namespace, so the module only ran once
+            assert_eq!(get(&mut interpreter, "same"), "true");
+            assert_eq!(get(&mut interpreter, "count"), "2");
+            assert_eq!(get(&mut interpreter, "shapes"), "shapes instance");
+            // the builtins every module starts out with aren't exported
+            assert!(crate::run("shapes.clock;".into(), &mut interpreter).is_err());
+        }
+    }
+
+    #[test]
+    fn import_errors() {
+        let main = write(
+            "cycle",
+            &[
+                ("main.lox", "import \"a.lox\";"),
+                ("a.lox", "import \"b.lox\";"),
+                ("b.lox", "import \"a.lox\";"),
+            ],
+        );
+        let e = run(&main, GcConfig::default()).1.unwrap_err().to_string();
+        // the error points at the import in `b.lox` that closed the cycle
+        assert!(
+            e.ends_with("b.lox:1:1: import cycle: a.lox -> b.lox -> a.lox"),
+            "{}",
+            e
+        );
+
+        let main = write(
+            "broken",
+            &[
+                ("main.lox", "import \"self.lox\";"),
+                ("self.lox", "import \"main.lox\";"),
+            ],
+        );
+        assert!(run(&main, GcConfig::default()).1.is_err());
+
+        for src in [
+            "import \"missing.lox\";",
+            "import \"bad.lox\";",
+            "import \"throws.lox\";",
+            "import \"not-a-name.lox\";",
+            "import \"throws.lox\" as;",
+        ] {
+            let main = write(
+                "errors",
+                &[
+                    ("main.lox", src),
+                    ("bad.lox", "var = 1;"),
+                    ("throws.lox", "throw 1;"),
+                ],
+            );
+            assert!(run(&main, GcConfig::default()).1.is_err(), "{}", src);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use super::{
+    callable::Callable,
+    error::{ErrorKind, InterpreterError},
+    list::List,
+    map::Map,
+    InterpreterVisitor, Object, Result,
+};
+
+/// The Rust side of a native function, it gets the already evaluated (and arity checked) arguments
+pub type NativeFn = dyn Fn(&[Object]) -> Result<Object>;
+
+/// Same as [NativeFn], but also gets the interpreter, for natives that need to allocate, e.g. one that returns a new list
+pub type InterpreterNativeFn = dyn Fn(&mut InterpreterVisitor, &[Object]) -> Result<Object>;
+
+/// A function implemented in Rust instead of nenia, e.g. `clock()`
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    function: Rc<InterpreterNativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&[Object]) -> Result<Object> + 'static,
+    {
+        Self::with_interpreter(name, arity, move |_, args| function(args))
+    }
+
+    pub fn with_interpreter<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&mut InterpreterVisitor, &[Object]) -> Result<Object> + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            arity,
+            function: Rc::new(function),
+        }
+    }
+}
+
+/// Natives are only equal to themselves, closures can't be compared
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeFunction({}/{})", self.name, self.arity)
+    }
+}
+
+impl Callable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut InterpreterVisitor, arguments: Vec<Object>) -> Result<Object> {
+        (self.function)(interpreter, &arguments)
+    }
+}
+
+/// Typed extraction helpers, mostly meant for native functions picking apart their arguments
+impl Object {
+    pub fn as_number(&self) -> Result<f32> {
+        match self {
+            Object::Number(n) => Ok(*n),
+            _ => Err(Box::new(InterpreterError::new(ErrorKind::FailedCast(
+                self.clone(),
+                "number",
+            )))),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&Rc<RefCell<List>>> {
+        match self {
+            Object::List(list) => Ok(list),
+            _ => Err(Box::new(InterpreterError::new(ErrorKind::FailedCast(
+                self.clone(),
+                "list",
+            )))),
+        }
+    }
+
+    pub fn as_map(&self) -> Result<&Rc<RefCell<Map>>> {
+        match self {
+            Object::Map(map) => Ok(map),
+            _ => Err(Box::new(InterpreterError::new(ErrorKind::FailedCast(
+                self.clone(),
+                "map",
+            )))),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            Object::String(s) => Ok(s.as_ref()),
+            _ => Err(Box::new(InterpreterError::new(ErrorKind::FailedCast(
+                self.clone(),
+                "string",
+            )))),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool> {
+        match self {
+            Object::Boolean(b) => Ok(*b),
+            _ => Err(Box::new(InterpreterError::new(ErrorKind::FailedCast(
+                self.clone(),
+                "boolean",
+            )))),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::{
+    ast::{Expr, Stmt},
+    interner::Symbol,
+};
+
+use super::{heap::Collection, Object};
+
+/// Writes down everything the interpreter does while `--trace` is on.
+/// Every line is indented by how many blocks (or function bodies) deep the interpreter currently is
+pub struct Tracer {
+    out: Box<dyn Write>,
+    depth: usize,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Tracer { out, depth: 0 }
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(Box::new(io::stderr()))
+    }
+
+    /// Truncates `path` if it already exists
+    pub fn file(path: &str) -> io::Result<Self> {
+        Ok(Self::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    /// Called by [super::InterpreterVisitor::execute_block] right before running a block's statements
+    pub fn enter(&mut self) {
+        self.depth += 1;
+    }
+
+    pub fn exit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    pub fn statement(&mut self, stmt: &Stmt) {
+        let kind = match stmt {
+            Stmt::Expr(_) => "expr".to_string(),
+            Stmt::Print(_) => "print".to_string(),
+            Stmt::Var { name, .. } => format!("var {}", name.lexeme),
+            Stmt::Block { .. } => "block".to_string(),
+            Stmt::If { .. } => "if".to_string(),
+            Stmt::While { .. } => "while".to_string(),
+            Stmt::Function(declaration) => format!("func {}", declaration.name.lexeme),
+            Stmt::Class { name, .. } => format!("class {}", name.lexeme),
+            Stmt::Return { .. } => "return".to_string(),
+            Stmt::Break { .. } => "break".to_string(),
+            Stmt::Continue { .. } => "continue".to_string(),
+            Stmt::Import { name, .. } => format!("import {}", name.lexeme),
+            Stmt::Throw { .. } => "throw".to_string(),
+            Stmt::Try { .. } => "try".to_string(),
+        };
+        self.write(format_args!("[{}] {}", Self::line(stmt.line()), kind));
+    }
+
+    /// Expressions are logged once they're done, so nested ones show up before the expression containing them
+    pub fn expression(&mut self, expr: &Expr, value: &Object) {
+        let kind = match expr {
+            Expr::Literal(..) => "literal",
+            Expr::Grouping { .. } => "grouping",
+            Expr::Assign { .. } => "assign",
+            Expr::Logical { .. } => "logical",
+            Expr::Binary { .. } => "binary",
+            Expr::Unary { .. } => "unary",
+            Expr::Variable { .. } => "variable",
+            Expr::Call { .. } => "call",
+            Expr::Ternary { .. } => "ternary",
+            Expr::Update { .. } => "update",
+            Expr::Get { .. } => "get",
+            Expr::Set { .. } => "set",
+            Expr::This { .. } => "this",
+            Expr::List { .. } => "list",
+            Expr::Map { .. } => "map",
+            Expr::Index { .. } => "index",
+            Expr::SetIndex { .. } => "set index",
+            Expr::Null => "null",
+        };
+        self.write(format_args!(
+            "  [{}] {} => {}",
+            Self::line(expr.line()),
+            kind,
+            value
+        ));
+    }
+
+    pub fn define(&mut self, name: Symbol, value: &Object, env: usize) {
+        self.write(format_args!("  define {} = {} in env {}", name, value, env));
+    }
+
+    pub fn assign(&mut self, name: Symbol, value: &Object, env: usize) {
+        self.write(format_args!("  assign {} = {} in env {}", name, value, env));
+    }
+
+    pub fn collection(&mut self, collection: &Collection) {
+        self.write(format_args!(
+            "  gc: freed {} environments, {} instances, {} lists and {} maps",
+            collection.environments, collection.instances, collection.lists, collection.maps
+        ));
+    }
+
+    fn line(line: Option<usize>) -> String {
+        match line {
+            Some(line) => format!("line {}", line),
+            None => "line ?".to_string(),
+        }
+    }
+
+    /// A trace that can't be written shouldn't stop the script, so io errors are ignored
+    fn write(&mut self, args: std::fmt::Arguments) {
+        let _ = writeln!(self.out, "{}{}", "  ".repeat(self.depth), args);
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
@@ -1,74 +1,266 @@
-use interpreter::InterpreterVisitor;
+use interpreter::{heap::GcConfig, trace::Tracer, InterpreterVisitor};
+use resolver::Resolver;
 use scanner::Scanner;
-use std::error::Error;
-use std::io::{self, Write};
+use std::io;
+use vm::{compiler::Compiler, Vm};
 
 pub mod ast;
+pub mod diagnostic;
 pub mod environment;
 pub mod error;
+pub mod explain;
+pub mod interner;
 pub mod interpreter;
 pub mod parser;
+pub mod repl;
+pub mod resolver;
 pub mod scanner;
 pub mod token;
+pub mod vm;
 
-pub fn main() -> Result<(), Box<dyn Error>> {
-    let mut args = std::env::args();
-    args.next();
+pub use error::Error;
 
-    if args.len() > 1 {
-        println!("Usage: nenia [script]");
-        std::process::exit(64);
-    } else if args.len() != 1 {
-        run_prompt()?;
-    } else {
-        run_file(&args.next().unwrap())?;
+/// Which backend runs a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// [InterpreterVisitor], walks the syntax tree directly
+    TreeWalker,
+    /// [Vm], compiles to bytecode first
+    Vm,
+}
+
+/// Where `--trace` output goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trace {
+    Stderr,
+    File(String),
+}
+
+impl Trace {
+    fn tracer(&self) -> Result<Tracer, Error> {
+        match self {
+            Trace::Stderr => Ok(Tracer::stderr()),
+            Trace::File(path) => Tracer::file(path).map_err(|e| Error::Write(path.clone(), e)),
+        }
+    }
+}
+
+/// Everything that can be set from the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub backend: Backend,
+    pub trace: Option<Trace>,
+    pub gc: GcConfig,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            backend: Backend::TreeWalker,
+            trace: None,
+            gc: GcConfig::default(),
+        }
+    }
+}
+
+impl Options {
+    /// A fresh tree-walker set up the way the options ask for
+    pub fn interpreter(&self) -> Result<InterpreterVisitor, Error> {
+        let mut interpreter = InterpreterVisitor::with_gc(self.gc);
+        if let Some(trace) = &self.trace {
+            interpreter.set_tracer(trace.tracer()?);
+        }
+        Ok(interpreter)
     }
+}
 
+fn usage() -> ! {
+    println!("Usage: nenia [--vm] [--trace[=file]] [--gc-stress] [--gc-threshold=bytes] [--gc-growth=factor] [script]");
+    println!("       nenia explain <code>");
+    println!("       nenia fmt [--check] <script>");
+    std::process::exit(64);
+}
+
+/// `nenia explain E0102`, prints what the error with that code means
+fn explain(code: Option<String>) -> ! {
+    let Some(code) = code else { usage() };
+    match explain::explain(&code) {
+        Some(explanation) => {
+            println!("{}", explanation);
+            std::process::exit(0);
+        }
+        None => {
+            eprintln!("error: no error has the code `{}`", code);
+            std::process::exit(64);
+        }
+    }
+}
+
+/// `nenia fmt main.lox`, formats the script in place. With `--check` nothing gets written,
+/// the script not being formatted already is an error instead
+fn fmt(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut check = false;
+    let mut script = None;
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            a if a.starts_with("--") => usage(),
+            _ if script.is_none() => script = Some(arg),
+            _ => usage(),
+        }
+    }
+    let Some(script) = script else { usage() };
+
+    let content = std::fs::read_to_string(&script).map_err(|e| Error::Read(script.clone(), e))?;
+    let formatted = ast::printer::format(&content, &script)?;
+    if formatted == content {
+        return Ok(());
+    }
+    // Whatever the formatter got wrong, the script on disk stays the way it was
+    if let Err(e) = ast::printer::format(&formatted, &script) {
+        eprintln!(
+            "error: formatting `{}` would break it, so it's left alone",
+            script
+        );
+        return Err(e);
+    }
+    if check {
+        eprintln!(
+            "error: `{}` isn't formatted, `nenia fmt {}` would change it",
+            script, script
+        );
+        std::process::exit(1);
+    }
+    std::fs::write(&script, formatted).map_err(|e| Error::Write(script, e))?;
     Ok(())
 }
 
-pub fn run(src: String, interpreter: &mut InterpreterVisitor) -> Result<(), Box<dyn Error>> {
+pub fn main() -> Result<(), Error> {
+    let mut options = Options::default();
+    let mut script = None;
+
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().is_some_and(|arg| arg == "explain") {
+        explain(args.nth(1));
+    }
+    if args.peek().is_some_and(|arg| arg == "fmt") {
+        return fmt(args.skip(1));
+    }
+
+    for arg in args {
+        match arg.as_str() {
+            "--vm" => options.backend = Backend::Vm,
+            "--trace" => options.trace = Some(Trace::Stderr),
+            a if a.starts_with("--trace=") => {
+                options.trace = Some(Trace::File(a["--trace=".len()..].to_string()))
+            }
+            "--gc-stress" => options.gc.stress = true,
+            a if a.starts_with("--gc-threshold=") => {
+                options.gc.initial_threshold = a["--gc-threshold=".len()..]
+                    .parse()
+                    .unwrap_or_else(|_| usage())
+            }
+            a if a.starts_with("--gc-growth=") => {
+                options.gc.growth_factor = match a["--gc-growth=".len()..].parse() {
+                    Ok(factor) if factor > 0 => factor,
+                    _ => usage(),
+                }
+            }
+            a if a.starts_with("--") => usage(),
+            _ if script.is_none() => script = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    // Tracing and the collector hook into the tree-walker, the VM has neither
+    if options.backend == Backend::Vm
+        && (options.trace.is_some() || options.gc != GcConfig::default())
+    {
+        usage();
+    }
+
+    match script {
+        Some(script) => run_file(&script, &options)?,
+        // The REPL only knows how to talk to the tree-walker
+        None if options.backend == Backend::Vm => usage(),
+        None => run_prompt(&options)?,
+    }
+
+    Ok(())
+}
+
+pub fn run(src: String, interpreter: &mut InterpreterVisitor) -> Result<(), Error> {
     // println!("running");
-    let mut scanner = Scanner::new(src);
-    scanner.scan_tokens()?;
+    let statements = parse(src, &interpreter.script_name())?;
+    interpreter.interpret(statements)?;
+    Ok(())
+}
 
-    let mut parser = parser::Parser::new(scanner.tokens);
-    let statements = parser.parse()?;
+/// Scans, parses and resolves `src`, which is everything that has to happen before the tree-walker can run it.
+/// `file` is what spans, and so error messages, call the source
+pub fn parse(src: String, file: &str) -> Result<Vec<ast::Stmt>, Error> {
+    let mut scanner = Scanner::with_file(src, file);
+    let scanned = scanner.scan_tokens();
+    let statements = syntax(scanned, scanner.tokens)?;
     // dbg!(&statements);
-    interpreter.interpret(statements)?;
+    Resolver::new().resolve(&statements)?;
+    Ok(statements)
+}
+
+/// Parses what the scanner came up with, `scanned` being whether that went alright.
+/// Even if scanning went wrong, there are tokens to look for syntax errors in, so everything is reported in one go
+pub(crate) fn syntax(
+    scanned: Result<(), Vec<error::ScanError>>,
+    tokens: Vec<token::Token>,
+) -> Result<Vec<ast::Stmt>, Error> {
+    let mut parser = parser::Parser::new(tokens);
+    if let Err(errors) = &scanned {
+        parser.set_scan_errors(errors.iter().map(error::ScanError::span).collect());
+    }
+    match (scanned, parser.parse()) {
+        (Ok(()), Ok(statements)) => Ok(statements),
+        (scanned, parsed) => {
+            let scanned = scanned.err().unwrap_or_default();
+            let mut parsed = parsed.err().unwrap_or_default();
+            // A string or comment that never ends swallows the rest of the file, running out of tokens is just noise then
+            if !scanned.is_empty() {
+                parsed.retain(|e| !e.at_eof());
+            }
+            let errors = scanned.into_iter().map(Error::from);
+            Err(Error::many(
+                errors.chain(parsed.into_iter().map(Error::from)).collect(),
+            ))
+        }
+    }
+}
+
+/// Same as [run], but compiles to bytecode and runs it on `vm` instead. `file` is what error messages call the source
+pub fn run_vm(src: String, file: &str, vm: &mut Vm) -> Result<(), Error> {
+    // The compiler does its own slot resolution, but the resolver still catches static errors
+    let statements = parse(src, file)?;
+    let function = Compiler::compile(&statements)?;
+    vm.interpret(function)?;
     Ok(())
 }
 
 // Interactive
-pub fn run_prompt() -> Result<(), Box<dyn Error>> {
-    // create interpreter
-    let mut interpreter = InterpreterVisitor::new();
-    loop {
-        let mut input = String::new();
-        print!("> ");
-        io::stdout().flush()?;
-        io::stdin().read_line(&mut input)?;
-        match &input.trim() {
-            b if b.is_empty() => {
+pub fn run_prompt(options: &Options) -> Result<(), Error> {
+    let mut repl = repl::Repl::new(options)?;
+    let mut editor = repl::editor::Editor::new();
+    while let Some(input) =
+        repl::read_input(|prompt| editor.read_line(prompt, |word| repl.completions(word)))?
+    {
+        match input.trim() {
+            "" => {
                 break;
             }
-            &"exit" => {
+            "exit" => {
                 break;
             }
+            // The session, and every variable in it, outlives whatever went wrong
             _ => {
-                // TODO replace `run` w/ `run_repl`, where we only parse 1 stmt, and match that statement to see if it's an expr or a declaration
-                // If it is just an expr then return it.
-                // We certaintly don't want to pass a variable into run to achieve this because then it'll affect runtime performance for actual files at a nontrivial level
-                let mut scanner = Scanner::new(input);
-                scanner.scan_tokens()?;
-                let mut parser = parser::Parser::new(scanner.tokens);
-                let statements = parser.parse()?;
-                match statements.get(0) {
-                    Some(s) => match s {
-                        ast::Stmt::Expr(e) => println!("{:#?}", interpreter.evaluate(e)?),
-                        s => interpreter.execute(s)?,
-                    },
-                    None => eprintln!("no statements "),
+                if let Err(e) = repl.handle(input, &mut io::stdout()) {
+                    diagnostic::report(&e);
                 }
             }
         }
@@ -76,8 +268,14 @@ pub fn run_prompt() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn run_file(arg: &str) -> Result<(), Box<dyn Error>> {
-    let content = std::fs::read_to_string(arg)?;
-    let mut interpreter = InterpreterVisitor::new();
-    run(content, &mut interpreter)
+pub fn run_file(arg: &str, options: &Options) -> Result<(), Error> {
+    let content = std::fs::read_to_string(arg).map_err(|e| Error::Read(arg.into(), e))?;
+    match options.backend {
+        Backend::TreeWalker => {
+            let mut interpreter = options.interpreter()?;
+            interpreter.set_script(arg);
+            run(content, &mut interpreter)
+        }
+        Backend::Vm => run_vm(content, arg, &mut Vm::new()),
+    }
 }
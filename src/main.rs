@@ -1,6 +1,11 @@
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    if let Err(e) = nenia::main() {
-        eprintln!("{}", e)
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match nenia::main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            nenia::diagnostic::report(&e);
+            ExitCode::from(e.exit_code())
+        }
     }
-    Ok(())
 }
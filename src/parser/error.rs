@@ -1,5 +1,8 @@
 /// Heavy inspirations from ripgrep's error handling: https://github.com/BurntSushi/ripgrep/blob/master/crates/regex/src/error.rs
-use crate::token::Token;
+use crate::{
+    diagnostic::Diagnostic,
+    token::{Span, Token, TokenType},
+};
 use std::{error, fmt};
 
 #[derive(Debug)]
@@ -13,18 +16,131 @@ impl ParseError {
     pub fn new(kind: ParseErrorKind) -> ParseError {
         ParseError { kind }
     }
+
+    /// Where in the source the error is, which is always the token the kind holds on to
+    pub fn span(&self) -> Span {
+        match &self.kind {
+            ParseErrorKind::Error(_, t, _)
+            | ParseErrorKind::ExpectExpression(t)
+            | ParseErrorKind::ExpectLeftOperand(t)
+            | ParseErrorKind::TooManyArguments(t)
+            | ParseErrorKind::InvalidAssignmentTarget(t)
+            | ParseErrorKind::OutsideLoop(t)
+            | ParseErrorKind::TryWithoutHandler(t)
+            | ParseErrorKind::ModuleName(t) => t.span,
+        }
+    }
+}
+
+/// How a token shows up after "found" in an error, the end of the file doesn't have a lexeme to show
+fn found(t: &Token) -> String {
+    match t.token_type {
+        TokenType::Eof => "end of file".into(),
+        _ => format!("`{}`", t.lexeme),
+    }
+}
+
+impl ParseError {
+    /// See [crate::Error::code]
+    pub fn code(&self) -> &'static str {
+        match &self.kind {
+            ParseErrorKind::Error(..) => "E0201",
+            ParseErrorKind::ExpectExpression(_) => "E0202",
+            ParseErrorKind::ExpectLeftOperand(_) => "E0203",
+            ParseErrorKind::TooManyArguments(_) => "E0204",
+            ParseErrorKind::InvalidAssignmentTarget(_) => "E0205",
+            ParseErrorKind::OutsideLoop(_) => "E0206",
+            ParseErrorKind::TryWithoutHandler(_) => "E0207",
+            ParseErrorKind::ModuleName(_) => "E0208",
+        }
+    }
+
+    /// Whether the parser ran out of tokens, i.e. the error is about what it found being the end of the file
+    pub fn at_eof(&self) -> bool {
+        match &self.kind {
+            ParseErrorKind::Error(_, t, _) | ParseErrorKind::ExpectExpression(t) => {
+                t.token_type == TokenType::Eof
+            }
+            _ => false,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.kind.to_string()).with_span(Some(self.span()));
+        match &self.kind {
+            ParseErrorKind::Error(exp, ..) => {
+                diagnostic.with_label(format!("expected {}", exp.describe()))
+            }
+            ParseErrorKind::ExpectExpression(_) => diagnostic.with_label("expected an expression"),
+            ParseErrorKind::ExpectLeftOperand(_) => {
+                diagnostic.with_label("needs an operand on its left")
+            }
+            ParseErrorKind::TooManyArguments(_) => diagnostic
+                .with_label("one too many")
+                .with_note("functions can only take up to 255 arguments"),
+            ParseErrorKind::InvalidAssignmentTarget(t) if t.token_type == TokenType::Equal => {
+                diagnostic
+                    .with_label("can't assign to what's on the left of this")
+                    .with_help("only variables, properties and indexes can be assigned to")
+            }
+            // `+=`, `++` and the like are sugar that's only there for variables
+            ParseErrorKind::InvalidAssignmentTarget(t) => diagnostic
+                .with_label(format!("can't use `{}` on this", t.lexeme))
+                .with_help(format!("`{}` only works on variables", t.lexeme)),
+            ParseErrorKind::OutsideLoop(_) => diagnostic
+                .with_label("not inside of a loop")
+                .with_note("a function declared inside of a loop can't reach out of it"),
+            ParseErrorKind::TryWithoutHandler(_) => diagnostic
+                .with_label("nothing to handle errors")
+                .with_help("add a `catch (e) { .. }`, a `finally { .. }`, or both"),
+            ParseErrorKind::ModuleName(_) => diagnostic.with_label("not a valid identifier"),
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self.kind {
+        write!(f, "{}: {}", self.span(), self.kind)
+    }
+}
+
+/// Just the message, without where it happened
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
             ParseErrorKind::Error(exp, fnd, str) => {
-                write!(f, "expected `Token {:?}`, found {:?}, {}", exp.token_type, fnd, str)
+                write!(
+                    f,
+                    "expected {} {}, found {}",
+                    exp.describe(),
+                    str,
+                    found(fnd)
+                )
+            }
+            ParseErrorKind::ExpectExpression(t) => {
+                write!(f, "expected an expression, found {}", found(t))
+            }
+            ParseErrorKind::ExpectLeftOperand(t) => {
+                write!(f, "missing left operand for `{}`", t.lexeme)
+            }
+            ParseErrorKind::InvalidAssignmentTarget(t) => {
+                write!(f, "invalid assignment target for `{}`", t.lexeme)
+            }
+            ParseErrorKind::OutsideLoop(t) => {
+                write!(f, "can't `{}` outside of a loop", t.lexeme)
+            }
+            ParseErrorKind::ModuleName(t) => write!(
+                f,
+                "can't name a module {}, use `import {} as name;` instead",
+                t.lexeme, t.lexeme
+            ),
+            ParseErrorKind::TryWithoutHandler(_) => {
+                write!(f, "`try` needs a `catch` or a `finally` after it")
             }
-            ParseErrorKind::ExpectLeftOperand(t) => write!(
+            ParseErrorKind::TooManyArguments(t) => write!(
                 f,
-                "missing left operand for {:?}({}) in line {}",
-                t.token_type, t.lexeme, t.line
+                "can't have more than 255 arguments, found another one at `{}`",
+                t.lexeme
             ),
         }
     }
@@ -32,6 +148,16 @@ impl fmt::Display for ParseError {
 
 #[derive(Debug)]
 pub enum ParseErrorKind {
-    Error(Token, Token, String),
+    /// The token type that was expected, the token found instead, and what it was expected for
+    Error(TokenType, Token, String),
+    /// Something that can't start an expression is where one should be
+    ExpectExpression(Token),
     ExpectLeftOperand(Token),
+    TooManyArguments(Token),
+    InvalidAssignmentTarget(Token),
+    /// `break` or `continue` that isn't inside of a loop in the same function
+    OutsideLoop(Token),
+    TryWithoutHandler(Token),
+    /// The file name of an `import` without an `as` isn't a valid identifier
+    ModuleName(Token),
 }
@@ -1,55 +1,146 @@
 pub mod error;
 
+use std::{cell::Cell, rc::Rc};
+
 use crate::{
-    ast::{Expr, Stmt},
-    environment::error::env_error,
-    token::{Literal, Token, TokenType},
+    ast::{Expr, FunctionDecl, Stmt},
+    token::{Literal, Span, Token, TokenType},
 };
 
 use self::error::{ParseError, ParseErrorKind};
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+type Result<T> = std::result::Result<T, Box<ParseError>>;
+
+/// Functions can't take more arguments than this, same limit as the book
+const MAX_ARGUMENTS: usize = 255;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    /// How many loops we're inside of, so `break` and `continue` know if they're allowed
+    loop_depth: usize,
+    /// How many blocks we're inside of, so recovering from an error doesn't skip the `}` that closes one
+    block_depth: usize,
+    /// Every error so far, parsing picks back up at the next statement after one
+    errors: Vec<ParseError>,
+    /// Where the scanner already found something wrong, see [Parser::set_scan_errors]
+    scan_errors: Vec<Span>,
 }
 
 impl Parser {
-    pub fn parse(&mut self) -> Result<Vec<Stmt>> {
+    /// Parses every statement there is. A statement with an error in it doesn't stop the parser, it skips ahead to the next one,
+    /// so every error in the file is handed back
+    pub fn parse(&mut self) -> std::result::Result<Vec<Stmt>, Vec<ParseError>> {
         // create vec of statements
         let mut statements: Vec<Stmt> = Vec::new();
 
         // as long as we're not at end of file
         while !self.is_at_end() {
-            // dbg!(&statements);
             // make mo statements
-            let declaration = match self.declaration() {
-                Ok(d) => d,
-                Err(e) => {
-                    // synchronize (e.g. ignore all other errors in the statement)
-                    self.synchronize();
-                    return Err(e);
-                }
-            };
-            statements.push(declaration);
+            if let Some(declaration) = self.recovering_declaration() {
+                statements.push(declaration);
+            }
         }
-        // eprintln!("{:?}", statements);
 
-        Ok(statements)
+        match self.errors.is_empty() {
+            true => Ok(statements),
+            false => Err(std::mem::take(&mut self.errors)),
+        }
+    }
+
+    /// A declaration, or if there's an error in it, nothing. The error is remembered,
+    /// and the parser skips ahead to where the next declaration probably starts
+    fn recovering_declaration(&mut self) -> Option<Stmt> {
+        let start = self.peek().span.start;
+        match self.declaration() {
+            Ok(declaration) => Some(declaration),
+            Err(e) => {
+                // The scanner already reported what's wrong with this statement, e.g. the `@` in `var a = @;`.
+                // What the parser makes of the tokens around it is just the same mistake again
+                let end = e.span().end;
+                if !self
+                    .scan_errors
+                    .iter()
+                    .any(|s| (start..=end).contains(&s.start))
+                {
+                    self.errors.push(*e);
+                }
+                // synchronize (e.g. ignore all other errors in the statement)
+                self.synchronize();
+                None
+            }
+        }
     }
 
     fn declaration(&mut self) -> Result<Stmt> {
         if self.matches(&[TokenType::Var]) {
             self.var_declaration()
+        } else if self.matches(&[TokenType::Func]) {
+            Ok(Stmt::Function(self.function("function")?))
+        } else if self.matches(&[TokenType::Class]) {
+            self.class_declaration()
         } else {
             self.statement()
         }
     }
 
+    /// Parses `class Name { ... }`, where the body is a list of methods (which are functions without the `func`)
+    fn class_declaration(&mut self) -> Result<Stmt> {
+        let name = self
+            .consume(TokenType::Identifier, "for the class name")?
+            .clone();
+        self.consume(TokenType::LeftBrace, "before class body")?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+
+        self.consume(TokenType::RightBrace, "after class body")?;
+
+        Ok(Stmt::Class { name, methods })
+    }
+
+    /// Parses everything after the `func` keyword, i.e. `name(a, b) { ... }`
+    /// `kind` is only used to make error messages a little more descriptive
+    fn function(&mut self, kind: &str) -> Result<Rc<FunctionDecl>> {
+        let name = self
+            .consume(TokenType::Identifier, &format!("for the {} name", kind))?
+            .clone();
+
+        self.consume(TokenType::LeftParen, &format!("after {} name", kind))?;
+        let mut params = Vec::new();
+        // `func a()` has no parameters at all, so only look for them if the parenthesis isn't closed right away
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    return Err(Box::new(ParseError::new(ParseErrorKind::TooManyArguments(
+                        self.peek_clone(),
+                    ))));
+                }
+                params.push(
+                    self.consume(TokenType::Identifier, "for a parameter name")?
+                        .clone(),
+                );
+                if !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "after parameters")?;
+
+        // A loop outside of the function can't be broken out of from inside of it
+        let enclosing_loops = std::mem::replace(&mut self.loop_depth, 0);
+        let body = self.block_statements(&format!("{} body", kind));
+        self.loop_depth = enclosing_loops;
+        let body = body?;
+
+        Ok(Rc::new(FunctionDecl { name, params, body }))
+    }
+
     fn var_declaration(&mut self) -> Result<Stmt> {
         let name = self
-            .consume(TokenType::Identifier, "var declaration beginning")?
+            .consume(TokenType::Identifier, "for the variable name")?
             .clone();
 
         let mut initializer = Expr::Null;
@@ -58,7 +149,7 @@ impl Parser {
             initializer = self.expression()?;
         }
 
-        self.consume(TokenType::Semicolon, "var declaration ending")?;
+        self.consume(TokenType::Semicolon, "after variable declaration")?;
 
         Ok(Stmt::Var { name, initializer })
     }
@@ -68,26 +159,39 @@ impl Parser {
         let mut statements = Vec::new();
 
         // keep consuming tokens until we get to a right brace
+        self.block_depth += 1;
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
             // push it onto the vec
-            statements.push(self.declaration()?);
+            if let Some(declaration) = self.recovering_declaration() {
+                statements.push(declaration);
+            }
         }
+        self.block_depth -= 1;
 
         // Then consume the right bracket
-        self.consume(TokenType::RightBrace, "block")?;
+        self.consume(TokenType::RightBrace, "after block")?;
 
         // Return our statements
         Ok(Stmt::Block { statements })
     }
 
+    /// Parses a `{ ... }` that has to be there, like a function body, and hands back what's inside of it.
+    /// Unlike `block()`, this consumes the `{` too
+    fn block_statements(&mut self, what: &str) -> Result<Vec<Stmt>> {
+        self.consume(TokenType::LeftBrace, &format!("before {}", what))?;
+        match self.block()? {
+            Stmt::Block { statements } => Ok(statements),
+            _ => unreachable!("block() always returns a Stmt::Block"),
+        }
+    }
+
     fn statement(&mut self) -> Result<Stmt> {
         // check if it's a print statement
         // TODO Could, should convert this into match statement, it's looking a lot like a certain Yandere Developer's code right now
         if self.matches(&[TokenType::Print]) {
             self.print_statement()
-        // check if its a block
-        } else if self.matches(&[TokenType::LeftBrace]) {
-            // dbg!("its a block");
+        // check if its a block, `{` could also be the start of a map literal though
+        } else if !self.starts_map() && self.matches(&[TokenType::LeftBrace]) {
             self.block()
         } else if self.matches(&[TokenType::If]) {
             self.if_statement()
@@ -95,6 +199,16 @@ impl Parser {
             self.while_statement()
         } else if self.matches(&[TokenType::For]) {
             self.for_statement()
+        } else if self.matches(&[TokenType::Return]) {
+            self.return_statement()
+        } else if self.matches(&[TokenType::Break, TokenType::Continue]) {
+            self.loop_jump_statement()
+        } else if self.matches(&[TokenType::Import]) {
+            self.import_statement()
+        } else if self.matches(&[TokenType::Throw]) {
+            self.throw_statement()
+        } else if self.matches(&[TokenType::Try]) {
+            self.try_statement()
         } else {
             // otherwise just treat it as an extension
             self.expression_statement()
@@ -103,8 +217,9 @@ impl Parser {
 
     /// Desugars a `for` loop to [Stmt::While]
     fn for_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
         // Take the for `(` beginning parenthesis
-        self.consume(TokenType::LeftParen, "beginning of for statement")?;
+        self.consume(TokenType::LeftParen, "after `for`")?;
 
         // Any part of the space in between semicolons can be omitted
         // for(; sdf; sdf)
@@ -133,7 +248,7 @@ impl Parser {
             Some(self.expression()?)
         };
         // We have to check instead of consume here because if user does not have any semicolons then we should error rather than interpret that as an optional omission
-        self.consume(TokenType::Semicolon, "first for statement semicolon")?;
+        self.consume(TokenType::Semicolon, "after loop condition")?;
 
         // for (asdf; asdf; )
         let increment = if !self.check(TokenType::RightParen) {
@@ -141,35 +256,34 @@ impl Parser {
         } else {
             None
         };
-        self.consume(TokenType::RightParen, "second for statement semicolon")?;
+        self.consume(TokenType::RightParen, "after for clauses")?;
 
         // body refers to the `{ }` after the `for` statement
-        let body = self.statement()?;
+        let body = self.loop_body()?;
 
         // Now we start to build a ast syntax tree that includes all these elements
 
-        // increment would be
+        // The increment used to be appended to the end of the body, like
         /*
         {
             user code here...
             b += 1;
         }
         */
-        // If there is an increment add it to the body, otherwise leave body as as
-        // map is pretty useful for manipulating options
-        let body = if let Some(increment) = increment {
-            Stmt::Block {
-                statements: vec![body, Stmt::Expr(increment)],
-            }
-        } else {
-            body
-        };
+        // but then a `continue` would skip right over it, so the while loop runs it itself instead
 
-        // If there is no condition, treat it as a `while (true) {}` loop
+        // If there is no condition, treat it as a `while (true) {}` loop, with the closing `)` standing in for the `true`
         let condition = if let Some(condition) = condition {
             condition
         } else {
-            Expr::Literal(Literal::Boolean(false))
+            Expr::Literal(Literal::Boolean(true), self.previous().span)
+        };
+
+        let body = Stmt::While {
+            keyword,
+            condition,
+            body: Box::new(body),
+            increment,
         };
 
         // If there is an initializer, we run it once before the whole loop
@@ -183,27 +297,51 @@ impl Parser {
             body
         };
 
-        dbg!(&body);
-
-        Ok(Stmt::While {
-            condition,
-            body: Box::new(body),
-        })
+        Ok(body)
     }
 
     /// Generates [Stmt::While] with a condition and a body
     fn while_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
         // Again, duplicate code that I'm too lazy to get rid of rn lol
         // See if_statement
         // First consume `(`
-        self.consume(TokenType::LeftParen, "beginning of while statement")?;
+        self.consume(TokenType::LeftParen, "after `while`")?;
         // Then consume the statemtent inside `(..)`
         let condition = self.expression()?;
-        self.consume(TokenType::RightParen, "end of while statement")?;
+        self.consume(TokenType::RightParen, "after condition")?;
         // Finaly, consume the right `)`
-        let body = Box::new(self.statement()?);
+        let body = Box::new(self.loop_body()?);
+
+        Ok(Stmt::While {
+            keyword,
+            condition,
+            body,
+            increment: None,
+        })
+    }
+
+    /// Parses the body of a `while` or `for`, where `break` and `continue` are allowed
+    fn loop_body(&mut self) -> Result<Stmt> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
 
-        Ok(Stmt::While { condition, body })
+    /// Generates [Stmt::Break] or [Stmt::Continue], as long as we're inside of a loop
+    fn loop_jump_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
+        if self.loop_depth == 0 {
+            return Err(Box::new(ParseError::new(ParseErrorKind::OutsideLoop(
+                keyword,
+            ))));
+        }
+        self.consume(TokenType::Semicolon, &format!("after {}", keyword.lexeme))?;
+        Ok(match keyword.token_type {
+            TokenType::Break => Stmt::Break { keyword },
+            _ => Stmt::Continue { keyword },
+        })
     }
 
     /// Generates expr conditional, then statement, else statement
@@ -213,9 +351,9 @@ impl Parser {
         // Consume left (
         // TODO it's not good nor idiomatic that we have to generate an error like this
         // Maybe helper method that gens this? Would like to see what the runtime error looks like first
-        self.consume(TokenType::LeftParen, "beginning of if statement")?;
+        self.consume(TokenType::LeftParen, "after `if`")?;
         let condition = self.expression()?;
-        self.consume(TokenType::RightParen, "end of if statement")?;
+        self.consume(TokenType::RightParen, "after condition")?;
         // Note that we use self.statement() here instead of self.block(), because unlike rust we want to
         // support single-line conditional into statements like `if (true) run();`
         let then_branch = self.statement()?;
@@ -232,6 +370,97 @@ impl Parser {
         })
     }
 
+    /// Generates [Stmt::Return], the value is optional, e.g. `return;` just returns nil
+    fn return_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
+        let value = if self.check(TokenType::Semicolon) {
+            Expr::Null
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "after return value")?;
+        Ok(Stmt::Return { keyword, value })
+    }
+
+    /// Generates [Stmt::Import]. Without an `as`, the module is named after its file, e.g. `lib/math.lox` is bound to `math`
+    fn import_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
+        let path = self
+            .consume(TokenType::String, "for the module path")?
+            .clone();
+
+        let name = if self.matches(&[TokenType::As]) {
+            self.consume(TokenType::Identifier, "for the module name")?
+                .clone()
+        } else {
+            let Literal::String(file) = path.literal else {
+                unreachable!("string tokens always hold a string literal")
+            };
+            let file = file.as_str();
+            let stem = std::path::Path::new(&*file)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default();
+            // Has to be something the scanner would've called an identifier
+            let is_identifier = stem.chars().next().is_some_and(char::is_alphabetic)
+                && stem.chars().all(char::is_alphanumeric)
+                && crate::scanner::keyword_type(stem) == TokenType::Identifier;
+            if !is_identifier {
+                return Err(Box::new(ParseError::new(ParseErrorKind::ModuleName(path))));
+            }
+            Token::new(TokenType::Identifier, stem.into(), Literal::Nil, path.span)
+        };
+
+        self.consume(TokenType::Semicolon, "after import")?;
+        Ok(Stmt::Import {
+            keyword,
+            path,
+            name,
+        })
+    }
+
+    /// Generates [Stmt::Throw], unlike `return` the value can't be left out
+    fn throw_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "after thrown value")?;
+        Ok(Stmt::Throw { keyword, value })
+    }
+
+    /// Generates [Stmt::Try], every part of it has to be a block
+    fn try_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
+        let body = self.block_statements("try body")?;
+
+        let catch = if self.matches(&[TokenType::Catch]) {
+            self.consume(TokenType::LeftParen, "after catch")?;
+            let name = self
+                .consume(TokenType::Identifier, "for the caught error name")?
+                .clone();
+            self.consume(TokenType::RightParen, "after caught error name")?;
+            Some((name, self.block_statements("catch body")?))
+        } else {
+            None
+        };
+        let finally = if self.matches(&[TokenType::Finally]) {
+            Some(self.block_statements("finally body")?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(Box::new(ParseError::new(
+                ParseErrorKind::TryWithoutHandler(keyword),
+            )));
+        }
+        Ok(Stmt::Try {
+            keyword,
+            body,
+            catch,
+            finally,
+        })
+    }
+
     /// Generates print expr statement
     fn print_statement(&mut self) -> Result<Stmt> {
         let value = self.expression()?;
@@ -242,7 +471,7 @@ impl Parser {
     /// Generates stock expr statement
     fn expression_statement(&mut self) -> Result<Stmt> {
         let expr = self.expression()?;
-        self.consume(TokenType::Semicolon, "after expr statement")?;
+        self.consume(TokenType::Semicolon, "after expression")?;
         Ok(Stmt::Expr(expr))
     }
 
@@ -278,23 +507,76 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Expr> {
-        // Offset the call to the or statement, which will eventually come back to equality
-        let expr = self.or()?;
+        // Offset the call to the conditional, which will eventually come back to equality
+        let expr = self.conditional()?;
 
         // if we find an equals after the left-hand side,
         // wrap it all up in an assignment expression
         if self.matches(&[TokenType::Equal]) {
-            let _equals = self.previous();
+            let equals = self.previous().clone();
             // right-associative recursion is ok
             let value = self.assignment()?;
 
-            if let Expr::Variable { name } = expr {
-                Ok(Expr::Assign {
+            match expr {
+                Expr::Variable { name, .. } => Ok(Expr::Assign {
                     name,
                     value: Box::new(value),
-                })
-            } else {
-                Err(Box::new(env_error(self.peek(), "during assignment")))
+                    depth: Cell::new(None),
+                }),
+                // `a.b = c` is parsed as a get first, which we can just turn into a set
+                Expr::Get { object, name } => Ok(Expr::Set {
+                    object,
+                    name,
+                    value: Box::new(value),
+                }),
+                // Same goes for `a[b] = c`
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Ok(Expr::SetIndex {
+                    object,
+                    bracket,
+                    index,
+                    value: Box::new(value),
+                }),
+                _ => Err(Box::new(ParseError::new(
+                    ParseErrorKind::InvalidAssignmentTarget(equals),
+                ))),
+            }
+        } else if self.matches(&[
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+        ]) {
+            let compound = self.previous().clone();
+            let value = self.assignment()?;
+
+            // `a += b` is just sugar for `a = a + b`, the variable is still only read and written once
+            match expr {
+                Expr::Variable { name, .. } => {
+                    let (token_type, lexeme) = match compound.token_type {
+                        TokenType::PlusEqual => (TokenType::Plus, "+"),
+                        TokenType::MinusEqual => (TokenType::Minus, "-"),
+                        TokenType::StarEqual => (TokenType::Star, "*"),
+                        _ => (TokenType::Slash, "/"),
+                    };
+                    let operator =
+                        Token::new(token_type, lexeme.into(), Literal::Nil, compound.span);
+                    Ok(Expr::Assign {
+                        name: name.clone(),
+                        value: Box::new(Expr::Binary {
+                            left: Box::new(Expr::variable(name)),
+                            operator,
+                            right: Box::new(value),
+                        }),
+                        depth: Cell::new(None),
+                    })
+                }
+                _ => Err(Box::new(ParseError::new(
+                    ParseErrorKind::InvalidAssignmentTarget(compound),
+                ))),
             }
         } else {
             // otherwise just return the expr
@@ -302,6 +584,25 @@ impl Parser {
         }
     }
 
+    /// `condition ? then : else`, which binds looser than `or` and is right-associative,
+    /// so `a ? b : c ? d : e` is `a ? b : (c ? d : e)`
+    fn conditional(&mut self) -> Result<Expr> {
+        let condition = self.or()?;
+
+        if self.matches(&[TokenType::Question]) {
+            let then_branch = self.expression()?;
+            self.consume(TokenType::Colon, "between the branches of a conditional")?;
+            let else_branch = self.conditional()?;
+            Ok(Expr::Ternary {
+                condition: Box::new(condition),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            })
+        } else {
+            Ok(condition)
+        }
+    }
+
     fn or(&mut self) -> Result<Expr> {
         // Match the lower precedence level, or has lower precendence than and (and is evaluated first)
         let mut expr = self.and()?;
@@ -350,7 +651,7 @@ impl Parser {
                 TokenType::Greater,
                 TokenType::GreaterEqual,
                 TokenType::Less,
-                TokenType::Greater,
+                TokenType::LessEqual,
             ],
             Self::term,
         )
@@ -366,7 +667,7 @@ impl Parser {
         self.recursive_descent(&[TokenType::Slash, TokenType::Star], Self::unary)
     }
 
-    /// '!' or '-' found, we can recursively parse itself (i.e. !!true, --number, etc.)
+    /// '!' or '-' found, we can recursively parse itself (i.e. !!true, -(-number), etc.)
     fn unary(&mut self) -> Result<Expr> {
         if self.matches(&[TokenType::Bang, TokenType::Minus]) {
             let operator = self.previous().clone();
@@ -375,12 +676,105 @@ impl Parser {
                 operator,
                 right: Box::new(right),
             })
+        } else if self.matches(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            // prefix `++a`
+            let operator = self.previous().clone();
+            let target = self.unary()?;
+            self.update(target, operator, true)
+        } else {
+            self.postfix()
+        }
+    }
+
+    /// postfix `a++` and `a--`
+    fn postfix(&mut self) -> Result<Expr> {
+        let expr = self.call()?;
+
+        if self.matches(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let operator = self.previous().clone();
+            self.update(expr, operator, false)
         } else {
-            Ok(self.primary()?)
+            Ok(expr)
+        }
+    }
+
+    /// Builds an [Expr::Update], only variables can be incremented or decremented
+    fn update(&self, target: Expr, operator: Token, prefix: bool) -> Result<Expr> {
+        match target {
+            Expr::Variable { name, .. } => Ok(Expr::Update {
+                name,
+                operator,
+                prefix,
+                depth: Cell::new(None),
+            }),
+            _ => Err(Box::new(ParseError::new(
+                ParseErrorKind::InvalidAssignmentTarget(operator),
+            ))),
+        }
+    }
+
+    /// A primary expression followed by any number of `(..)` or `.name`, so `a()()` calls whatever `a()` returns
+    /// and `a.b().c` gets `c` off of whatever `a.b()` returns
+    fn call(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.matches(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.matches(&[TokenType::Dot]) {
+                let name = self
+                    .consume(TokenType::Identifier, "property name after `.`")?
+                    .clone();
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
+            } else if self.matches(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self
+                    .consume(TokenType::RightBracket, "after index")?
+                    .clone();
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses the argument list of a call, the `(` has already been consumed
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    return Err(Box::new(ParseError::new(ParseErrorKind::TooManyArguments(
+                        self.peek_clone(),
+                    ))));
+                }
+                arguments.push(self.expression()?);
+                if !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+            }
         }
+        let paren = self
+            .consume(TokenType::RightParen, "after arguments")?
+            .clone();
+
+        Ok(Expr::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+        })
     }
 
-    /// The lowest precedence part of the context-free grammar, matches various primitive types like `false`, `(` + `expr` + `)`, etc. 
+    /// The lowest precedence part of the context-free grammar, matches various primitive types like `false`, `(` + `expr` + `)`, etc.
     fn primary(&mut self) -> Result<Expr> {
         // TODO How do we get rid of this duplication zzz.
         if self.matches(&[
@@ -389,6 +783,7 @@ impl Parser {
             TokenType::Nil,
             TokenType::Number,
             TokenType::String,
+            TokenType::Interpolation,
             TokenType::LeftParen,
             // better error handling, no left operand
             // + 2
@@ -411,28 +806,71 @@ impl Parser {
             TokenType::EqualEqual,
             // [identifier]
             TokenType::Identifier,
+            TokenType::This,
+            TokenType::LeftBracket,
+            TokenType::LeftBrace,
         ]) {
+            let span = self.previous().span;
             let expr = match self.previous().token_type {
-                TokenType::False => Expr::Literal(Literal::Boolean(false)),
-                TokenType::True => Expr::Literal(Literal::Boolean(true)),
-                TokenType::Nil => Expr::Literal(Literal::Nil),
+                TokenType::False => Expr::Literal(Literal::Boolean(false), span),
+                TokenType::True => Expr::Literal(Literal::Boolean(true), span),
+                TokenType::Nil => Expr::Literal(Literal::Nil, span),
+                // the parts of an interpolated string after the first, like `}b${` or `}c"`, start with `}`,
+                // they're only expressions when they open the string
+                TokenType::String | TokenType::Interpolation
+                    if !self.previous().lexeme.as_str().starts_with('"') =>
+                {
+                    return Err(Box::new(ParseError::new(ParseErrorKind::ExpectExpression(
+                        self.previous().clone(),
+                    ))));
+                }
                 TokenType::Number | TokenType::String => {
-                    Expr::Literal(self.previous().literal.clone())
+                    Expr::Literal(self.previous().literal.clone(), span)
                 }
+                TokenType::Interpolation => self.interpolation()?,
                 // in `var apple = 2;`, the name token would just be the previous, which would then match apple!
-                TokenType::Identifier => Expr::Variable {
-                    name: self.previous().clone(),
+                TokenType::Identifier => Expr::variable(self.previous().clone()),
+                TokenType::This => Expr::This {
+                    keyword: self.previous().clone(),
+                    depth: Cell::new(None),
                 },
                 TokenType::LeftParen => {
                     let expr = self.expression()?;
-                    self.consume(
-                        TokenType::RightParen,
-                        "during primary (), while matching parentheses",
-                    )?;
+                    self.consume(TokenType::RightParen, "after expression")?;
                     Expr::Grouping {
                         expression: Box::new(expr),
                     }
                 }
+                TokenType::LeftBracket => {
+                    let bracket = self.previous().clone();
+                    let mut elements = Vec::new();
+                    if !self.check(TokenType::RightBracket) {
+                        loop {
+                            elements.push(self.expression()?);
+                            if !self.matches(&[TokenType::Comma]) {
+                                break;
+                            }
+                        }
+                    }
+                    self.consume(TokenType::RightBracket, "after list elements")?;
+                    Expr::List { bracket, elements }
+                }
+                TokenType::LeftBrace => {
+                    let brace = self.previous().clone();
+                    let mut entries = Vec::new();
+                    if !self.check(TokenType::RightBrace) {
+                        loop {
+                            let key = self.expression()?;
+                            self.consume(TokenType::Colon, "after map key")?;
+                            entries.push((key, self.expression()?));
+                            if !self.matches(&[TokenType::Comma]) {
+                                break;
+                            }
+                        }
+                    }
+                    self.consume(TokenType::RightBrace, "after map entries")?;
+                    Expr::Map { brace, entries }
+                }
                 // The tokens below shouldn't be in primary, so it's mostly error collection
                 // Call factor to evaluate the rest of the statement as a factor, not as terms
                 TokenType::Star | TokenType::Slash => {
@@ -467,23 +905,35 @@ impl Parser {
                     )));
                 }
                 _ => {
-                    return Err(Box::new(ParseError::new(
-                        ParseErrorKind::Error(self.peek().clone(), self.peek().clone(), "invalid sequence, shouldn't ever occur".into()),
-                    )));
+                    return Err(Box::new(ParseError::new(ParseErrorKind::ExpectExpression(
+                        self.previous().clone(),
+                    ))));
                 }
             };
             Ok(expr)
         } else {
-            Err(Box::new(ParseError::new(
-                ParseErrorKind::Error(self.peek().clone(), self.peek().clone(), "don't know why this would occur".into()),
-            )))
+            Err(Box::new(ParseError::new(ParseErrorKind::ExpectExpression(
+                self.peek_clone(),
+            ))))
         }
     }
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+        Parser {
+            tokens,
+            current: 0,
+            loop_depth: 0,
+            block_depth: 0,
+            errors: Vec::new(),
+            scan_errors: Vec::new(),
+        }
+    }
+
+    /// Tells the parser where scanning went wrong, so a statement with a scan error in it doesn't get a parse error too
+    pub fn set_scan_errors(&mut self, spans: Vec<Span>) {
+        self.scan_errors = spans;
     }
 
     /// If our token signals the end of file, then return it
@@ -499,6 +949,60 @@ impl Parser {
         self.previous()
     }
 
+    /// Whether the `{` we're looking at starts a map literal instead of a block.
+    /// A block can never start with a single token followed by a `:`, e.g. `{"a": 1}`,
+    /// while `{}` is always treated as an empty block
+    fn starts_map(&self) -> bool {
+        self.check(TokenType::LeftBrace)
+            && self
+                .tokens
+                .get(self.current + 2)
+                .is_some_and(|t| t.token_type == TokenType::Colon)
+    }
+
+    /// Lowers `"a ${b} c"` into `"a " + (b) + " c"`. Starting off with the string makes every `+` a concatenation,
+    /// and the grouping keeps `"${1 + 2}"` from turning into `"" + 1 + 2`
+    fn interpolation(&mut self) -> Result<Expr> {
+        let mut expr = Expr::Literal(self.previous().literal.clone(), self.previous().span);
+        loop {
+            let plus = Token::new(
+                TokenType::Plus,
+                "+".into(),
+                Literal::Nil,
+                self.previous().span,
+            );
+            let interpolated = Expr::Grouping {
+                expression: Box::new(self.expression()?),
+            };
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator: plus.clone(),
+                right: Box::new(interpolated),
+            };
+
+            // What's left of the string is either the start of another interpolation, or the end of it
+            let done = !self.matches(&[TokenType::Interpolation]);
+            if done {
+                self.consume(TokenType::String, "after interpolated expression")?;
+            }
+            if let Literal::String(s) = &self.previous().literal {
+                if !s.as_str().is_empty() {
+                    expr = Expr::Binary {
+                        left: Box::new(expr),
+                        operator: plus,
+                        right: Box::new(Expr::Literal(
+                            self.previous().literal.clone(),
+                            self.previous().span,
+                        )),
+                    };
+                }
+            }
+            if done {
+                return Ok(expr);
+            }
+        }
+    }
+
     /// Return reference to token at current position
     fn peek(&self) -> &Token {
         &self.tokens[self.current]
@@ -536,7 +1040,7 @@ impl Parser {
             Ok(self.advance())
         } else {
             Err(Box::new(ParseError::new(ParseErrorKind::Error(
-                Token::new(token_type, "any".into(), Literal::Nil, 0),
+                token_type,
                 self.peek_clone(),
                 string.into(),
             ))))
@@ -550,10 +1054,16 @@ impl Parser {
     /// the false positives that will be generated from it as a result.
     /// This function discards all errors until what it thinks is the next statement (e.g. right after a semicolon)
     fn synchronize(&mut self) {
+        // Inside of a block, a `}` is where the block picks back up, so it can't be skipped over
+        let closes_block =
+            |parser: &Parser| parser.block_depth > 0 && parser.check(TokenType::RightBrace);
+        if closes_block(self) {
+            return;
+        }
         self.advance();
 
         while !self.is_at_end() {
-            if self.previous().token_type == TokenType::Semicolon {
+            if self.previous().token_type == TokenType::Semicolon || closes_block(self) {
                 return;
             }
 
@@ -565,7 +1075,12 @@ impl Parser {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Return
+                | TokenType::Break
+                | TokenType::Continue
+                | TokenType::Import
+                | TokenType::Throw
+                | TokenType::Try => return,
                 _ => {}
             }
             self.advance();
@@ -585,4 +1100,34 @@ mod test {
             Err(e) => eprintln!("{}", e),
         };
     }
+
+    #[test]
+    fn errors() {
+        let error = |src: &str| {
+            crate::parse(src.into(), "test.lox")
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("var a = 1\nprint a;"),
+            "test.lox:2:1: expected `;` after variable declaration, found `print`"
+        );
+        assert_eq!(
+            error("print (1;"),
+            "test.lox:1:9: expected `)` after expression, found `;`"
+        );
+        assert_eq!(
+            error("print 1 +"),
+            "test.lox:1:10: expected an expression, found end of file"
+        );
+        // the rest of an interpolated string isn't an expression of its own
+        assert_eq!(
+            error("print \"${1 +}\";"),
+            "test.lox:1:13: expected an expression, found `}\"`"
+        );
+        assert_eq!(
+            error("print \"a${1 +}b${2}c\";"),
+            "test.lox:1:14: expected an expression, found `}b${`"
+        );
+    }
 }
@@ -0,0 +1,624 @@
+//! A small line editor for the prompt, since std only knows how to read whole lines.
+//! Understands the usual readline keys: arrows, home and end, ctrl-a/e/k/u/w, alt-b/f, up and down for history,
+//! ctrl-r to search it, and tab to complete the word in front of the cursor.
+//! When stdin isn't a terminal, lines are read as they are
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, IsTerminal, Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// Lives in the home directory, one line per entry
+const HISTORY_FILE: &str = ".nenia_history";
+/// How many lines of history are kept around, older ones are forgotten
+const HISTORY_SIZE: usize = 1000;
+
+pub struct Editor {
+    /// Oldest first
+    history: Vec<String>,
+    /// Where history is kept between sessions, `None` if there's nowhere to keep it
+    path: Option<PathBuf>,
+}
+
+impl Editor {
+    /// An editor that remembers history in [HISTORY_FILE] in the home directory
+    pub fn new() -> Self {
+        Self::with_history(home_dir().map(|home| home.join(HISTORY_FILE)))
+    }
+
+    /// An editor that remembers history in `path`, or only for as long as it's around if there's no path
+    pub fn with_history(path: Option<PathBuf>) -> Self {
+        let mut history = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|file| file.lines().map(String::from).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if history.len() > HISTORY_SIZE {
+            history.drain(..history.len() - HISTORY_SIZE);
+            // Otherwise the file would only ever grow
+            if let Some(path) = &path {
+                let _ = fs::write(
+                    path,
+                    history
+                        .iter()
+                        .map(|h| format!("{}\n", h))
+                        .collect::<String>(),
+                );
+            }
+        }
+        Self { history, path }
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Reads a line, without the newline, after showing `prompt`. `complete` hands back every word that could finish
+    /// the one it's given. `None` once there's nothing left to read, and ctrl-c is an [io::ErrorKind::Interrupted] error
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        complete: impl Fn(&str) -> Vec<String>,
+    ) -> io::Result<Option<String>> {
+        let raw = match io::stdin().is_terminal() {
+            true => RawMode::enable().ok(),
+            false => None,
+        };
+        let line = match raw {
+            // `_raw` puts the terminal back once the line's been read, so whatever runs next sees it the way it was
+            Some(_raw) => self.edit(
+                prompt,
+                &mut io::stdin().lock(),
+                &mut io::stdout(),
+                &complete,
+            )?,
+            None => plain(&mut io::stdin().lock(), &mut io::stdout(), prompt)?,
+        };
+        if let Some(line) = &line {
+            self.remember(line);
+        }
+        Ok(line)
+    }
+
+    fn remember(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+        // Appended right away, so history survives the REPL getting killed.
+        // Not being able to save it isn't worth interrupting anyone over
+        if let Some(path) = &self.path {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    /// The editing itself, reading keys from `input` and drawing the line on `out`
+    fn edit(
+        &mut self,
+        prompt: &str,
+        input: &mut impl Read,
+        out: &mut impl Write,
+        complete: &impl Fn(&str) -> Vec<String>,
+    ) -> io::Result<Option<String>> {
+        let mut line = Line::default();
+        // Where up and down are in history, `history.len()` being the line that's being typed
+        let mut index = self.history.len();
+        // What was being typed before going through history, so coming back down to it gives it back
+        let mut draft = String::new();
+
+        line.render(out, prompt)?;
+        loop {
+            let Some(key) = read_key(input)? else {
+                return Ok(None);
+            };
+            match key {
+                Key::Enter => {
+                    writeln!(out)?;
+                    return Ok(Some(line.text()));
+                }
+                Key::Ctrl('c') => {
+                    writeln!(out, "^C")?;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Key::Ctrl('d') if line.chars.is_empty() => {
+                    writeln!(out)?;
+                    return Ok(None);
+                }
+                Key::Char(c) => line.insert(c),
+                Key::Backspace => line.backspace(),
+                Key::Delete | Key::Ctrl('d') => line.delete(),
+                Key::Left | Key::Ctrl('b') => line.cursor = line.cursor.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => {
+                    line.cursor = (line.cursor + 1).min(line.chars.len())
+                }
+                Key::Home | Key::Ctrl('a') => line.cursor = 0,
+                Key::End | Key::Ctrl('e') => line.cursor = line.chars.len(),
+                Key::WordLeft => line.cursor = line.word_left(),
+                Key::WordRight => line.cursor = line.word_right(),
+                Key::Ctrl('k') => line.chars.truncate(line.cursor),
+                Key::Ctrl('u') => {
+                    line.chars.drain(..line.cursor);
+                    line.cursor = 0;
+                }
+                Key::Ctrl('w') => {
+                    let start = line.word_left();
+                    line.chars.drain(start..line.cursor);
+                    line.cursor = start;
+                }
+                Key::Up | Key::Ctrl('p') if index > 0 => {
+                    if index == self.history.len() {
+                        draft = line.text();
+                    }
+                    index -= 1;
+                    line.set(&self.history[index]);
+                }
+                Key::Down | Key::Ctrl('n') if index < self.history.len() => {
+                    index += 1;
+                    line.set(self.history.get(index).unwrap_or(&draft));
+                }
+                Key::Tab => line.complete(complete, out, prompt)?,
+                Key::Ctrl('r') => match self.search(input, out)? {
+                    Search::Accept(found) => {
+                        line.set(&found);
+                        line.render(out, prompt)?;
+                        writeln!(out)?;
+                        return Ok(Some(found));
+                    }
+                    Search::Edit(found) => line.set(&found),
+                    Search::Cancel => {}
+                },
+                Key::Ctrl('l') => write!(out, "\x1b[H\x1b[2J")?,
+                _ => {}
+            }
+            line.render(out, prompt)?;
+        }
+    }
+
+    /// Ctrl-r, going back through history for lines that contain what's typed.
+    /// Enter runs the line that was found, ctrl-c and ctrl-g give up, and any other key stops to edit it
+    fn search(&self, input: &mut impl Read, out: &mut impl Write) -> io::Result<Search> {
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        loop {
+            let text = found.map_or("", |i| self.history[i].as_str());
+            write!(out, "\r(reverse-i-search)`{}': {}\x1b[K", query, text)?;
+            out.flush()?;
+            let Some(key) = read_key(input)? else {
+                return Ok(Search::Cancel);
+            };
+            match key {
+                // The match so far could still be a match, otherwise look further back
+                Key::Char(c) => {
+                    query.push(c);
+                    found = self.find(&query, found.map_or(self.history.len(), |i| i + 1));
+                }
+                Key::Backspace => {
+                    query.pop();
+                    found = self.find(&query, self.history.len());
+                }
+                // Older matches, staying put when there aren't any
+                Key::Ctrl('r') => found = found.map(|i| self.find(&query, i).unwrap_or(i)),
+                Key::Enter => {
+                    return Ok(
+                        found.map_or(Search::Cancel, |i| Search::Accept(self.history[i].clone()))
+                    )
+                }
+                Key::Ctrl('c') | Key::Ctrl('g') => return Ok(Search::Cancel),
+                _ => {
+                    return Ok(
+                        found.map_or(Search::Cancel, |i| Search::Edit(self.history[i].clone()))
+                    )
+                }
+            }
+        }
+    }
+
+    /// The newest line before `before` that contains `query`
+    fn find(&self, query: &str, before: usize) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        self.history[..before]
+            .iter()
+            .rposition(|h| h.contains(query))
+    }
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How a reverse search ended
+enum Search {
+    Accept(String),
+    Edit(String),
+    Cancel,
+}
+
+/// Reads a line the way std does, for when there's no terminal to edit on
+pub fn plain(
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+    prompt: &str,
+) -> io::Result<Option<String>> {
+    write!(out, "{}", prompt)?;
+    out.flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(Some(line))
+}
+
+/// The line being edited
+#[derive(Default)]
+struct Line {
+    chars: Vec<char>,
+    /// Index into `chars` the cursor is in front of
+    cursor: usize,
+}
+
+impl Line {
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    /// Where the word the cursor is in or right after starts, skipping any whitespace in between
+    fn word_left(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        while i > 0 && !self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        i
+    }
+
+    /// Where the word the cursor is in or right before ends
+    fn word_right(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && self.chars[i].is_whitespace() {
+            i += 1;
+        }
+        while i < self.chars.len() && !self.chars[i].is_whitespace() {
+            i += 1;
+        }
+        i
+    }
+
+    /// Finishes the identifier in front of the cursor: all the way if there's only one way to, otherwise as far as
+    /// every option agrees, listing them if that doesn't get any further
+    fn complete(
+        &mut self,
+        complete: &impl Fn(&str) -> Vec<String>,
+        out: &mut impl Write,
+        prompt: &str,
+    ) -> io::Result<()> {
+        // Same as what the scanner takes to be part of an identifier
+        let start = self.chars[..self.cursor]
+            .iter()
+            .rposition(|c| !c.is_alphanumeric())
+            .map_or(0, |i| i + 1);
+        let word = self.chars[start..self.cursor].iter().collect::<String>();
+        if word.is_empty() || !word.starts_with(char::is_alphabetic) {
+            return Ok(());
+        }
+
+        let options = complete(&word);
+        let common = options
+            .iter()
+            .skip(1)
+            .fold(options.first().cloned(), |common, option| {
+                let common = common?;
+                let len = common
+                    .chars()
+                    .zip(option.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(c, _)| c.len_utf8())
+                    .sum();
+                Some(common[..len].to_string())
+            });
+        match common {
+            // Nothing to finish it with
+            None => write!(out, "\x07")?,
+            Some(common) if common.len() > word.len() => {
+                common[word.len()..].chars().for_each(|c| self.insert(c))
+            }
+            _ if options.len() > 1 => {
+                writeln!(out)?;
+                writeln!(out, "{}", options.join("  "))?;
+                self.render(out, prompt)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Redraws the whole line, and puts the cursor back where it belongs
+    fn render(&self, out: &mut impl Write, prompt: &str) -> io::Result<()> {
+        write!(out, "\r{}{}\x1b[K", prompt, self.text())?;
+        let back = self.chars.len() - self.cursor;
+        if back > 0 {
+            write!(out, "\x1b[{}D", back)?;
+        }
+        out.flush()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    /// A letter pressed with ctrl held down, lowercase
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// Alt-b
+    WordLeft,
+    /// Alt-f
+    WordRight,
+    /// Any escape sequence that isn't one of the above
+    Unknown,
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The next key pressed, `None` once there aren't any more
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => escape(input)?,
+        1..=26 => Key::Ctrl((b'a' + byte - 1) as char),
+        0..=31 => Key::Unknown,
+        // The rest of the character's utf-8, the first byte says how much there is
+        _ => {
+            let len = match byte {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                0xc0.. => 2,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.extend(read_byte(input)?);
+            }
+            std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+                .map_or(Key::Unknown, Key::Char)
+        }
+    };
+    Ok(Some(key))
+}
+
+/// What comes after an escape, which is how terminals send every key that isn't a character
+fn escape(input: &mut impl Read) -> io::Result<Key> {
+    let key = match read_byte(input)? {
+        Some(b'b') => Key::WordLeft,
+        Some(b'f') => Key::WordRight,
+        Some(b'[' | b'O') => {
+            // Parameters, up until the byte that says which key it is
+            let mut params = Vec::new();
+            let last = loop {
+                match read_byte(input)? {
+                    Some(b @ 0x40..=0x7e) => break b,
+                    Some(b) => params.push(b),
+                    None => return Ok(Key::Unknown),
+                }
+            };
+            match (last, params.as_slice()) {
+                (b'A', _) => Key::Up,
+                (b'B', _) => Key::Down,
+                (b'C', _) => Key::Right,
+                (b'D', _) => Key::Left,
+                (b'H', _) | (b'~', b"1" | b"7") => Key::Home,
+                (b'F', _) | (b'~', b"4" | b"8") => Key::End,
+                (b'~', b"3") => Key::Delete,
+                _ => Key::Unknown,
+            }
+        }
+        _ => Key::Unknown,
+    };
+    Ok(key)
+}
+
+/// Turns off line buffering and echoing for as long as it's around, and puts back whatever the terminal was set to
+/// before once it's dropped. std has no way of doing that itself, so it goes through `stty`
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        // Ctrl-c and friends come through as keys instead of signals, the editor decides what they do
+        stty(&[
+            "-icanon", "-echo", "-isig", "-ixon", "-iexten", "min", "1", "time", "0",
+        ])?;
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    // stty works on whatever its stdin is, which has to be the terminal
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Types `keys` into an editor that remembers `history`, handing back the line it reads
+    fn edit(history: &[&str], keys: &str, complete: &[&str]) -> io::Result<Option<String>> {
+        let mut editor = Editor::with_history(None);
+        history.iter().for_each(|line| editor.remember(line));
+        let complete = |word: &str| {
+            complete
+                .iter()
+                .filter(|c| c.starts_with(word))
+                .map(|c| c.to_string())
+                .collect()
+        };
+        editor.edit("> ", &mut keys.as_bytes(), &mut Vec::new(), &complete)
+    }
+
+    fn line(history: &[&str], keys: &str) -> String {
+        edit(history, keys, &[]).unwrap().unwrap()
+    }
+
+    #[test]
+    fn editing() {
+        assert_eq!(line(&[], "ab\x1b[Dc\r"), "acb");
+        assert_eq!(line(&[], "abc\x01x\x05y\r"), "xabcy");
+        assert_eq!(line(&[], "abc\x7f\x7f\r"), "a");
+        assert_eq!(line(&[], "abc\x1b[H\x1b[3~\r"), "bc");
+        assert_eq!(line(&[], "ab cd\x01\x1bf\x0b\r"), "ab");
+        assert_eq!(line(&[], "ab cd\x17\r"), "ab ");
+        assert_eq!(line(&[], "ab cd\x1bb\x15\r"), "cd");
+        assert_eq!(line(&[], "π = 3\r"), "π = 3");
+        assert_eq!(edit(&[], "", &[]).unwrap(), None);
+        assert_eq!(edit(&[], "\x04", &[]).unwrap(), None);
+        assert_eq!(
+            edit(&[], "ab\x03", &[]).unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+    }
+
+    #[test]
+    fn history() {
+        let history = ["var a = 1;", "print a;", "print a + 1;"];
+        assert_eq!(line(&history, "\x1b[A\x1b[A\r"), "print a;");
+        assert_eq!(line(&history, "x\x1b[A\x1b[A\x1b[B\x1b[B\r"), "x");
+        assert_eq!(line(&history, "\x1b[A\x1b[A\x1b[A\x1b[A\r"), "var a = 1;");
+        // searching
+        assert_eq!(line(&history, "\x12print\r"), "print a + 1;");
+        assert_eq!(line(&history, "\x12print\x12\r"), "print a;");
+        assert_eq!(line(&history, "\x12var\x1b[C!\r"), "var a = 1;!");
+        assert_eq!(line(&history, "x\x12nope\x07\r"), "x");
+
+        // blank lines and repeats aren't worth remembering
+        let mut editor = Editor::with_history(None);
+        ["a", "a", " ", "b", "a"]
+            .iter()
+            .for_each(|line| editor.remember(line));
+        assert_eq!(editor.history(), ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn persistent_history() {
+        let path = std::env::temp_dir().join(format!("nenia-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut editor = Editor::with_history(Some(path.clone()));
+        editor.remember("var a = 1;");
+        editor.remember("print a;");
+        assert_eq!(
+            Editor::with_history(Some(path.clone())).history(),
+            ["var a = 1;", "print a;"]
+        );
+
+        // too much history gets cut back down to size
+        let lines = (0..HISTORY_SIZE + 10)
+            .map(|i| format!("{}\n", i))
+            .collect::<String>();
+        fs::write(&path, lines).unwrap();
+        let editor = Editor::with_history(Some(path.clone()));
+        assert_eq!(editor.history().len(), HISTORY_SIZE);
+        assert_eq!(editor.history()[0], "10");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap().lines().count(),
+            HISTORY_SIZE
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn completion() {
+        let names = ["print", "clock", "class", "classes"];
+        let complete = |keys| edit(&[], keys, &names).unwrap().unwrap();
+        assert_eq!(complete("pr\t 1\r"), "print 1");
+        // only as far as every option agrees
+        assert_eq!(complete("var x = c\t\r"), "var x = cl");
+        assert_eq!(complete("cl\t\r"), "cl");
+        assert_eq!(complete("cla\t\r"), "class");
+        assert_eq!(complete("(nope\t)\r"), "(nope)");
+        assert_eq!(complete("1\t\r"), "1");
+    }
+}
@@ -0,0 +1,391 @@
+//! The interactive prompt, what `nenia` runs without a script
+
+pub mod editor;
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    ast::Stmt,
+    diagnostic,
+    error::ScanErrorKind,
+    interpreter::{InterpreterVisitor, Object},
+    parser::{error::ParseErrorKind, Parser},
+    scanner::{Scanner, KEYWORDS},
+    token::TokenType,
+    Error, Options,
+};
+
+const PROMPT: &str = "> ";
+/// Shown instead of [PROMPT] while the input so far isn't finished yet
+const CONTINUATION: &str = ".. ";
+
+/// Reads lines with `read_line`, which shows the prompt it's given, until they make up something that can be run.
+/// `None` once there's nothing left to read before anything was typed. Ctrl-c throws away what's been typed so far
+pub fn read_input(
+    mut read_line: impl FnMut(&str) -> io::Result<Option<String>>,
+) -> io::Result<Option<String>> {
+    let mut src = String::new();
+    loop {
+        let prompt = if src.is_empty() { PROMPT } else { CONTINUATION };
+        match read_line(prompt) {
+            Ok(Some(line)) => {
+                src.push_str(&line);
+                src.push('\n');
+            }
+            // Running out halfway through hands over what there is, so whatever's missing gets reported
+            Ok(None) => return Ok((!src.is_empty()).then_some(src)),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                src.clear();
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+        // Commands always fit on one line, whatever their arguments look like
+        if src.starts_with(':') || !is_incomplete(&src) {
+            return Ok(Some(src));
+        }
+    }
+}
+
+/// Whether the next line could still finish `src`: a `(`, `[` or `{` is still open, a string or comment
+/// hasn't ended, or it ends in an operator that's missing its right operand.
+/// Anything else is as finished as it's going to get, even if it's wrong
+pub fn is_incomplete(src: &str) -> bool {
+    let mut scanner = Scanner::with_file(src.to_string(), "<repl>");
+    if let Err(errors) = scanner.scan_tokens() {
+        if errors.iter().any(|e| {
+            matches!(
+                e.kind,
+                ScanErrorKind::UnterminatedString(_) | ScanErrorKind::UnterminatedComment(_)
+            )
+        }) {
+            return true;
+        }
+    }
+
+    let mut depth = 0;
+    for token in &scanner.tokens {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => depth -= 1,
+            _ => {}
+        }
+        // Closing something that was never opened can't be fixed by typing more
+        if depth < 0 {
+            return false;
+        }
+    }
+    if depth > 0 {
+        return true;
+    }
+
+    // Everything's balanced, so running out of tokens where an expression should be means an operator is dangling.
+    // Running out where a `;` should be doesn't count, that's a bare expression to echo
+    match Parser::new(scanner.tokens).parse() {
+        Ok(_) => false,
+        Err(errors) => errors
+            .iter()
+            .any(|e| e.at_eof() && matches!(e.kind, ParseErrorKind::ExpectExpression(_))),
+    }
+}
+
+/// Runs every statement in `src`, handing back the value of the last one if it's an expression.
+/// The `;` after that expression is optional, since it's the one thing typed at a prompt that's never followed by
+/// anything else
+pub fn eval(src: String, interpreter: &mut InterpreterVisitor) -> Result<Option<Object>, Error> {
+    let (statements, _) = parse_input(src)?;
+    run_statements(&statements, interpreter)
+}
+
+/// Parses `src`, adding the `;` [eval] lets the last expression leave out if it has to.
+/// Also hands back the source that actually parsed, with that `;`
+fn parse_input(src: String) -> Result<(Vec<Stmt>, String), Error> {
+    match crate::parse(src.clone(), "<repl>") {
+        Ok(statements) => Ok((statements, src)),
+        // Only the `;` being missing is worth a second try, anything else reports what was actually typed
+        Err(e) => {
+            let retried = format!("{};", src.trim_end());
+            match crate::parse(retried.clone(), "<repl>") {
+                Ok(statements) => Ok((statements, retried)),
+                Err(_) => {
+                    // The second try replaced the source the errors point into
+                    diagnostic::add_source("<repl>".into(), &src);
+                    Err(e)
+                }
+            }
+        }
+    }
+}
+
+fn run_statements(
+    statements: &[Stmt],
+    interpreter: &mut InterpreterVisitor,
+) -> Result<Option<Object>, Error> {
+    let Some((last, rest)) = statements.split_last() else {
+        return Ok(None);
+    };
+    rest.iter().try_for_each(|s| interpreter.execute(s))?;
+    match last {
+        Stmt::Expr(expr) => Ok(Some(interpreter.evaluate(expr)?)),
+        s => {
+            interpreter.execute(s)?;
+            Ok(None)
+        }
+    }
+}
+
+const HELP: &str = "\
+:tokens <src>   show the tokens `src` scans to
+:ast <src>      show the syntax tree `src` parses to
+:env            list every variable in scope, and its value
+:load <file>    run a file in this session
+:save <file>    write every input that ran without errors to a file
+:reset          forget every variable and start over
+:help           show this
+exit            leave, so does an empty line";
+
+/// Everything a session at the prompt remembers between inputs
+pub struct Repl {
+    interpreter: InterpreterVisitor,
+    options: Options,
+    /// Every input that ran without errors, in order, which is what `:save` writes out.
+    /// An input that fails partway isn't in here, even though whatever ran before the error still happened
+    history: Vec<String>,
+}
+
+impl Repl {
+    pub fn new(options: &Options) -> Result<Self, Error> {
+        Ok(Self {
+            interpreter: options.interpreter()?,
+            options: options.clone(),
+            history: Vec::new(),
+        })
+    }
+
+    /// Runs one input from [read_input], either a `:command` or code, writing what it has to show to `out`.
+    /// Errors are handed back to be reported, the session carries on either way
+    pub fn handle(&mut self, input: String, out: &mut impl Write) -> Result<(), Error> {
+        if let Some(command) = input.trim().strip_prefix(':') {
+            return self.command(command, out);
+        }
+        let (statements, src) = parse_input(input)?;
+        match run_statements(&statements, &mut self.interpreter)? {
+            // nil is what everything that doesn't have a value evaluates to, echoing it would just be noise
+            Some(Object::Nil) | None => {}
+            Some(value) => writeln!(out, "{}", value)?,
+        }
+        self.remember(src);
+        Ok(())
+    }
+
+    /// Every keyword and variable in scope that starts with `word`, for the editor to complete it with
+    pub fn completions(&self, word: &str) -> Vec<String> {
+        let variables = self.interpreter.visible_bindings().into_iter();
+        let mut completions = KEYWORDS
+            .iter()
+            .map(|keyword| keyword.to_string())
+            .chain(variables.map(|(name, _)| name.to_string()))
+            .filter(|name| name.starts_with(word))
+            .collect::<Vec<_>>();
+        completions.sort();
+        completions.dedup();
+        completions
+    }
+
+    fn command(&mut self, command: &str, out: &mut impl Write) -> Result<(), Error> {
+        let (name, arg) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, arg)| (name, arg.trim()));
+        match (name, arg) {
+            ("tokens", src) => {
+                let mut scanner = Scanner::with_file(src.to_string(), "<repl>");
+                let scanned = scanner.scan_tokens();
+                for token in &scanner.tokens {
+                    let at = format!("{}:{}", token.span.line, token.span.column);
+                    let token_type = format!("{:?}", token.token_type);
+                    writeln!(out, "{:<6} {:<14} {}", at, token_type, token.lexeme)?;
+                }
+                scanned?;
+            }
+            ("ast", src) => {
+                for statement in crate::parse(src.to_string(), "<repl>")? {
+                    writeln!(out, "{:#?}", statement)?;
+                }
+            }
+            ("env", "") => {
+                for (name, value) in self.interpreter.visible_bindings() {
+                    writeln!(out, "{} = {}", name, value)?;
+                }
+            }
+            ("load", file) if !file.is_empty() => {
+                self.interpreter.load(Path::new(file))?;
+                // Saving the session should give back everything it can see, so the file itself is what's remembered
+                self.remember(fs::read_to_string(file).map_err(|e| Error::Read(file.into(), e))?);
+            }
+            ("save", file) if !file.is_empty() => {
+                fs::write(file, self.history.concat()).map_err(|e| Error::Write(file.into(), e))?
+            }
+            ("reset", "") => {
+                self.interpreter = self.options.interpreter()?;
+                self.history.clear();
+            }
+            ("help", "") => writeln!(out, "{}", HELP)?,
+            _ => writeln!(out, "unknown command `:{}`, try `:help`", command)?,
+        }
+        Ok(())
+    }
+
+    fn remember(&mut self, mut input: String) {
+        if !input.ends_with('\n') {
+            input.push('\n');
+        }
+        self.history.push(input);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn incomplete_input() {
+        for src in [
+            "while (x < 3) {",
+            "print (1 +\n",
+            "var list = [1,\n2,",
+            "class A {\n  m() {\n    print 1;\n  }\n",
+            "print \"a string\nthat goes on",
+            "/* a comment /* nested */",
+            "var a = 1 +",
+            "print a and",
+            "a.b =",
+        ] {
+            assert!(is_incomplete(src), "{:?}", src);
+        }
+        for src in [
+            "",
+            "print 1;",
+            "while (x < 3) {\n  x = x + 1;\n}",
+            "1 + 2",
+            "print \"done\";",
+            "/* done */",
+            // errors that another line can't fix are run, so they get reported
+            "print 1);",
+            "}",
+            "var = 1;",
+        ] {
+            assert!(!is_incomplete(src), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn continuation_prompts() {
+        let mut input = "if (true) {\n  print 1;\n}\nprint 2;\n".as_bytes();
+        let mut output = Vec::new();
+        let mut read = || read_input(|prompt| editor::plain(&mut input, &mut output, prompt));
+        assert_eq!(
+            read().unwrap().as_deref(),
+            Some("if (true) {\n  print 1;\n}\n")
+        );
+        assert_eq!(read().unwrap().as_deref(), Some("print 2;\n"));
+        assert_eq!(read().unwrap(), None);
+        assert_eq!(String::from_utf8(output).unwrap(), "> .. .. > > ");
+
+        // ctrl-c starts over
+        let mut lines = [
+            Ok(Some("{".into())),
+            Err(io::ErrorKind::Interrupted.into()),
+            Ok(Some("1".into())),
+        ]
+        .into_iter();
+        assert_eq!(
+            read_input(|_| lines.next().unwrap()).unwrap().as_deref(),
+            Some("1\n")
+        );
+    }
+
+    #[test]
+    fn commands() {
+        let mut repl = Repl::new(&Options::default()).unwrap();
+        let mut run = |input: &str| {
+            let mut out = Vec::new();
+            repl.handle(input.into(), &mut out)
+                .map(|_| String::from_utf8(out).unwrap())
+        };
+
+        assert_eq!(
+            run(":tokens var a = \"b\";").unwrap(),
+            "\
+1:1    Var            var
+1:5    Identifier     a
+1:7    Equal          =
+1:9    String         \"b\"
+1:12   Semicolon      ;
+1:13   Eof            \n"
+        );
+        assert!(run(":ast print 1;")
+            .unwrap()
+            .starts_with("Print(\n    Literal(\n        Number(\n            1.0,"));
+        assert!(run(":ast print (1;").is_err());
+        assert_eq!(
+            run(":nope").unwrap(),
+            "unknown command `:nope`, try `:help`\n"
+        );
+
+        run("var a = 1;").unwrap();
+        assert!(run("a = nope;").is_err());
+        run("func f() {}").unwrap();
+        let env = run(":env").unwrap();
+        assert!(
+            env.contains("a = 1\nclock = <native fn clock>\nf = <fn f>\n"),
+            "{}",
+            env
+        );
+
+        // saved with the `;` it was run with, or the file wouldn't load
+        assert_eq!(run("a + 1").unwrap(), "2\n");
+        // `b` is defined, but the input as a whole failed, so it's not saved
+        assert!(run("var b = 2; print nope;").is_err());
+        // only what ran without errors is saved, and loading it back gives the same session
+        let file = std::env::temp_dir().join(format!("nenia-repl-{}.lox", std::process::id()));
+        let file = file.to_str().unwrap();
+        run(&format!(":save {}", file)).unwrap();
+        assert_eq!(
+            fs::read_to_string(file).unwrap(),
+            "var a = 1;\nfunc f() {}\na + 1;\n"
+        );
+        run(":reset").unwrap();
+        assert!(run("a").is_err());
+        run(&format!(":load {}", file)).unwrap();
+        assert_eq!(run("a").unwrap(), "1\n");
+        assert!(run("b").is_err());
+        assert!(run(":load /does/not/exist.lox").is_err());
+        assert_eq!(
+            repl.completions("f"),
+            ["f", "false", "finally", "for", "func"]
+        );
+        assert_eq!(repl.completions("cl"), ["class", "clock"]);
+    }
+
+    #[test]
+    fn evaluates() {
+        let mut interpreter = InterpreterVisitor::new();
+        let mut eval =
+            |src: &str| eval(src.into(), &mut interpreter).map(|v| v.map(|v| v.to_string()));
+        assert_eq!(
+            eval("var a = 1; a = a + 1; a").unwrap().as_deref(),
+            Some("2")
+        );
+        assert_eq!(eval("\"a${a}\";").unwrap().as_deref(), Some("a2"));
+        assert_eq!(eval("var b = a;").unwrap(), None);
+        // an error only loses what came after it
+        assert!(eval("b = 3; nope; b = 4;").is_err());
+        assert_eq!(eval("b").unwrap().as_deref(), Some("3"));
+        assert!(eval("print (b;").is_err());
+        assert_eq!(eval("").unwrap(), None);
+    }
+}
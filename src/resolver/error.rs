@@ -0,0 +1,98 @@
+use crate::{
+    diagnostic::Diagnostic,
+    token::{Span, Token},
+};
+use std::{error, fmt};
+
+#[derive(Debug)]
+pub struct ResolveError {
+    pub kind: ResolveErrorKind,
+}
+
+impl error::Error for ResolveError {}
+
+impl ResolveError {
+    pub fn new(kind: ResolveErrorKind) -> ResolveError {
+        ResolveError { kind }
+    }
+
+    pub fn span(&self) -> Span {
+        match &self.kind {
+            ResolveErrorKind::ReadInOwnInitializer(t)
+            | ResolveErrorKind::AlreadyDeclared(t)
+            | ResolveErrorKind::ReturnOutsideFunction(t)
+            | ResolveErrorKind::ReturnFromInitializer(t)
+            | ResolveErrorKind::ThisOutsideClass(t) => t.span,
+        }
+    }
+
+    /// See [crate::Error::code]
+    pub fn code(&self) -> &'static str {
+        match &self.kind {
+            ResolveErrorKind::ReadInOwnInitializer(_) => "E0301",
+            ResolveErrorKind::AlreadyDeclared(_) => "E0302",
+            ResolveErrorKind::ReturnOutsideFunction(_) => "E0303",
+            ResolveErrorKind::ReturnFromInitializer(_) => "E0304",
+            ResolveErrorKind::ThisOutsideClass(_) => "E0305",
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.kind.to_string()).with_span(Some(self.span()));
+        match &self.kind {
+            ResolveErrorKind::ReadInOwnInitializer(_) => diagnostic
+                .with_label("read here")
+                .with_note("the new variable shadows any outer one with the same name as soon as it's declared"),
+            ResolveErrorKind::AlreadyDeclared(_) => diagnostic.with_label("declared again here"),
+            ResolveErrorKind::ReturnOutsideFunction(_) => {
+                diagnostic.with_label("not inside of a function")
+            }
+            ResolveErrorKind::ReturnFromInitializer(_) => diagnostic
+                .with_label("inside of `init`")
+                .with_help("`init` always hands back `this`, use a bare `return;` to leave early"),
+            ResolveErrorKind::ThisOutsideClass(_) => diagnostic.with_label("not inside of a method"),
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span(), self.kind)
+    }
+}
+
+/// Just the message, without where it happened
+impl fmt::Display for ResolveErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveErrorKind::ReadInOwnInitializer(t) => write!(
+                f,
+                "can't read local variable `{}` in its own initializer",
+                t.lexeme
+            ),
+            ResolveErrorKind::AlreadyDeclared(t) => write!(
+                f,
+                "a variable named `{}` is already declared in this scope",
+                t.lexeme
+            ),
+            ResolveErrorKind::ReturnOutsideFunction(_) => {
+                write!(f, "can't `return` from top-level code")
+            }
+            ResolveErrorKind::ReturnFromInitializer(_) => {
+                write!(f, "can't return a value from an initializer")
+            }
+            ResolveErrorKind::ThisOutsideClass(_) => {
+                write!(f, "can't use `this` outside of a class")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ResolveErrorKind {
+    ReadInOwnInitializer(Token),
+    AlreadyDeclared(Token),
+    ReturnOutsideFunction(Token),
+    ReturnFromInitializer(Token),
+    ThisOutsideClass(Token),
+}
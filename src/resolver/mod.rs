@@ -0,0 +1,443 @@
+pub mod error;
+
+use std::{cell::Cell, collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::{Expr, FunctionDecl, Stmt},
+    interner::Symbol,
+    interpreter::{ExprVisitor, StatementVisitor},
+    token::{Literal, Token},
+};
+
+use self::error::{ResolveError, ResolveErrorKind};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// What kind of function body the resolver is currently inside of, so we can tell if a `return` is allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// Whether the resolver is inside of a class body, so we can tell if `this` is allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClassType {
+    None,
+    Class,
+}
+
+/// Runs over the tree once before the interpreter does, and figures out how many environments up every
+/// local variable lives. Globals aren't tracked, so any variable that isn't found in a scope is assumed to be global
+pub struct Resolver {
+    /// Stack of block scopes, each mapping a name to whether its initializer has finished resolving
+    scopes: Vec<HashMap<Symbol, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Stmt]) -> Result<()> {
+        statements.iter().try_for_each(|s| s.accept(self))
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<()> {
+        match expr {
+            // e.g. `var a;` has nothing to resolve
+            Expr::Null => Ok(()),
+            _ => expr.accept(self),
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Adds `name` to the innermost scope, but marks it as not ready to be read yet
+    fn declare(&mut self, name: &Token) -> Result<()> {
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(&name.lexeme) {
+                return Err(Box::new(ResolveError::new(
+                    ResolveErrorKind::AlreadyDeclared(name.clone()),
+                )));
+            }
+            scope.insert(name.lexeme, false);
+        }
+        Ok(())
+    }
+
+    /// Marks `name` as fully initialized, so it can be read from now on
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme, true);
+        }
+    }
+
+    /// Looks for `name` starting from the innermost scope, and stores how far out it was found
+    fn resolve_local(&self, name: &Token, depth: &Cell<Option<usize>>) {
+        depth.set(
+            self.scopes
+                .iter()
+                .rev()
+                .position(|scope| scope.contains_key(&name.lexeme)),
+        );
+    }
+
+    /// Parameters and the body share one scope, same as the environment [crate::interpreter::callable::Function::call] creates
+    fn resolve_function(
+        &mut self,
+        declaration: &FunctionDecl,
+        function_type: FunctionType,
+    ) -> Result<()> {
+        let enclosing = self.current_function;
+        self.current_function = function_type;
+
+        self.begin_scope();
+        let res = declaration
+            .params
+            .iter()
+            .try_for_each(|param| {
+                self.declare(param)?;
+                self.define(param);
+                Ok(())
+            })
+            .and_then(|_| self.resolve(&declaration.body));
+        self.end_scope();
+
+        self.current_function = enclosing;
+        res
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExprVisitor<()> for Resolver {
+    fn visit_binary(&mut self, left: &Expr, _operator: &Token, right: &Expr) -> Result<()> {
+        self.resolve_expr(left)?;
+        self.resolve_expr(right)
+    }
+
+    fn visit_unary(&mut self, _operator: &Token, right: &Expr) -> Result<()> {
+        self.resolve_expr(right)
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> Result<()> {
+        self.resolve_expr(expr)
+    }
+
+    fn visit_literal(&self, _expr: &Literal) -> Result<()> {
+        Ok(())
+    }
+
+    fn visit_variable(&self, name: &Token, depth: &Cell<Option<usize>>) -> Result<()> {
+        // declared in this very scope, but its initializer isn't done yet, e.g. `var a = a;`
+        if let Some(false) = self.scopes.last().and_then(|scope| scope.get(&name.lexeme)) {
+            return Err(Box::new(ResolveError::new(
+                ResolveErrorKind::ReadInOwnInitializer(name.clone()),
+            )));
+        }
+
+        self.resolve_local(name, depth);
+        Ok(())
+    }
+
+    fn visit_assign_expr(
+        &mut self,
+        name: &Token,
+        value: &Expr,
+        depth: &Cell<Option<usize>>,
+    ) -> Result<()> {
+        self.resolve_expr(value)?;
+        self.resolve_local(name, depth);
+        Ok(())
+    }
+
+    fn visit_logical_expr(&mut self, left: &Expr, _operator: &Token, right: &Expr) -> Result<()> {
+        self.resolve_expr(left)?;
+        self.resolve_expr(right)
+    }
+
+    fn visit_call_expr(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) -> Result<()> {
+        self.resolve_expr(callee)?;
+        arguments.iter().try_for_each(|a| self.resolve_expr(a))
+    }
+
+    fn visit_ternary_expr(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Result<()> {
+        self.resolve_expr(condition)?;
+        self.resolve_expr(then_branch)?;
+        self.resolve_expr(else_branch)
+    }
+
+    fn visit_update_expr(
+        &mut self,
+        name: &Token,
+        _operator: &Token,
+        _prefix: bool,
+        depth: &Cell<Option<usize>>,
+    ) -> Result<()> {
+        self.resolve_local(name, depth);
+        Ok(())
+    }
+
+    /// Properties are looked up dynamically, so only the object needs resolving
+    fn visit_get_expr(&mut self, object: &Expr, _name: &Token) -> Result<()> {
+        self.resolve_expr(object)
+    }
+
+    fn visit_set_expr(&mut self, object: &Expr, _name: &Token, value: &Expr) -> Result<()> {
+        self.resolve_expr(value)?;
+        self.resolve_expr(object)
+    }
+
+    fn visit_list_expr(&mut self, _bracket: &Token, elements: &[Expr]) -> Result<()> {
+        elements.iter().try_for_each(|e| self.resolve_expr(e))
+    }
+
+    fn visit_map_expr(&mut self, _brace: &Token, entries: &[(Expr, Expr)]) -> Result<()> {
+        entries.iter().try_for_each(|(key, value)| {
+            self.resolve_expr(key)?;
+            self.resolve_expr(value)
+        })
+    }
+
+    fn visit_index_expr(&mut self, object: &Expr, _bracket: &Token, index: &Expr) -> Result<()> {
+        self.resolve_expr(object)?;
+        self.resolve_expr(index)
+    }
+
+    fn visit_set_index_expr(
+        &mut self,
+        object: &Expr,
+        _bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<()> {
+        self.resolve_expr(value)?;
+        self.resolve_expr(object)?;
+        self.resolve_expr(index)
+    }
+
+    fn visit_this_expr(&self, keyword: &Token, depth: &Cell<Option<usize>>) -> Result<()> {
+        if self.current_class == ClassType::None {
+            return Err(Box::new(ResolveError::new(
+                ResolveErrorKind::ThisOutsideClass(keyword.clone()),
+            )));
+        }
+        self.resolve_local(keyword, depth);
+        Ok(())
+    }
+}
+
+impl StatementVisitor for Resolver {
+    fn visit_expression_stmt(&mut self, stmt: &Expr) -> Result<()> {
+        self.resolve_expr(stmt)
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &Expr) -> Result<()> {
+        self.resolve_expr(stmt)
+    }
+
+    /// Declaring and defining are split up so that the initializer can't see the variable it's initializing
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Expr) -> Result<()> {
+        self.declare(name)?;
+        self.resolve_expr(initializer)?;
+        self.define(name);
+        Ok(())
+    }
+
+    fn visit_block(&mut self, statements: &[Stmt]) -> Result<()> {
+        self.begin_scope();
+        let res = self.resolve(statements);
+        self.end_scope();
+        res
+    }
+
+    /// Unlike the interpreter, both branches are always resolved
+    fn visit_if_stmt(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Result<()> {
+        self.resolve_expr(condition)?;
+        then_branch.accept(self)?;
+        if let Some(stmt) = else_branch {
+            stmt.accept(self)?;
+        }
+        Ok(())
+    }
+
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+    ) -> Result<()> {
+        self.resolve_expr(condition)?;
+        body.accept(self)?;
+        increment.map_or(Ok(()), |increment| self.resolve_expr(increment))
+    }
+
+    /// The name is defined before resolving the body, so functions can call themselves recursively
+    fn visit_function_stmt(&mut self, declaration: &Rc<FunctionDecl>) -> Result<()> {
+        self.declare(&declaration.name)?;
+        self.define(&declaration.name);
+
+        self.resolve_function(declaration, FunctionType::Function)
+    }
+
+    /// Methods get an extra scope around them that holds `this`, matching the environment [crate::interpreter::callable::Function::bind] creates
+    fn visit_class_stmt(&mut self, name: &Token, methods: &[Rc<FunctionDecl>]) -> Result<()> {
+        let enclosing = self.current_class;
+        self.current_class = ClassType::Class;
+
+        self.declare(name)?;
+        self.define(name);
+
+        self.begin_scope();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(Symbol::intern("this"), true);
+
+        let res = methods.iter().try_for_each(|method| {
+            let function_type = if method.name.lexeme == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, function_type)
+        });
+
+        self.end_scope();
+        self.current_class = enclosing;
+        res
+    }
+
+    fn visit_return_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<()> {
+        match self.current_function {
+            FunctionType::None => {
+                return Err(Box::new(ResolveError::new(
+                    ResolveErrorKind::ReturnOutsideFunction(keyword.clone()),
+                )))
+            }
+            // `init` always returns `this`, so it can't return anything else
+            FunctionType::Initializer if *value != Expr::Null => {
+                return Err(Box::new(ResolveError::new(
+                    ResolveErrorKind::ReturnFromInitializer(keyword.clone()),
+                )))
+            }
+            _ => {}
+        }
+        self.resolve_expr(value)
+    }
+
+    // The parser already made sure these are inside of a loop, and there's nothing to resolve
+    fn visit_break_stmt(&mut self, _keyword: &Token) -> Result<()> {
+        Ok(())
+    }
+
+    fn visit_continue_stmt(&mut self, _keyword: &Token) -> Result<()> {
+        Ok(())
+    }
+
+    /// The namespace is bound just like a variable would be
+    fn visit_import_stmt(&mut self, _path: &Token, name: &Token) -> Result<()> {
+        self.declare(name)?;
+        self.define(name);
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&mut self, _keyword: &Token, value: &Expr) -> Result<()> {
+        self.resolve_expr(value)
+    }
+
+    /// The caught error lives in its own scope, together with everything the handler declares,
+    /// matching the environment the interpreter defines it in
+    fn visit_try_stmt(
+        &mut self,
+        body: &[Stmt],
+        catch: Option<&(Token, Vec<Stmt>)>,
+        finally: Option<&[Stmt]>,
+    ) -> Result<()> {
+        self.visit_block(body)?;
+        if let Some((name, handler)) = catch {
+            self.begin_scope();
+            let res = self.declare(name).and_then(|()| {
+                self.define(name);
+                self.resolve(handler)
+            });
+            self.end_scope();
+            res?;
+        }
+        finally.map_or(Ok(()), |finally| self.visit_block(finally))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resolve(src: &str) -> std::result::Result<Vec<Stmt>, crate::Error> {
+        let statements = crate::parse(src.to_string(), "<script>")?;
+        Resolver::new().resolve(&statements)?;
+        Ok(statements)
+    }
+
+    #[test]
+    fn depths() {
+        let statements = resolve("var g; { var a; { var b; a; b; g; } }").unwrap();
+        let inner = match &statements[1] {
+            Stmt::Block { statements } => match &statements[1] {
+                Stmt::Block { statements } => statements,
+                s => panic!("expected block, found {:?}", s),
+            },
+            s => panic!("expected block, found {:?}", s),
+        };
+        let depths = inner[1..]
+            .iter()
+            .map(|s| match s {
+                Stmt::Expr(Expr::Variable { depth, .. }) => depth.get(),
+                s => panic!("expected variable, found {:?}", s),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(depths, vec![Some(1), Some(0), None]);
+    }
+
+    #[test]
+    fn static_errors() {
+        assert!(resolve("{ var a = 1; { var a = a; } }").is_err());
+        assert!(resolve("{ var a = 1; var a = 2; }").is_err());
+        assert!(resolve("func f(a, a) {}").is_err());
+        assert!(resolve("return 1;").is_err());
+        assert!(resolve("print this;").is_err());
+        assert!(resolve("func f() { return this; }").is_err());
+        assert!(resolve("class A { init() { return 1; } }").is_err());
+        assert!(resolve("class A { init() { return; } m() { return this; } }").is_ok());
+        // globals are allowed to be redeclared, and shadowing in a new block is fine
+        assert!(resolve("var a = 1; var a = a; { var a = 2; { var a = 3; } }").is_ok());
+    }
+}
@@ -1,28 +1,65 @@
 use crate::{
-    error::{ErrorKind, Position},
-    token::{Literal, Token, TokenType},
+    error::{ScanError, ScanErrorKind},
+    interner::Symbol,
+    token::{Literal, Span, Token, TokenType},
 };
 use core::panic;
-use std::error;
 pub struct Scanner {
     chars: Vec<char>,
+    /// Byte offset of every character in `chars`, plus one for the end of the source
+    offsets: Vec<usize>,
+    /// Name of the file being scanned, every token's span points into it
+    file: Symbol,
     pub tokens: Vec<Token>,
+    /// Where every comment is, they aren't tokens but the formatter still has to put them back
+    pub comments: Vec<Span>,
     /// First charcter in the lexeme being scanned
     start: usize,
     /// The character considered
     current: usize,
     /// What src line we're on
     line: usize,
+    /// Index of the first character of the current line, so columns can be worked out
+    line_start: usize,
+    /// Line and column of `start`, a token can span more than one line so they're remembered before scanning it
+    start_line: usize,
+    start_column: usize,
+    /// One entry for every `${` we're inside of, counting the `{`s opened since, so we know which `}` ends it.
+    /// Also remembers where its string started, in case it never ends
+    interpolations: Vec<(usize, Span)>,
+    /// Everything that went wrong so far, scanning carries on after an error so they can all be reported at once
+    errors: Vec<ScanError>,
 }
 impl Scanner {
+    /// A scanner for source that doesn't come from a file, e.g. a test
     pub fn new(src: String) -> Self {
+        Self::with_file(src, "<script>")
+    }
+
+    /// A scanner for the source of `file`, which is also remembered so diagnostics can show excerpts of it
+    pub fn with_file(src: String, file: &str) -> Self {
+        let file = Symbol::intern(file);
+        crate::diagnostic::add_source(file, &src);
         let chars = src.chars().collect::<Vec<char>>();
+        let offsets = src
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain([src.len()])
+            .collect();
         Self {
             chars,
+            offsets,
+            file,
             tokens: Vec::new(),
+            comments: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            interpolations: Vec::new(),
+            errors: Vec::new(),
         }
     }
 
@@ -30,60 +67,130 @@ impl Scanner {
         self.current >= self.chars.len()
     }
 
-    pub fn scan_tokens(&mut self) -> Result<(), Box<dyn error::Error>> {
+    /// Scans the whole source into `tokens`. A bad character or escape doesn't stop the scanner,
+    /// it skips over it and keeps going, so the error it hands back has everything that went wrong in it.
+    /// Even then `tokens` ends in an [TokenType::Eof], so the parser can look for errors of its own
+    pub fn scan_tokens(&mut self) -> Result<(), Vec<ScanError>> {
         while !self.is_at_end() {
             // Always remember the start position of the token, it's not modified anywhere else but here
             self.start = self.current;
-            self.scan_token()?;
+            self.start_line = self.line;
+            self.start_column = self.current - self.line_start + 1;
+            if let Err(e) = self.scan_token() {
+                self.errors.push(*e);
+            }
         }
 
+        // A `${` that's never closed means the string it's in never ends either, and neither does any string around it.
+        // Only the outermost one is reported, the others would just be the same mistake again
+        if let Some((_, span)) = self.interpolations.first() {
+            self.errors
+                .push(ScanError::new(ScanErrorKind::UnterminatedString(*span)));
+        }
+
+        let eof = self.span_from(self.current);
         self.tokens.push(Token::new(
             TokenType::Eof,
-            String::new(),
+            Symbol::intern(""),
             Literal::Nil,
-            self.line,
+            eof,
         ));
 
-        Ok(())
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(std::mem::take(&mut self.errors)),
+        }
     }
 
-    fn advance(&mut self) -> &char {
-        let char = &self.chars[self.current];
+    /// Consumes the current character, every newline in the source passes through here so this is where lines are counted
+    fn advance(&mut self) -> char {
+        let char = self.chars[self.current];
         self.current += 1;
+        if char == '\n' {
+            self.line += 1;
+            self.line_start = self.current;
+        }
         char
     }
 
+    /// Span of the token being scanned, from `start` up to `current`
+    fn token_span(&self) -> Span {
+        Span {
+            file: self.file,
+            start: self.offsets[self.start],
+            end: self.offsets[self.current],
+            line: self.start_line,
+            column: self.start_column,
+        }
+    }
+
+    /// Span from `start` up to `current`, where `start` is on the current line
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            file: self.file,
+            start: self.offsets[start],
+            end: self.offsets[self.current],
+            line: self.line,
+            column: start - self.line_start + 1,
+        }
+    }
+
     fn add_token_literal(&mut self, token_type: TokenType, literal: Literal) {
         let text = self.chars.substring(self.start, self.current);
+        let span = self.token_span();
         self.tokens
-            .push(Token::new(token_type, text, literal, self.line));
+            .push(Token::new(token_type, Symbol::intern(&text), literal, span));
     }
 
     fn add_token(&mut self, token_type: TokenType) {
         self.add_token_literal(token_type, Literal::Nil);
     }
 
-    fn scan_token(&mut self) -> Result<(), Box<dyn error::Error>> {
-        match *self.advance() {
+    fn scan_token(&mut self) -> Result<(), Box<ScanError>> {
+        match self.advance() {
+            // Braces have to be counted while inside of a `${..}`, the `}` matching the `${` picks the string back up
+            '{' => {
+                if let Some((depth, _)) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace);
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some((0, _)) => {
+                    // Nothing got scanned since the `${`, so the string is picked back up as if there wasn't one
+                    if self.tokens.last().map(|t| t.token_type) == Some(TokenType::Interpolation) {
+                        self.tokens.pop();
+                        let span = self.token_span();
+                        self.errors
+                            .push(ScanError::new(ScanErrorKind::EmptyInterpolation(span)));
+                    }
+                    let (_, position) = self.interpolations.pop().unwrap();
+                    self.string(position)?;
+                }
+                Some((depth, _)) => {
+                    *depth -= 1;
+                    self.add_token(TokenType::RightBrace);
+                }
+                None => self.add_token(TokenType::RightBrace),
+            },
             // fully single characters
-            s @ ('(' | ')' | '{' | '}' | ',' | '.' | '-' | '+' | ';' | '*') => {
+            s @ ('(' | ')' | '[' | ']' | ',' | '.' | ';' | '?' | ':') => {
                 self.add_token(match s {
                     '(' => TokenType::LeftParen,
                     ')' => TokenType::RightParen,
-                    '{' => TokenType::LeftBrace,
-                    '}' => TokenType::RightBrace,
+                    '[' => TokenType::LeftBracket,
+                    ']' => TokenType::RightBracket,
                     ',' => TokenType::Comma,
                     '.' => TokenType::Dot,
-                    '-' => TokenType::Minus,
-                    '+' => TokenType::Plus,
                     ';' => TokenType::Semicolon,
-                    '*' => TokenType::Star,
+                    '?' => TokenType::Question,
+                    ':' => TokenType::Colon,
                     _ => panic!(),
                 });
             }
             // possible doubled chars
             // looks really ugly and we could combine them but I can't think of a way not to use doubled match statements
-            d @ ('!' | '=' | '<' | '>') => {
+            d @ ('!' | '=' | '<' | '>' | '+' | '-' | '*') => {
                 let res = match d {
                     '!' => {
                         if self.next_is('=') {
@@ -113,16 +220,39 @@ impl Scanner {
                             TokenType::Greater
                         }
                     }
+                    '+' => {
+                        if self.next_is('=') {
+                            TokenType::PlusEqual
+                        } else if self.next_is('+') {
+                            TokenType::PlusPlus
+                        } else {
+                            TokenType::Plus
+                        }
+                    }
+                    '-' => {
+                        if self.next_is('=') {
+                            TokenType::MinusEqual
+                        } else if self.next_is('-') {
+                            TokenType::MinusMinus
+                        } else {
+                            TokenType::Minus
+                        }
+                    }
+                    '*' => {
+                        if self.next_is('=') {
+                            TokenType::StarEqual
+                        } else {
+                            TokenType::Star
+                        }
+                    }
                     _ => {
                         panic!()
                     }
                 };
                 self.add_token(res);
             }
-            // any white space
+            // any white space, newlines were already counted by `advance`
             w if w.is_whitespace() => {}
-            // newline
-            '\n' => self.line += 1,
             // special character, could be divide, but also could be a comment
             '/' => match self.peek() {
                 // Single line comment
@@ -132,80 +262,59 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    self.comments.push(self.token_span());
                 }
                 // Multiline line comment
                 '*' => {
-                    let start_line = self.line;
+                    let start = self.token_span();
                     // initiate stack
                     let mut stack = 1;
 
                     // As long as our stack isn't empty
                     while stack != 0 && !self.is_at_end() {
                         match self.peek() {
-                            // if there's a new line add aline
-                            '\n' => self.line += 1,
                             // if it's a star, check if it's an end comment
-                            '*' => {
-                                if self.peek_next() == '/' {
-                                    stack -= 1;
-                                    self.advance();
-                                }
+                            '*' if self.peek_next() == '/' => {
+                                stack -= 1;
+                                self.advance();
                             }
                             // if it's a slash, check if it's a begin comment
-                            '/' => {
-                                if self.peek_next() == '*' {
-                                    stack += 1;
-                                    self.advance();
-                                }
+                            '/' if self.peek_next() == '*' => {
+                                stack += 1;
+                                self.advance();
                             }
                             _ => {}
                         }
                         // Advance regardless
                         self.advance();
                     }
-                    if self.is_at_end() {
-                        return Err(Box::new(crate::error::Error::new(
-                            ErrorKind::UnterminatedComment(Position::new(start_line, self.start)),
+                    // A comment can end right at the end of the file, it just has to have been closed
+                    if stack != 0 {
+                        return Err(Box::new(ScanError::new(
+                            ScanErrorKind::UnterminatedComment(start),
                         )));
                     }
+                    self.comments.push(self.token_span());
+                }
+                '=' => {
+                    self.advance();
+                    self.add_token(TokenType::SlashEqual)
                 }
                 _ => self.add_token(TokenType::Slash),
             },
             // string literals
-            '"' => {
-                let start_line = self.line;
-                while self.peek() != '"' && !self.is_at_end() {
-                    // newlines inside "" don't count
-                    if self.peek() == '\n' {
-                        self.line += 1;
-                    }
-                    // remember, advance only changes current, not start
-                    self.advance();
-                }
-                // check if string terminates at end of file w/o closing
-                if self.is_at_end() {
-                    return Err(Box::new(crate::error::Error::new(
-                        ErrorKind::UnterminatedString(Position::new(start_line, self.start)),
-                    )));
-                }
-                // advance one more time, since we stop at the quote
-                self.advance();
-                // trim the quotes, and add the token
-                // substring start + 1 end - 1
-                let text = self.chars.substring(self.start + 1, self.current - 1);
-                self.add_token_literal(TokenType::String, Literal::String(text));
-            }
+            '"' => self.string(self.token_span())?,
             // digit
-            n if n.is_digit(10) => {
-                while self.peek().is_digit(10) {
+            n if n.is_ascii_digit() => {
+                while self.peek().is_ascii_digit() {
                     self.advance();
                 }
                 // if fraction continue, also 0. doesn't work, it has to be 0.(digit+)
-                if self.peek() == '.' && self.peek_next().is_digit(10) {
+                if self.peek() == '.' && self.peek_next().is_ascii_digit() {
                     // consume .
                     self.advance();
                     // get the digits to the right
-                    while self.peek().is_digit(10) {
+                    while self.peek().is_ascii_digit() {
                         self.advance();
                     }
                 }
@@ -229,15 +338,105 @@ impl Scanner {
                 let text = self.chars.substring(self.start, self.current);
                 self.add_token(keyword_type(&text));
             }
-            _ => {
-                return Err(Box::new(crate::error::Error::new(
-                    ErrorKind::UnexpectedCharacter(Position::new(self.line, self.start)),
+            c => {
+                return Err(Box::new(ScanError::new(
+                    ScanErrorKind::UnexpectedCharacter(c, self.token_span()),
                 )));
             }
         };
         Ok(())
     }
 
+    /// Scans the inside of a string literal, up to and including the closing quote or the next `${`.
+    /// `start` is where the whole string began, which is where an unterminated string is reported
+    fn string(&mut self, start: Span) -> Result<(), Box<ScanError>> {
+        let mut value = String::new();
+        loop {
+            // check if string terminates at end of file w/o closing
+            if self.is_at_end() {
+                // Inside of a `${..}`, it's the string around it that gets reported, see [Self::scan_tokens]
+                if !self.interpolations.is_empty() {
+                    return Ok(());
+                }
+                return Err(Box::new(ScanError::new(ScanErrorKind::UnterminatedString(
+                    start,
+                ))));
+            }
+            let c = self.advance();
+            match c {
+                '"' => break,
+                // A bad escape is left out, the rest of the string is still scanned as a string
+                '\\' => match self.escape() {
+                    Ok(c) => value.push(c),
+                    Err(e) => self.errors.push(*e),
+                },
+                '$' if self.peek() == '{' => {
+                    self.advance();
+                    self.add_token_literal(
+                        TokenType::Interpolation,
+                        Literal::String(Symbol::intern(&value)),
+                    );
+                    self.interpolations.push((0, start));
+                    return Ok(());
+                }
+                _ => value.push(c),
+            }
+        }
+        self.add_token_literal(TokenType::String, Literal::String(Symbol::intern(&value)));
+        Ok(())
+    }
+
+    /// Turns what comes after a `\` in a string into the character it stands for
+    fn escape(&mut self) -> Result<char, Box<ScanError>> {
+        let backslash = self.current - 1;
+        let escaped = match self.peek() {
+            'n' => '\n',
+            't' => '\t',
+            '"' => '"',
+            '\\' => '\\',
+            // So a string can still say `${` without interpolating anything
+            '$' => '$',
+            'u' => {
+                self.advance();
+                return self.unicode_escape(backslash);
+            }
+            // Running out of string is reported as an unterminated string by the caller
+            '\0' if self.is_at_end() => return Ok('\\'),
+            c => {
+                self.advance();
+                return Err(Box::new(ScanError::new(ScanErrorKind::UnknownEscape(
+                    c,
+                    self.span_from(backslash),
+                ))));
+            }
+        };
+        self.advance();
+        Ok(escaped)
+    }
+
+    /// `\u{1F600}`, where the `\u` has already been consumed
+    fn unicode_escape(&mut self, backslash: usize) -> Result<char, Box<ScanError>> {
+        let invalid = |scanner: &Scanner| -> Box<ScanError> {
+            Box::new(ScanError::new(ScanErrorKind::InvalidUnicodeEscape(
+                scanner.span_from(backslash),
+            )))
+        };
+        if !self.next_is('{') {
+            return Err(invalid(self));
+        }
+        let mut digits = String::new();
+        while self.peek().is_ascii_hexdigit() && digits.len() < 6 {
+            digits.push(self.advance());
+        }
+        if digits.is_empty() || !self.next_is('}') {
+            return Err(invalid(self));
+        }
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| invalid(self))
+    }
+
     fn next_is(&mut self, expected: char) -> bool {
         if self.is_at_end() {
             return false;
@@ -280,24 +479,158 @@ impl Substring for Vec<char> {
 }
 
 /// Serves as a hashmap, matches string to thing
-fn keyword_type(str: &str) -> TokenType {
+/// Every word [keyword_type] doesn't consider an identifier, e.g. for the REPL to complete
+pub(crate) const KEYWORDS: [&str; 24] = [
+    "and", "as", "break", "catch", "class", "continue", "else", "false", "finally", "for", "func",
+    "if", "import", "nil", "or", "print", "return", "super", "this", "throw", "true", "try", "var",
+    "while",
+];
+
+pub(crate) fn keyword_type(str: &str) -> TokenType {
     match str {
         "and" => TokenType::And,
+        "as" => TokenType::As,
+        "break" => TokenType::Break,
+        "catch" => TokenType::Catch,
         "class" => TokenType::Class,
+        "continue" => TokenType::Continue,
         "else" => TokenType::Else,
         "false" => TokenType::False,
+        "finally" => TokenType::Finally,
+        "for" => TokenType::For,
         "func" => TokenType::Func,
         "if" => TokenType::If,
+        "import" => TokenType::Import,
         "nil" => TokenType::Nil,
         "or" => TokenType::Or,
         "print" => TokenType::Print,
         "return" => TokenType::Return,
         "super" => TokenType::Super,
         "this" => TokenType::This,
+        "throw" => TokenType::Throw,
         "true" => TokenType::True,
+        "try" => TokenType::Try,
         "var" => TokenType::Var,
         "while" => TokenType::While,
         // If it's not any of the above keyworks, let it be a user-defined name lol
         _ => TokenType::Identifier,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scan(src: &str) -> Result<Vec<Token>, crate::Error> {
+        let mut scanner = Scanner::new(src.to_string());
+        scanner.scan_tokens()?;
+        Ok(scanner.tokens)
+    }
+
+    fn string(token: &Token) -> String {
+        match &token.literal {
+            Literal::String(s) => s.to_string(),
+            l => std::panic!("expected a string literal, found {:?}", l),
+        }
+    }
+
+    #[test]
+    fn escapes() {
+        let tokens = scan(r#""a\tb\nc \"d\" \\ \$ \u{48}\u{1F600}""#).unwrap();
+        assert_eq!(string(&tokens[0]), "a\tb\nc \"d\" \\ $ H\u{1F600}");
+        // the lexeme is still the source text
+        assert_eq!(tokens[0].lexeme, r#""a\tb\nc \"d\" \\ \$ \u{48}\u{1F600}""#);
+
+        for src in [
+            r#""\q""#,
+            r#""\u{}""#,
+            r#""\u{110000}""#,
+            r#""\u48""#,
+            r#""\"#,
+        ] {
+            assert!(scan(src).is_err(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = scan("var é = \"ü\";\n  /* a\n */ print é;").unwrap();
+        let spans = tokens
+            .iter()
+            .map(|t| (t.span.start, t.span.end, t.span.line, t.span.column))
+            .collect::<Vec<_>>();
+        // offsets are in bytes, columns in characters
+        assert_eq!(
+            spans,
+            [
+                (0, 3, 1, 1),
+                (4, 6, 1, 5),
+                (7, 8, 1, 7),
+                (9, 13, 1, 9),
+                (13, 14, 1, 12),
+                (26, 31, 3, 5),
+                (32, 34, 3, 11),
+                (34, 35, 3, 12),
+                (35, 35, 3, 13),
+            ]
+        );
+
+        let e = scan("print 1;\n  \"abc").unwrap_err();
+        assert_eq!(e.to_string(), "<script>:2:3: unterminated string");
+        assert!(scan("/* closed right at the end */").is_ok());
+    }
+
+    #[test]
+    fn interpolation() {
+        let tokens = scan(r#""a ${x + {"k": 1}["k"]} b ${"c${y}"}""#).unwrap();
+        let types = tokens.iter().map(|t| t.token_type).collect::<Vec<_>>();
+        use TokenType::*;
+        assert_eq!(
+            types,
+            [
+                Interpolation,
+                Identifier,
+                Plus,
+                LeftBrace,
+                String,
+                Colon,
+                Number,
+                RightBrace,
+                LeftBracket,
+                String,
+                RightBracket,
+                Interpolation,
+                Interpolation,
+                Identifier,
+                String,
+                String,
+                Eof
+            ]
+        );
+        assert_eq!(string(&tokens[0]), "a ");
+        assert_eq!(string(&tokens[11]), " b ");
+        assert_eq!(string(&tokens[15]), "");
+
+        for src in [r#""${x""#, r#""${x"#, r#""${}""#, r#""${ {"#] {
+            assert!(scan(src).is_err(), "{}", src);
+        }
+        // one mistake, one error, pointing at the outermost string
+        for src in [r#""${";"#, r#""a ${ "b ${ 1 "#, r#""a ${ "b ${1} "#] {
+            let errors = Scanner::new(src.to_string()).scan_tokens().unwrap_err();
+            assert_eq!(errors.len(), 1, "{}", src);
+            assert!(
+                matches!(errors[0].kind, ScanErrorKind::UnterminatedString(span) if span.start == 0),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn keywords() {
+        for keyword in KEYWORDS {
+            assert_ne!(keyword_type(keyword), TokenType::Identifier, "{}", keyword);
+        }
+        assert_eq!(keyword_type("clock"), TokenType::Identifier);
+    }
+}
@@ -1,4 +1,7 @@
 use std::fmt;
+
+use crate::interner::Symbol;
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum TokenType {
     // One character tokens (always)
@@ -6,14 +9,24 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    Semicolon,
+    Question,
+    Colon,
+    // One or two character tokens
     Minus,
+    MinusEqual,
+    MinusMinus,
     Plus,
-    Semicolon,
+    PlusEqual,
+    PlusPlus,
     Slash,
+    SlashEqual,
     Star,
-    // One or two character tokens
+    StarEqual,
     Bang,
     BangEqual,
     Equal,
@@ -25,31 +38,110 @@ pub enum TokenType {
     // literals
     Identifier,
     String,
+    /// The part of a string before a `${`, the interpolated expression's tokens come right after it.
+    /// The rest of the string is another `Interpolation` if there's another `${`, otherwise it's a plain `String`
+    Interpolation,
     Number,
     // Keywords
     And,
+    As,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     Func,
     For,
     If,
+    Import,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
     // eof
     Eof,
 }
 
+impl TokenType {
+    /// How the token shows up in error messages, e.g. "expected `;`"
+    pub fn describe(self) -> &'static str {
+        use TokenType::*;
+        match self {
+            LeftParen => "`(`",
+            RightParen => "`)`",
+            LeftBrace => "`{`",
+            RightBrace => "`}`",
+            LeftBracket => "`[`",
+            RightBracket => "`]`",
+            Comma => "`,`",
+            Dot => "`.`",
+            Semicolon => "`;`",
+            Question => "`?`",
+            Colon => "`:`",
+            Minus => "`-`",
+            MinusEqual => "`-=`",
+            MinusMinus => "`--`",
+            Plus => "`+`",
+            PlusEqual => "`+=`",
+            PlusPlus => "`++`",
+            Slash => "`/`",
+            SlashEqual => "`/=`",
+            Star => "`*`",
+            StarEqual => "`*=`",
+            Bang => "`!`",
+            BangEqual => "`!=`",
+            Equal => "`=`",
+            EqualEqual => "`==`",
+            Greater => "`>`",
+            GreaterEqual => "`>=`",
+            Less => "`<`",
+            LessEqual => "`<=`",
+            Identifier => "an identifier",
+            String => "a string",
+            Interpolation => "the rest of the string",
+            Number => "a number",
+            And => "`and`",
+            As => "`as`",
+            Break => "`break`",
+            Catch => "`catch`",
+            Class => "`class`",
+            Continue => "`continue`",
+            Else => "`else`",
+            False => "`false`",
+            Finally => "`finally`",
+            Func => "`func`",
+            For => "`for`",
+            If => "`if`",
+            Import => "`import`",
+            Nil => "`nil`",
+            Or => "`or`",
+            Print => "`print`",
+            Return => "`return`",
+            Super => "`super`",
+            This => "`this`",
+            Throw => "`throw`",
+            True => "`true`",
+            Try => "`try`",
+            Var => "`var`",
+            While => "`while`",
+            Eof => "end of file",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
-    String(String),
+    /// Interned, so every use of the same string literal shares one allocation
+    String(Symbol),
     Number(f32),
     Boolean(bool),
     Nil,
@@ -66,21 +158,77 @@ impl fmt::Display for Literal {
     }
 }
 
+/// Where a piece of source code came from. `start` and `end` are byte offsets into the file,
+/// `line` and `column` (both starting at 1, columns counted in characters) are where it starts
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub file: Symbol,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// A span running from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: self.end.max(other.end),
+            ..self
+        }
+    }
+
+    /// Joins two spans that might not be there, e.g. of an expression that might be [crate::ast::Expr::Null]
+    pub fn join(first: Option<Span>, last: Option<Span>) -> Option<Span> {
+        match (first, last) {
+            (Some(first), Some(last)) => Some(first.to(last)),
+            (first, last) => first.or(last),
+        }
+    }
+}
+
+/// Tokens that don't come from any source, like the ones tests make up, point at the start of an unnamed file
+impl Default for Span {
+    fn default() -> Self {
+        Span {
+            file: Symbol::intern("<unknown>"),
+            start: 0,
+            end: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+/// `file:line:column`, the way compilers usually point at source
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Kept to one line, since every token and every node of a syntax tree has one, e.g. `main.lox:1:5 (4..7)`
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}..{})", self, self.start, self.end)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Literal,
-    pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, literal: Literal, line: usize) -> Self {
+    pub fn new(token_type: TokenType, lexeme: Symbol, literal: Literal, span: Span) -> Self {
         Self {
             token_type,
             lexeme,
             literal,
-            line,
+            span,
         }
     }
 }
@@ -0,0 +1,184 @@
+use super::value::Value;
+use crate::token::Span;
+
+/// Every instruction the VM understands. Operands follow the opcode directly in [Chunk::code]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+pub enum OpCode {
+    /// `[idx]` pushes `constants[idx]`
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// `[slot]`
+    GetLocal,
+    /// `[slot]`
+    SetLocal,
+    /// `[name idx]`
+    GetGlobal,
+    /// `[name idx]`
+    DefineGlobal,
+    /// `[name idx]`
+    SetGlobal,
+    /// `[idx]`
+    GetUpvalue,
+    /// `[idx]`
+    SetUpvalue,
+    /// `[name idx]`
+    GetProperty,
+    /// `[name idx]`
+    SetProperty,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    /// `[hi, lo]` jumps forward
+    Jump,
+    /// `[hi, lo]` jumps forward if the top of the stack is falsey, without popping it
+    JumpIfFalse,
+    /// `[hi, lo]` jumps backward
+    Loop,
+    /// `[arg count]`
+    Call,
+    /// `[function idx]` followed by an `[is_local, index]` pair for every upvalue
+    Closure,
+    CloseUpvalue,
+    Return,
+    /// `[name idx]`
+    Class,
+    /// `[name idx]`
+    Method,
+}
+
+impl OpCode {
+    /// Every opcode in the order of its discriminant, so a byte can be turned back into an [OpCode]
+    const ALL: [OpCode; 36] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Method,
+    ];
+}
+
+impl From<u8> for OpCode {
+    fn from(byte: u8) -> Self {
+        OpCode::ALL[byte as usize]
+    }
+}
+
+/// A compiled function body: the bytecode itself, the constants it refers to, and where in the source each byte came from
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    /// Run-length encoded `(span, number of bytes)` pairs, consecutive bytes almost always share a span
+    spans: Vec<(Span, usize)>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+        match self.spans.last_mut() {
+            Some((s, count)) if *s == span => *count += 1,
+            _ => self.spans.push((span, 1)),
+        }
+    }
+
+    pub fn write_op(&mut self, op: OpCode, span: Span) {
+        self.write(op as u8, span);
+    }
+
+    /// Adds `value` to the constants table and returns its index
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// The source the byte at `offset` was compiled from
+    pub fn span(&self, offset: usize) -> Span {
+        let mut seen = 0;
+        for (span, count) in &self.spans {
+            seen += count;
+            if offset < seen {
+                return *span;
+            }
+        }
+        Span::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn span_table() {
+        let line = |line| Span {
+            line,
+            ..Span::default()
+        };
+        let mut chunk = Chunk::new();
+        chunk.write_op(OpCode::Nil, line(1));
+        chunk.write_op(OpCode::Nil, line(1));
+        chunk.write_op(OpCode::Print, line(2));
+        chunk.write_op(OpCode::Nil, line(4));
+        assert_eq!(chunk.spans.len(), 3);
+        assert_eq!(
+            (0..4).map(|o| chunk.span(o).line).collect::<Vec<_>>(),
+            vec![1, 1, 2, 4]
+        );
+    }
+
+    #[test]
+    fn opcode_round_trip() {
+        OpCode::ALL
+            .iter()
+            .for_each(|op| assert_eq!(OpCode::from(*op as u8), *op));
+    }
+}
@@ -0,0 +1,659 @@
+use std::rc::Rc;
+
+use crate::{
+    ast::{Expr, FunctionDecl, Stmt},
+    interner::Symbol,
+    token::{Literal, Span, Token, TokenType},
+};
+
+use super::{
+    chunk::OpCode,
+    error::{VmError, VmErrorKind},
+    value::{FunctionProto, Value},
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Locals and upvalues are addressed with a single byte
+const MAX_SLOTS: usize = u8::MAX as usize + 1;
+
+/// A local variable's stack slot, as seen at compile time
+struct Local {
+    name: Symbol,
+    /// Scope depth the variable was declared at, `None` while its initializer is still compiling
+    depth: Option<usize>,
+    /// Whether a closure captured this local, in which case it has to be moved off of the stack when it goes out of scope
+    is_captured: bool,
+}
+
+/// Where a closure finds one of its captured variables
+struct UpvalueRef {
+    index: u8,
+    /// `true` if it's a local of the directly enclosing function, `false` if it's one of that function's upvalues
+    is_local: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// A loop that's being compiled, `break` and `continue` jump forward so they're patched once we know where to
+struct Loop {
+    /// Scope depth outside of the body, every local deeper than this has to be popped before jumping
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// Everything needed to compile one function body, nested functions push a new one of these
+struct FunctionState {
+    function: FunctionProto,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: Symbol) -> Self {
+        // Slot zero holds the function being called, or `this` for methods
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => "",
+        };
+        Self {
+            function: FunctionProto {
+                name: name.to_string(),
+                ..Default::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: slot_zero.into(),
+                depth: Some(0),
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+        }
+    }
+}
+
+/// Turns a parsed (and resolved) program into bytecode for the [super::Vm]
+pub struct Compiler {
+    states: Vec<FunctionState>,
+    /// Span of the most recent token or statement we've seen, every instruction emitted is put down as coming from it
+    span: Span,
+}
+
+impl Compiler {
+    /// Compiles a whole program into the implicit top-level `<script>` function
+    pub fn compile(statements: &[Stmt]) -> Result<Rc<FunctionProto>> {
+        let mut compiler = Compiler {
+            states: vec![FunctionState::new(FunctionKind::Script, "".into())],
+            span: Span::default(),
+        };
+
+        statements.iter().try_for_each(|s| compiler.statement(s))?;
+        compiler.emit_return();
+
+        Ok(Rc::new(compiler.states.pop().unwrap().function))
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn error(&self, kind: VmErrorKind) -> Box<dyn std::error::Error> {
+        Box::new(VmError::new(kind, self.span))
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<()> {
+        // `print` and expression statements don't keep a token of their own, so start off on the statement's span
+        if let Some(span) = stmt.span() {
+            self.span = span;
+        }
+        match stmt {
+            Stmt::Expr(e) => {
+                self.expression(e)?;
+                self.emit_op(OpCode::Pop);
+            }
+            Stmt::Print(e) => {
+                self.expression(e)?;
+                self.emit_op(OpCode::Print);
+            }
+            Stmt::Var { name, initializer } => {
+                self.span = name.span;
+                self.declare_variable(name)?;
+                self.expression(initializer)?;
+                self.define_variable(name)?;
+            }
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                statements.iter().try_for_each(|s| self.statement(s))?;
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(then_branch)?;
+
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump)?;
+                self.emit_op(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump)?;
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                let loop_start = self.state().function.chunk.code.len();
+                self.expression(condition)?;
+
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                let scope_depth = self.state().scope_depth;
+                self.state().loops.push(Loop {
+                    scope_depth,
+                    breaks: Vec::new(),
+                    continues: Vec::new(),
+                });
+                let body = self.statement(body);
+                let Loop {
+                    breaks, continues, ..
+                } = self.state().loops.pop().unwrap();
+                body?;
+
+                continues
+                    .into_iter()
+                    .try_for_each(|jump| self.patch_jump(jump))?;
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit_op(OpCode::Pop);
+                }
+                self.emit_loop(loop_start)?;
+
+                self.patch_jump(exit_jump)?;
+                self.emit_op(OpCode::Pop);
+                // The condition was already popped when we went into the body, so breaks land after that
+                breaks
+                    .into_iter()
+                    .try_for_each(|jump| self.patch_jump(jump))?;
+            }
+            Stmt::Import { keyword, .. } => {
+                self.span = keyword.span;
+                return Err(self.error(VmErrorKind::Unsupported("modules".into())));
+            }
+            Stmt::Throw { keyword, .. } | Stmt::Try { keyword, .. } => {
+                self.span = keyword.span;
+                return Err(self.error(VmErrorKind::Unsupported("exceptions".into())));
+            }
+            Stmt::Break { keyword } | Stmt::Continue { keyword } => {
+                self.span = keyword.span;
+                self.discard_loop_locals();
+                let jump = self.emit_jump(OpCode::Jump);
+                let current = self.state().loops.last_mut().unwrap();
+                match stmt {
+                    Stmt::Break { .. } => current.breaks.push(jump),
+                    _ => current.continues.push(jump),
+                }
+            }
+            Stmt::Function(declaration) => {
+                self.span = declaration.name.span;
+                self.declare_variable(&declaration.name)?;
+                // Mark it as initialized straight away so the function can refer to itself
+                self.mark_initialized();
+                self.function(declaration, FunctionKind::Function)?;
+                self.define_variable(&declaration.name)?;
+            }
+            Stmt::Class { name, methods } => {
+                self.span = name.span;
+                let name_constant = self.identifier_constant(name.lexeme)?;
+                self.declare_variable(name)?;
+                self.emit_bytes(OpCode::Class, name_constant);
+                self.define_variable(name)?;
+
+                // Put the class back on the stack so `Method` can find it
+                self.named_variable(name, false)?;
+                for method in methods {
+                    let kind = if method.name.lexeme == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    let method_constant = self.identifier_constant(method.name.lexeme)?;
+                    self.function(method, kind)?;
+                    self.emit_bytes(OpCode::Method, method_constant);
+                }
+                self.emit_op(OpCode::Pop);
+            }
+            Stmt::Return { keyword, value } => {
+                self.span = keyword.span;
+                match value {
+                    // The resolver already made sure initializers can't return a value
+                    Expr::Null => self.emit_return(),
+                    _ => {
+                        self.expression(value)?;
+                        self.emit_op(OpCode::Return);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<()> {
+        match expr {
+            Expr::Literal(literal, span) => {
+                self.span = *span;
+                match literal {
+                    Literal::Nil => self.emit_op(OpCode::Nil),
+                    Literal::Boolean(true) => self.emit_op(OpCode::True),
+                    Literal::Boolean(false) => self.emit_op(OpCode::False),
+                    Literal::Number(n) => self.emit_constant(Value::Number(*n))?,
+                    Literal::String(s) => self.emit_constant(Value::String(s.as_str()))?,
+                }
+            }
+            Expr::Grouping { expression } => self.expression(expression)?,
+            Expr::Unary { operator, right } => {
+                self.expression(right)?;
+                self.span = operator.span;
+                match operator.token_type {
+                    TokenType::Minus => self.emit_op(OpCode::Negate),
+                    _ => self.emit_op(OpCode::Not),
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.span = operator.span;
+                self.emit_op(match operator.token_type {
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::BangEqual => OpCode::NotEqual,
+                    _ => OpCode::Equal,
+                });
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                if operator.token_type == TokenType::Or {
+                    // If left is falsey, skip over the jump to the end and evaluate right
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump)?;
+                    self.emit_op(OpCode::Pop);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump)?;
+                } else {
+                    let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    self.emit_op(OpCode::Pop);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump)?;
+                }
+            }
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition)?;
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.expression(then_branch)?;
+                let end_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(else_jump)?;
+                self.emit_op(OpCode::Pop);
+                self.expression(else_branch)?;
+                self.patch_jump(end_jump)?;
+            }
+            Expr::Variable { name, .. } => self.named_variable(name, false)?,
+            Expr::Assign { name, value, .. } => {
+                self.expression(value)?;
+                self.named_variable(name, true)?;
+            }
+            Expr::Update {
+                name,
+                operator,
+                prefix,
+                ..
+            } => {
+                // Subtracting keeps the "numbers only" check of the tree-walker, `a++` is `a - -1`
+                let step = match operator.token_type {
+                    TokenType::PlusPlus => -1.0,
+                    _ => 1.0,
+                };
+                self.named_variable(name, false)?;
+                if !prefix {
+                    // keep the old value around as the result
+                    self.named_variable(name, false)?;
+                }
+                self.emit_constant(Value::Number(step))?;
+                self.emit_op(OpCode::Subtract);
+                self.named_variable(name, true)?;
+                if !prefix {
+                    self.emit_op(OpCode::Pop);
+                }
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.expression(callee)?;
+                arguments.iter().try_for_each(|a| self.expression(a))?;
+                self.span = paren.span;
+                self.emit_bytes(OpCode::Call, arguments.len() as u8);
+            }
+            Expr::Get { object, name } => {
+                self.expression(object)?;
+                self.span = name.span;
+                let constant = self.identifier_constant(name.lexeme)?;
+                self.emit_bytes(OpCode::GetProperty, constant);
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object)?;
+                self.expression(value)?;
+                self.span = name.span;
+                let constant = self.identifier_constant(name.lexeme)?;
+                self.emit_bytes(OpCode::SetProperty, constant);
+            }
+            Expr::This { keyword, .. } => self.named_variable(keyword, false)?,
+            Expr::List { bracket, .. }
+            | Expr::Index { bracket, .. }
+            | Expr::SetIndex { bracket, .. } => {
+                self.span = bracket.span;
+                return Err(self.error(VmErrorKind::Unsupported("lists".into())));
+            }
+            Expr::Map { brace, .. } => {
+                self.span = brace.span;
+                return Err(self.error(VmErrorKind::Unsupported("maps".into())));
+            }
+            Expr::Null => self.emit_op(OpCode::Nil),
+        }
+        Ok(())
+    }
+
+    /// Compiles a function body into its own [FunctionProto] and emits the code that wraps it in a closure
+    fn function(&mut self, declaration: &FunctionDecl, kind: FunctionKind) -> Result<()> {
+        self.states
+            .push(FunctionState::new(kind, declaration.name.lexeme));
+        self.state().function.arity = declaration.params.len();
+        self.begin_scope();
+
+        for param in &declaration.params {
+            self.declare_variable(param)?;
+            self.mark_initialized();
+        }
+        declaration
+            .body
+            .iter()
+            .try_for_each(|s| self.statement(s))?;
+        self.emit_return();
+
+        // No need to end the scope, the whole frame is thrown away when the function returns
+        let state = self.states.pop().unwrap();
+        let mut function = state.function;
+        function.upvalue_count = state.upvalues.len();
+
+        let constant = self.make_constant(Value::Function(Rc::new(function)))?;
+        self.emit_bytes(OpCode::Closure, constant);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    /// Pops every local declared in the scope, moving captured ones into their upvalue first
+    fn end_scope(&mut self) {
+        self.state().scope_depth -= 1;
+        let scope_depth = self.state().scope_depth;
+        while let Some(local) = self.state().locals.last() {
+            if local.depth.unwrap_or(usize::MAX) <= scope_depth {
+                break;
+            }
+            let op = if local.is_captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.emit_op(op);
+            self.state().locals.pop();
+        }
+    }
+
+    /// Pops the locals declared inside of the innermost loop's body without forgetting about them,
+    /// since the code after a `break` or `continue` is still in their scope as far as the compiler knows
+    fn discard_loop_locals(&mut self) {
+        let scope_depth = self.state().loops.last().unwrap().scope_depth;
+        let ops = self
+            .state()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.unwrap_or(usize::MAX) > scope_depth)
+            .map(|local| {
+                if local.is_captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect::<Vec<_>>();
+        ops.into_iter().for_each(|op| self.emit_op(op));
+    }
+
+    /// Locals live on the stack, so declaring one just remembers which slot it's in. Globals aren't declared
+    fn declare_variable(&mut self, name: &Token) -> Result<()> {
+        if self.state().scope_depth == 0 {
+            return Ok(());
+        }
+        if self.state().locals.len() >= MAX_SLOTS {
+            return Err(self.error(VmErrorKind::TooManyLocals));
+        }
+        self.state().locals.push(Local {
+            name: name.lexeme,
+            depth: None,
+            is_captured: false,
+        });
+        Ok(())
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return;
+        }
+        let depth = state.scope_depth;
+        state.locals.last_mut().unwrap().depth = Some(depth);
+    }
+
+    /// The value is on top of the stack. For a local that's already where it belongs, globals get stored by name
+    fn define_variable(&mut self, name: &Token) -> Result<()> {
+        if self.state().scope_depth > 0 {
+            self.mark_initialized();
+            return Ok(());
+        }
+        let constant = self.identifier_constant(name.lexeme)?;
+        self.emit_bytes(OpCode::DefineGlobal, constant);
+        Ok(())
+    }
+
+    /// Emits a get (or set, if `assign`) of `name`, working out whether it's a local, an upvalue or a global
+    fn named_variable(&mut self, name: &Token, assign: bool) -> Result<()> {
+        self.span = name.span;
+        let current = self.states.len() - 1;
+        let (get, set, arg) = if let Some(slot) = self.resolve_local(current, name.lexeme) {
+            (OpCode::GetLocal, OpCode::SetLocal, slot)
+        } else if let Some(index) = self.resolve_upvalue(current, name.lexeme)? {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index)
+        } else {
+            let constant = self.identifier_constant(name.lexeme)?;
+            (OpCode::GetGlobal, OpCode::SetGlobal, constant)
+        };
+        self.emit_bytes(if assign { set } else { get }, arg);
+        Ok(())
+    }
+
+    fn resolve_local(&self, state: usize, name: Symbol) -> Option<u8> {
+        self.states[state]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    /// Looks for `name` in the enclosing functions, threading an upvalue through every function in between
+    fn resolve_upvalue(&mut self, state: usize, name: Symbol) -> Result<Option<u8>> {
+        if state == 0 {
+            return Ok(None);
+        }
+        if let Some(slot) = self.resolve_local(state - 1, name) {
+            self.states[state - 1].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(state, slot, true).map(Some);
+        }
+        match self.resolve_upvalue(state - 1, name)? {
+            Some(index) => self.add_upvalue(state, index, false).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(&mut self, state: usize, index: u8, is_local: bool) -> Result<u8> {
+        let upvalues = &mut self.states[state].upvalues;
+        // A closure that mentions the same variable twice only captures it once
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|u| u.index == index && u.is_local == is_local)
+        {
+            return Ok(existing as u8);
+        }
+        if upvalues.len() >= MAX_SLOTS {
+            return Err(self.error(VmErrorKind::TooManyUpvalues));
+        }
+        upvalues.push(UpvalueRef { index, is_local });
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    /// Names are stored as string constants, reusing an existing one if the function already has it
+    fn identifier_constant(&mut self, name: Symbol) -> Result<u8> {
+        let name = name.as_str();
+        let existing = self
+            .state()
+            .function
+            .chunk
+            .constants
+            .iter()
+            .position(|c| matches!(c, Value::String(s) if Rc::ptr_eq(s, &name)));
+        match existing {
+            Some(idx) => Ok(idx as u8),
+            None => self.make_constant(Value::String(name)),
+        }
+    }
+
+    fn make_constant(&mut self, value: Value) -> Result<u8> {
+        let idx = self.state().function.chunk.add_constant(value);
+        if idx >= MAX_SLOTS {
+            return Err(self.error(VmErrorKind::TooManyConstants));
+        }
+        Ok(idx as u8)
+    }
+
+    fn emit_constant(&mut self, value: Value) -> Result<()> {
+        let constant = self.make_constant(value)?;
+        self.emit_bytes(OpCode::Constant, constant);
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let span = self.span;
+        self.state().function.chunk.write(byte, span);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_bytes(&mut self, op: OpCode, operand: u8) {
+        self.emit_op(op);
+        self.emit_byte(operand);
+    }
+
+    /// Initializers always hand back `this`, every other function returns nil if it falls off the end
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit_bytes(OpCode::GetLocal, 0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    /// Emits a jump with a placeholder offset, returning where the offset lives so it can be patched later
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        self.state().function.chunk.code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) -> Result<()> {
+        // -2 to account for the offset bytes themselves
+        let jump = self.state().function.chunk.code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            return Err(self.error(VmErrorKind::JumpTooLarge));
+        }
+        let code = &mut self.state().function.chunk.code;
+        code[offset] = (jump >> 8) as u8;
+        code[offset + 1] = jump as u8;
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> Result<()> {
+        self.emit_op(OpCode::Loop);
+        // +2 to jump back over the offset bytes too
+        let offset = self.state().function.chunk.code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            return Err(self.error(VmErrorKind::JumpTooLarge));
+        }
+        self.emit_byte((offset >> 8) as u8);
+        self.emit_byte(offset as u8);
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use std::{error, fmt};
+
+use super::value::Value;
+use crate::{diagnostic::Diagnostic, token::Span};
+
+#[derive(Debug)]
+pub struct VmError {
+    pub kind: VmErrorKind,
+    /// Where the instruction that failed came from, or the statement that couldn't be compiled
+    pub span: Span,
+}
+
+impl error::Error for VmError {}
+
+impl VmError {
+    pub fn new(kind: VmErrorKind, span: Span) -> VmError {
+        VmError { kind, span }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.kind.to_string()).with_span(Some(self.span))
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+/// Just the message, without where it happened
+impl fmt::Display for VmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmErrorKind::TooManyConstants => write!(f, "too many constants in one function"),
+            VmErrorKind::TooManyLocals => {
+                write!(f, "too many local variables in one function")
+            }
+            VmErrorKind::TooManyUpvalues => {
+                write!(f, "too many captured variables in one function")
+            }
+            VmErrorKind::JumpTooLarge => write!(f, "too much code to jump over"),
+            VmErrorKind::Unsupported(s) => {
+                write!(f, "{} isn't supported by the bytecode backend", s)
+            }
+            VmErrorKind::FailedCast(v, to) => write!(f, "failed to cast {} to {}", v, to),
+            VmErrorKind::DivideByZero(n) => write!(f, "attempt to divide {} by 0", n),
+            VmErrorKind::UnitializedVariable => write!(f, "unitialized variable"),
+            VmErrorKind::UndefinedVariable(name) => write!(f, "undefined variable `{}`", name),
+            VmErrorKind::NotCallable(v) => {
+                write!(f, "can only call functions, but tried to call {}", v)
+            }
+            VmErrorKind::WrongArity(expected, found) => {
+                write!(f, "expected {} arguments but got {}", expected, found)
+            }
+            VmErrorKind::NotAnInstance(v) => {
+                write!(f, "only instances have properties, but {} isn't one", v)
+            }
+            VmErrorKind::UndefinedProperty(name) => write!(f, "undefined property `{}`", name),
+            VmErrorKind::StackOverflow => write!(f, "stack overflow"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VmErrorKind {
+    // Compile errors
+    TooManyConstants,
+    TooManyLocals,
+    TooManyUpvalues,
+    JumpTooLarge,
+    Unsupported(String),
+    // Runtime errors
+    FailedCast(Value, &'static str),
+    DivideByZero(f32),
+    UnitializedVariable,
+    UndefinedVariable(String),
+    NotCallable(Value),
+    WrongArity(usize, usize),
+    NotAnInstance(Value),
+    UndefinedProperty(String),
+    StackOverflow,
+}
+
+impl VmErrorKind {
+    /// See [crate::Error::code]. Runtime errors share their codes with the tree-walker's errors that mean the same thing
+    pub fn code(&self) -> &'static str {
+        match self {
+            VmErrorKind::TooManyConstants => "E0501",
+            VmErrorKind::TooManyLocals => "E0502",
+            VmErrorKind::TooManyUpvalues => "E0503",
+            VmErrorKind::JumpTooLarge => "E0504",
+            VmErrorKind::Unsupported(_) => "E0505",
+            VmErrorKind::StackOverflow => "E0506",
+            VmErrorKind::UndefinedVariable(_) => "E0401",
+            VmErrorKind::FailedCast(..) => "E0402",
+            VmErrorKind::DivideByZero(_) => "E0403",
+            VmErrorKind::UnitializedVariable => "E0404",
+            VmErrorKind::NotCallable(_) => "E0405",
+            VmErrorKind::WrongArity(..) => "E0406",
+            VmErrorKind::NotAnInstance(_) => "E0407",
+            VmErrorKind::UndefinedProperty(_) => "E0408",
+        }
+    }
+
+    /// Whether the script never got to run, because it couldn't be compiled
+    pub fn is_compile_error(&self) -> bool {
+        matches!(
+            self,
+            VmErrorKind::TooManyConstants
+                | VmErrorKind::TooManyLocals
+                | VmErrorKind::TooManyUpvalues
+                | VmErrorKind::JumpTooLarge
+                | VmErrorKind::Unsupported(_)
+        )
+    }
+}
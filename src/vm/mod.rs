@@ -0,0 +1,734 @@
+pub mod chunk;
+pub mod compiler;
+pub mod error;
+pub mod value;
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Instant};
+
+use self::{
+    chunk::OpCode,
+    error::{VmError, VmErrorKind},
+    value::{BoundMethod, Class, Closure, FunctionProto, Instance, Native, Upvalue, Value},
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// How deep calls can nest before we call it a stack overflow
+const FRAMES_MAX: usize = 256;
+
+/// A function call in progress
+struct CallFrame {
+    closure: Rc<Closure>,
+    /// Index of the next instruction in the closure's chunk
+    ip: usize,
+    /// Stack index of the frame's slot zero
+    slots: usize,
+}
+
+/// A stack-based virtual machine that runs the bytecode produced by [compiler::Compiler].
+/// An alternative to [crate::interpreter::InterpreterVisitor] that doesn't re-walk the tree on every loop iteration
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    pub globals: HashMap<Rc<str>, Value>,
+    /// Upvalues that still point into the stack, sorted by slot
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+        };
+
+        // Same as the tree-walker's `clock()`, seconds since the VM was created
+        let start = Instant::now();
+        vm.define_native("clock", 0, move |_| {
+            Ok(Value::Number(start.elapsed().as_secs_f32()))
+        });
+
+        vm
+    }
+
+    /// Exposes a Rust function to scripts as a global called `name`
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value> + 'static,
+    {
+        let native = Native {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        };
+        self.globals
+            .insert(name.into(), Value::Native(Rc::new(native)));
+    }
+
+    /// Runs a compiled `<script>` function to completion
+    pub fn interpret(&mut self, function: Rc<FunctionProto>) -> Result<()> {
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: Vec::new(),
+        });
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        self.call(closure, 0)?;
+
+        let res = self.run();
+        if res.is_err() {
+            // Throw away whatever the failed script left behind, globals are kept though
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        res
+    }
+
+    fn frame(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> usize {
+        let hi = self.read_byte() as usize;
+        let lo = self.read_byte() as usize;
+        (hi << 8) | lo
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let idx = self.read_byte() as usize;
+        self.frame().closure.function.chunk.constants[idx].clone()
+    }
+
+    /// Constants used as names are always strings, the compiler makes sure of that
+    fn read_string(&mut self) -> Rc<str> {
+        match self.read_constant() {
+            Value::String(s) => s,
+            v => unreachable!("expected a name constant, found {}", v),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// Builds an error pointing at the source of the instruction that's currently running
+    fn error(&mut self, kind: VmErrorKind) -> Box<dyn std::error::Error> {
+        let frame = self.frame();
+        let span = frame
+            .closure
+            .function
+            .chunk
+            .span(frame.ip.saturating_sub(1));
+        Box::new(VmError::new(kind, span))
+    }
+
+    fn num(&mut self, value: Value) -> Result<f32> {
+        match value {
+            Value::Number(n) => Ok(n),
+            v => Err(self.error(VmErrorKind::FailedCast(v, "number"))),
+        }
+    }
+
+    /// Pops two numbers, right first since it was pushed last
+    fn pop_numbers(&mut self) -> Result<(f32, f32)> {
+        let right = self.pop();
+        let left = self.pop();
+        Ok((self.num(left)?, self.num(right)?))
+    }
+
+    fn run(&mut self) -> Result<()> {
+        loop {
+            match OpCode::from(self.read_byte()) {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.stack.push(constant);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Boolean(true)),
+                OpCode::False => self.stack.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let idx = self.frame().slots + slot;
+                    let value = self.stack[idx].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let idx = self.frame().slots + slot;
+                    // assignment is an expression, so the value stays on the stack
+                    self.stack[idx] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => {
+                            return Err(self.error(VmErrorKind::UndefinedVariable(name.to_string())))
+                        }
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => {
+                            return Err(self.error(VmErrorKind::UndefinedVariable(name.to_string())))
+                        }
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let idx = self.read_byte() as usize;
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[idx]);
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let idx = self.read_byte() as usize;
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[idx]);
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let instance = match self.pop() {
+                        Value::Instance(instance) => instance,
+                        v => return Err(self.error(VmErrorKind::NotAnInstance(v))),
+                    };
+
+                    // Fields shadow methods
+                    let field = instance.borrow().fields.get(&*name).cloned();
+                    let method = instance
+                        .borrow()
+                        .class
+                        .borrow()
+                        .methods
+                        .get(&*name)
+                        .cloned();
+                    match (field, method) {
+                        (Some(value), _) => self.stack.push(value),
+                        (None, Some(method)) => {
+                            self.stack.push(Value::BoundMethod(Rc::new(BoundMethod {
+                                receiver: Value::Instance(instance),
+                                method,
+                            })))
+                        }
+                        (None, None) => {
+                            return Err(self.error(VmErrorKind::UndefinedProperty(name.to_string())))
+                        }
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    match self.pop() {
+                        Value::Instance(instance) => {
+                            instance
+                                .borrow_mut()
+                                .fields
+                                .insert(name.to_string(), value.clone());
+                        }
+                        v => return Err(self.error(VmErrorKind::NotAnInstance(v))),
+                    }
+                    self.stack.push(value);
+                }
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Boolean(left == right));
+                }
+                OpCode::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Boolean(left != right));
+                }
+                OpCode::Greater => {
+                    let (left, right) = self.pop_numbers()?;
+                    self.stack.push(Value::Boolean(left > right));
+                }
+                OpCode::GreaterEqual => {
+                    let (left, right) = self.pop_numbers()?;
+                    self.stack.push(Value::Boolean(left >= right));
+                }
+                OpCode::Less => {
+                    let (left, right) = self.pop_numbers()?;
+                    self.stack.push(Value::Boolean(left < right));
+                }
+                OpCode::LessEqual => {
+                    let (left, right) = self.pop_numbers()?;
+                    self.stack.push(Value::Boolean(left <= right));
+                }
+                OpCode::Add => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    // Same rules as the tree-walker, numbers add and anything next to a string concatenates
+                    let value = match (left, right) {
+                        (Value::Number(l), Value::String(r)) => {
+                            Value::String(format!("{}{}", l, r).into())
+                        }
+                        (Value::Number(l), r) => Value::Number(l + self.num(r)?),
+                        (Value::String(l), r) => Value::String(format!("{}{}", l, r).into()),
+                        (_, r) => return Err(self.error(VmErrorKind::FailedCast(r, "string"))),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Subtract => {
+                    let (left, right) = self.pop_numbers()?;
+                    self.stack.push(Value::Number(left - right));
+                }
+                OpCode::Multiply => {
+                    let (left, right) = self.pop_numbers()?;
+                    self.stack.push(Value::Number(left * right));
+                }
+                OpCode::Divide => {
+                    let (left, right) = self.pop_numbers()?;
+                    if right == 0.0 {
+                        return Err(self.error(VmErrorKind::DivideByZero(left)));
+                    }
+                    self.stack.push(Value::Number(left / right));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Boolean(!value.is_truthy()));
+                }
+                OpCode::Negate => {
+                    let value = self.pop();
+                    let n = self.num(value)?;
+                    self.stack.push(Value::Number(-n));
+                }
+                OpCode::Print => match self.pop() {
+                    // The tree-walker refuses to print nil, so we do too
+                    Value::Nil => return Err(self.error(VmErrorKind::UnitializedVariable)),
+                    v => println!("{}", v),
+                },
+                OpCode::Jump => {
+                    let offset = self.read_short();
+                    self.frame().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if !self.peek(0).is_truthy() {
+                        self.frame().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_short();
+                    self.frame().ip -= offset;
+                }
+                OpCode::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    let callee = self.peek(arg_count).clone();
+                    self.call_value(callee, arg_count)?;
+                }
+                OpCode::Closure => {
+                    let function = match self.read_constant() {
+                        Value::Function(function) => function,
+                        v => unreachable!("expected a function constant, found {}", v),
+                    };
+                    let upvalues = (0..function.upvalue_count)
+                        .map(|_| {
+                            let is_local = self.read_byte() == 1;
+                            let index = self.read_byte() as usize;
+                            if is_local {
+                                let slot = self.frame().slots + index;
+                                self.capture_upvalue(slot)
+                            } else {
+                                Rc::clone(&self.frame().closure.upvalues[index])
+                            }
+                        })
+                        .collect();
+                    self.stack
+                        .push(Value::Closure(Rc::new(Closure { function, upvalues })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_string();
+                    self.stack.push(Value::Class(Rc::new(RefCell::new(Class {
+                        name: name.to_string(),
+                        methods: HashMap::new(),
+                    }))));
+                }
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let method = match self.pop() {
+                        Value::Closure(closure) => closure,
+                        v => unreachable!("expected a method closure, found {}", v),
+                    };
+                    if let Value::Class(class) = self.peek(0) {
+                        class.borrow_mut().methods.insert(name.to_string(), method);
+                    }
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<()> {
+        match callee {
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::Native(native) => {
+                if arg_count != native.arity {
+                    return Err(self.error(VmErrorKind::WrongArity(native.arity, arg_count)));
+                }
+                let args_start = self.stack.len() - arg_count;
+                let result = (native.function)(&self.stack[args_start..])?;
+                // get rid of the arguments and the native itself
+                self.stack.truncate(args_start - 1);
+                self.stack.push(result);
+                Ok(())
+            }
+            Value::Class(class) => {
+                let instance = Value::Instance(Rc::new(RefCell::new(Instance {
+                    class: Rc::clone(&class),
+                    fields: HashMap::new(),
+                })));
+                // The instance takes the class's place, so it's slot zero (`this`) of the initializer
+                let callee_slot = self.stack.len() - arg_count - 1;
+                self.stack[callee_slot] = instance;
+
+                let init = class.borrow().methods.get("init").cloned();
+                match init {
+                    Some(init) => self.call(init, arg_count),
+                    None if arg_count != 0 => {
+                        Err(self.error(VmErrorKind::WrongArity(0, arg_count)))
+                    }
+                    None => Ok(()),
+                }
+            }
+            Value::BoundMethod(bound) => {
+                let callee_slot = self.stack.len() - arg_count - 1;
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(Rc::clone(&bound.method), arg_count)
+            }
+            v => Err(self.error(VmErrorKind::NotCallable(v))),
+        }
+    }
+
+    /// Pushes a new frame, whose slots start at the callee that's already sitting on the stack under its arguments
+    fn call(&mut self, closure: Rc<Closure>, arg_count: usize) -> Result<()> {
+        if arg_count != closure.function.arity {
+            return Err(self.error(VmErrorKind::WrongArity(closure.function.arity, arg_count)));
+        }
+        if self.frames.len() >= FRAMES_MAX {
+            return Err(self.error(VmErrorKind::StackOverflow));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    /// Closures that capture the same variable have to share one upvalue, so reuse an open one if there is one
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|u| matches!(*u.borrow(), Upvalue::Open(s) if s == slot));
+        if let Some(upvalue) = existing {
+            return Rc::clone(upvalue);
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        let idx = self
+            .open_upvalues
+            .iter()
+            .position(|u| matches!(*u.borrow(), Upvalue::Open(s) if s > slot))
+            .unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(idx, Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Moves every variable at or above `last` off of the stack and into its upvalue
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) => slot,
+                Upvalue::Closed(_) => unreachable!("closed upvalues aren't kept in the open list"),
+            };
+            if slot < last {
+                break;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+            self.open_upvalues.pop();
+        }
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ast::Expr,
+        interner::Symbol,
+        interpreter::InterpreterVisitor,
+        token::{Literal, Token, TokenType},
+    };
+
+    /// Runs `src` on both backends and checks that every global in `names` ends up displaying the same way
+    fn same_as_tree_walker(src: &str, names: &[&str]) {
+        let mut vm = Vm::new();
+        crate::run_vm(src.to_string(), "<script>", &mut vm).unwrap();
+
+        let mut interpreter = InterpreterVisitor::new();
+        crate::run(src.to_string(), &mut interpreter).unwrap();
+
+        for name in names {
+            let expected = interpreter
+                .evaluate(&Expr::variable(Token::new(
+                    TokenType::Identifier,
+                    Symbol::intern(name),
+                    Literal::Nil,
+                    crate::token::Span::default(),
+                )))
+                .unwrap();
+            let found = vm.globals.get(*name).unwrap();
+            assert_eq!(found.to_string(), expected.to_string(), "global `{}`", name);
+        }
+    }
+
+    fn fails(src: &str) -> bool {
+        crate::run_vm(src.to_string(), "<script>", &mut Vm::new()).is_err()
+    }
+
+    #[test]
+    fn arithmetic_and_strings() {
+        same_as_tree_walker(
+            "
+            var a = 1 + 2 * 3 - 4 / 2;
+            var b = -a;
+            var c = \"x\" + 1 + true;
+            var d = 1 + \"x\";
+            var e = !nil == true;
+            var f = 1 < 2 and 2 <= 2 and 3 > 2 and 3 >= 3 and 1 != 2;
+            var g = nil or \"fallback\";
+            var h = false ? 1 : 2;
+            ",
+            &["a", "b", "c", "d", "e", "f", "g", "h"],
+        );
+    }
+
+    #[test]
+    fn locals_and_control_flow() {
+        same_as_tree_walker(
+            "
+            var sum = 0;
+            for (var i = 0; i < 10; i++) {
+                if (i == 3) sum += 100; else sum += i;
+            }
+            var count = 0;
+            while (count < 5) { count = count + 1; }
+            var shadow;
+            { var a = 1; { var a = 2; shadow = a; } }
+            var i = 5;
+            var post = i--;
+            var pre = --i;
+            ",
+            &["sum", "count", "shadow", "i", "post", "pre"],
+        );
+    }
+
+    #[test]
+    fn functions_and_closures() {
+        same_as_tree_walker(
+            "
+            func fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            var a = fib(15);
+            func makeCounter() {
+                var i = 0;
+                func count() { i++; return i; }
+                return count;
+            }
+            var counter = makeCounter();
+            counter();
+            var b = counter();
+            var first;
+            var second;
+            for (var j = 0; j < 2; j++) {
+                var k = j * 10;
+                func get() { return k; }
+                if (j == 0) first = get; else second = get;
+            }
+            var c = first() + second();
+            func outer() {
+                var x = \"outer\";
+                func middle() {
+                    func inner() { return x; }
+                    return inner;
+                }
+                return middle()();
+            }
+            var d = outer();
+            var e = fib;
+            var f = clock;
+            ",
+            &["a", "b", "c", "d", "e", "f"],
+        );
+    }
+
+    #[test]
+    fn string_escapes_and_interpolation() {
+        same_as_tree_walker(
+            "
+            var x = 1;
+            var a = \"tab\\t\\\"${x + 1}\\\" ${\"in${x}\"}\\u{e9}\";
+            var b = \"${x}${nil}${true}\";
+            ",
+            &["a", "b"],
+        );
+    }
+
+    #[test]
+    fn break_and_continue() {
+        same_as_tree_walker(
+            "
+            var skipped = \"\";
+            for (var i = 0; i < 10; i++) {
+                var local = i;
+                if (i == 2) continue;
+                if (i == 5) break;
+                skipped = skipped + local;
+            }
+            var captured = \"\";
+            func collect() {
+                for (var j = 0; j < 5; j++) {
+                    var k = j;
+                    func get() { return k; }
+                    if (j == 1) continue;
+                    if (j == 3) break;
+                    captured = captured + get();
+                }
+                var after = \"after\";
+                return after;
+            }
+            var ret = collect();
+            var pairs = 0;
+            for (var a = 0; a < 3; a++) {
+                var x = a;
+                for (var b = 0; b < 3; b++) {
+                    var y = b;
+                    if (y == x) break;
+                    pairs++;
+                }
+            }
+            ",
+            &["skipped", "captured", "ret", "pairs"],
+        );
+    }
+
+    #[test]
+    fn classes() {
+        same_as_tree_walker(
+            "
+            class Point {
+                init(x, y) { this.x = x; this.y = y; }
+                sum() { return this.x + this.y; }
+                adder() {
+                    func add(n) { return this.x + n; }
+                    return add;
+                }
+            }
+            var p = Point(1, 2);
+            p.y = 10;
+            var a = p.sum();
+            var method = p.sum;
+            var b = method();
+            var c = p.adder()(5);
+            var d = p.init(3, 4) == p;
+            var e = p;
+            var f = Point;
+            ",
+            &["a", "b", "c", "d", "e", "f"],
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert!(fails("print nil;"));
+        assert!(fails("var a = 1 / 0;"));
+        assert!(fails("var a = \"s\"; a++;"));
+        assert!(fails("print undefined;"));
+        assert!(fails("func f(a) {} f();"));
+        assert!(fails("var a = 1; a();"));
+        assert!(fails("class A {} A().missing;"));
+        assert!(fails("class A {} A(1);"));
+        assert!(fails("func f() { f(); } f();"));
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(fails("var xs = [1, 2];"));
+        assert!(fails("var a = 1; a[0];"));
+        assert!(fails("var m = {\"a\": 1};"));
+        assert!(fails("try { throw 1; } catch (e) {}"));
+        assert!(fails("import \"lib.lox\";"));
+    }
+
+    #[test]
+    fn error_lines() {
+        let line = |src: &str| match crate::run_vm(src.to_string(), "<script>", &mut Vm::new()) {
+            Err(crate::Error::Vm(e)) => e.span.line,
+            result => panic!("expected a VM error, got {:?}", result),
+        };
+        assert_eq!(line("var a = 1;\nprint a;\n\nprint nil;"), 4);
+        assert_eq!(line("print 1;\n\nprint true and nil;"), 3);
+        assert_eq!(line("var a = 1;\nvar b =\n  -nil;"), 3);
+    }
+
+    #[test]
+    fn error_spans() {
+        let e = crate::run_vm("var a = 1;\nprint -nil;".into(), "main.lox", &mut Vm::new())
+            .unwrap_err();
+        assert!(e.to_string().starts_with("main.lox:2:7: "), "{}", e);
+        let e = crate::run_vm("var xs = [1];".into(), "main.lox", &mut Vm::new()).unwrap_err();
+        assert!(e.to_string().starts_with("main.lox:1:10: "), "{}", e);
+    }
+}